@@ -1,4 +1,16 @@
-use clap::{Parser, Subcommand};
+use crate::config::{Config, EnvScheme, BACKUP_KEEP};
+use crate::connectivity::DEFAULT_JOBS;
+use crate::shell::Shell;
+use crate::templates::TEMPLATES;
+use crate::transfer::{ExportFormat, ImportStrategy};
+use anyhow::Result;
+use clap::builder::{BoolishValueParser, RangedU64ValueParser};
+use clap::error::ErrorKind;
+use clap::{ArgAction, ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(
@@ -9,46 +21,785 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// When to use colored output
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Disable colored output (same as --color never)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Use this config file instead of ~/.cce/config.toml (overrides CCE_CONFIG)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Show what a command would change without saving anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Drop confirmations and hints; errors, warnings, and requested output still print
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Log diagnostics to stderr: -v for debug, -vv for trace (RUST_LOG also works)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Timeout in seconds for requests to providers (overrides `cce config timeout`)
+    #[arg(long, global = true, value_name = "SECS", value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+    pub timeout: Option<u64>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// List all service providers
-    List,
+    List {
+        /// Sort order for the provider list
+        #[arg(long, value_enum, default_value_t = ListSort::Name)]
+        sort: ListSort,
+        /// Only show providers whose name or API URL contains this text
+        #[arg(long, value_name = "TEXT")]
+        filter: Option<String>,
+        /// Only show providers with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Show whether each provider is reachable, re-checking results older than 5 minutes
+        #[arg(long)]
+        health: bool,
+        /// How to print the providers
+        #[arg(long, value_enum, default_value_t = OutputFormat::Pretty)]
+        format: OutputFormat,
+        /// Print as JSON without colors or decoration (same as --format json)
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+    },
+
+    /// Find providers by name, API URL, tag, or description
+    Search {
+        /// Text to look for, ignoring case
+        query: String,
+        /// Print as JSON without colors or decoration
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Add a service provider
     Add {
         /// Provider name
         name: String,
-        /// API URL
-        api_url: String,
-        /// API Token
-        token: String,
+        /// API URL (taken from the template with --template)
+        #[arg(required_unless_present = "template", conflicts_with = "template")]
+        api_url: Option<String>,
+        /// API Token; prompted for if omitted, `-` reads it from stdin
+        #[arg(conflicts_with = "token_file")]
+        token: Option<String>,
+        /// Read the API token from a file
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+        /// Prefill the API URL and model from a preset (see `cce templates`)
+        #[arg(long, add = ArgValueCandidates::new(template_candidates))]
+        template: Option<String>,
+        /// Override the template's API URL
+        #[arg(long, requires = "template")]
+        url: Option<String>,
         /// Model name (optional)
         #[arg(short, long)]
         model: Option<String>,
+        /// Environment variable to export the token as (default: ANTHROPIC_AUTH_TOKEN)
+        #[arg(long)]
+        token_env: Option<String>,
+        /// Environment variable to export the API URL as (default: ANTHROPIC_BASE_URL)
+        #[arg(long)]
+        url_env: Option<String>,
+        /// Note on what the provider is for
+        #[arg(long = "desc", value_name = "TEXT")]
+        description: Option<String>,
+        /// Web dashboard opened by `cce open`
+        #[arg(long, value_name = "URL")]
+        console_url: Option<String>,
+        /// Relative chance of being picked by `cce use --random` (default 1)
+        #[arg(long, value_parser = RangedU64ValueParser::<u32>::new().range(1..))]
+        weight: Option<u32>,
+        /// Skip API URL validation (for unusual internal endpoints)
+        #[arg(long)]
+        no_validate: bool,
+        /// Overwrite an existing provider after showing the changes, without asking
+        #[arg(short, long)]
+        yes: bool,
+        /// Overwrite an existing provider without asking, even if it is locked
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Create or update a provider to match the given settings, without prompting
+    Ensure {
+        /// Provider name
+        name: String,
+        /// API URL the provider should have
+        #[arg(long)]
+        url: String,
+        /// API token the provider should have; `-` reads it from stdin
+        #[arg(
+            long,
+            required_unless_present = "token_file",
+            conflicts_with = "token_file"
+        )]
+        token: Option<String>,
+        /// Read the API token from a file
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+        /// Model the provider should have; left unchanged if omitted
+        #[arg(short, long)]
+        model: Option<String>,
+        /// Skip API URL validation (for unusual internal endpoints)
+        #[arg(long)]
+        no_validate: bool,
     },
 
     /// Delete the specified service provider
+    #[command(visible_alias = "remove")]
     Delete {
-        /// Name of provider to delete
+        /// Name of provider to delete (a pattern with --all)
+        #[arg(
+            required_unless_present = "tag",
+            conflicts_with = "tag",
+            add = ArgValueCandidates::new(provider_candidates)
+        )]
+        name: Option<String>,
+        /// Treat the name as a pattern (`*` matches anything) and delete every match
+        #[arg(long)]
+        all: bool,
+        /// Delete every provider with this tag
+        #[arg(long, conflicts_with = "all")]
+        tag: Option<String>,
+        /// Skip the confirmation prompt (asked for --all, --tag, and the
+        /// current or last provider)
+        #[arg(short, long)]
+        yes: bool,
+        /// Remove locked providers too
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Remove providers that are unreachable, expired, or unused, after confirmation
+    #[command(group(
+        ArgGroup::new("criteria")
+            .args(["dead", "expired", "unused"])
+            .multiple(true)
+            .required(true)
+    ))]
+    Prune {
+        /// Providers that are unreachable or whose token is rejected
+        #[arg(long)]
+        dead: bool,
+        /// Providers whose token is past its expiry date
+        #[arg(long)]
+        expired: bool,
+        /// Providers not used within this many days (or never used)
+        #[arg(long, value_name = "DAYS", value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+        unused: Option<u64>,
+        /// Skip the confirmation prompt; the current provider is kept unless
+        /// confirmed separately
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Edit an existing service provider in place
+    Edit {
+        /// Name of provider to edit
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+        /// New API URL
+        #[arg(long = "url")]
+        api_url: Option<String>,
+        /// New API Token (`-` reads it from stdin)
+        #[arg(long, conflicts_with = "token_file")]
+        token: Option<String>,
+        /// Read the new API token from a file
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+        /// Environment variable to export the token as (empty to reset)
+        #[arg(long)]
+        token_env: Option<String>,
+        /// Environment variable to export the API URL as (empty to reset)
+        #[arg(long)]
+        url_env: Option<String>,
+        /// New note on what the provider is for (empty to remove)
+        #[arg(long = "desc", value_name = "TEXT")]
+        description: Option<String>,
+        /// New web dashboard opened by `cce open` (empty to remove)
+        #[arg(long, value_name = "URL")]
+        console_url: Option<String>,
+        /// New relative chance of being picked by `cce use --random`
+        #[arg(long, value_parser = RangedU64ValueParser::<u32>::new().range(1..))]
+        weight: Option<u32>,
+        /// When the token expires, as YYYY-MM-DD or RFC 3339 (empty to remove)
+        #[arg(long, value_name = "DATE")]
+        expires: Option<String>,
+        /// Skip API URL validation (for unusual internal endpoints)
+        #[arg(long)]
+        no_validate: bool,
+        /// Edit the provider even if it is locked
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Replace a provider's token after checking that the new one works
+    Rotate {
+        /// Name of provider to rotate the token of
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
         name: String,
+        /// New API token (`-` reads it from stdin; prompted for if omitted)
+        #[arg(long, conflicts_with = "token_file")]
+        token: Option<String>,
+        /// Read the new API token from a file
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+        /// Save the new token even if the connectivity test fails or the
+        /// provider is locked
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Manage several tokens for one provider
+    Token {
+        #[command(subcommand)]
+        action: TokenCommands,
+    },
+
+    /// Rename a service provider
+    Rename {
+        /// Current provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        old: String,
+        /// New provider name
+        new: String,
+        /// Overwrite the target provider if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Rename several providers at once with a pattern substitution
+    RenameBulk {
+        /// Names to rename, with `*` wildcards, e.g. 'old-*'
+        from: String,
+        /// New names, with the text matched by each `*` inserted, e.g. 'new-*'
+        to: String,
+        /// Treat FROM as a regular expression and TO as its replacement ($1 for groups)
+        #[arg(long)]
+        regex: bool,
+        /// Rename without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Duplicate a service provider under a new name
+    Copy {
+        /// Provider to copy
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        src: String,
+        /// Name of the new provider
+        dest: String,
+        /// API URL for the copy
+        #[arg(long = "url")]
+        api_url: Option<String>,
+        /// API Token for the copy
+        #[arg(long)]
+        token: Option<String>,
+        /// Overwrite the target provider if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Compare two service providers field by field
+    Diff {
+        /// First provider
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        a: String,
+        /// Second provider
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        b: String,
+        /// Also compare every variable `cce use` would export
+        #[arg(long)]
+        all_env: bool,
+    },
+
+    /// Add tags to a provider
+    Tag {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Tags to add (stored lowercase)
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Protect a provider from being removed or overwritten without --force
+    Lock {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+    },
+
+    /// Allow a locked provider to be removed and overwritten again
+    Unlock {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+    },
+
+    /// Remove tags from a provider
+    Untag {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Set the model exported as ANTHROPIC_MODEL for a provider
+    SetModel {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Model name
+        #[arg(required_unless_present = "clear", conflicts_with = "clear")]
+        model: Option<String>,
+        /// Remove the model so ANTHROPIC_MODEL is no longer exported
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Set the HTTP(S) proxy exported with a provider and used by `cce test`
+    SetProxy {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Proxy URL, e.g. http://proxy.corp:8080
+        #[arg(required_unless_present = "clear", conflicts_with = "clear")]
+        url: Option<String>,
+        /// Only change the proxy for this scheme
+        #[arg(long, value_enum)]
+        only: Option<ProxyScheme>,
+        /// Remove the proxy
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Set an extra environment variable exported with a provider
+    SetEnv {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Variable assignment in KEY=VALUE form
+        assignment: String,
+    },
+
+    /// Remove an extra environment variable from a provider
+    UnsetEnv {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Variable name
+        key: String,
+    },
+
+    /// Send an HTTP header with a provider's requests, e.g. for gateways
+    SetHeader {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Header in `Name: Value` form (quoting is optional)
+        #[arg(required = true, num_args = 1.., allow_hyphen_values = true)]
+        header: Vec<String>,
+    },
+
+    /// Stop sending an HTTP header with a provider's requests
+    UnsetHeader {
+        /// Provider name
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: String,
+        /// Header name, in any case
+        name: String,
+    },
+
+    /// Manage profiles, independent groups of providers
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommands,
+    },
+
+    /// Create or remove a short alias for a service provider
+    Alias {
+        /// Alias name
+        alias: String,
+        /// Provider (or alias) the alias points to
+        #[arg(
+            required_unless_present = "remove",
+            conflicts_with = "remove",
+            add = ArgValueCandidates::new(provider_candidates)
+        )]
+        provider: Option<String>,
+        /// Remove the alias instead of creating it
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Manage shortcuts for whole commands, like `cce prod` for `cce use production`
+    AliasCmd {
+        #[command(subcommand)]
+        action: AliasCmdCommands,
     },
 
     /// Use the specified service provider
+    #[command(group(ArgGroup::new("pick").args(["random", "round_robin"])))]
     Use {
-        /// Name of provider to use
-        name: String,
+        /// Name of provider to use (pick interactively if omitted, `-` for the previous one)
+        #[arg(
+            conflicts_with_all = ["from_env", "pick"],
+            add = ArgValueCandidates::new(provider_candidates)
+        )]
+        name: Option<String>,
+        /// Pick a provider at random, weighted by each provider's weight
+        #[arg(long, conflicts_with = "from_env")]
+        random: bool,
+        /// Pick the provider after the one picked last time
+        #[arg(long, conflicts_with_all = ["from_env", "no_save"])]
+        round_robin: bool,
+        /// Only pick among providers with this tag
+        #[arg(long, requires = "pick")]
+        tag: Option<String>,
+        /// Print shell commands that export the provider's environment variables
+        #[arg(long)]
+        eval: bool,
+        /// Use a transient provider from CCE_PROVIDER_URL and CCE_PROVIDER_TOKEN
+        /// (and optionally CCE_PROVIDER_MODEL) without touching the config
+        #[arg(long)]
+        from_env: bool,
+        /// Export the provider in this shell only, leaving the saved current
+        /// provider unchanged
+        #[arg(long, conflicts_with = "from_env")]
+        no_save: bool,
+        /// Show the variables that would be exported, with secrets masked,
+        /// without switching or exporting anything
+        #[arg(long, conflicts_with_all = ["eval", "from_env", "no_save", "pick"])]
+        print: bool,
+        /// Check that the provider's host resolves and accepts connections first
+        #[arg(long, conflicts_with_all = ["from_env", "print"])]
+        verify: bool,
+        /// Switch even if the reachability check fails
+        #[arg(long, conflicts_with_all = ["from_env", "print"])]
+        force: bool,
+        /// Shell syntax for --eval output (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+    },
+
+    /// Switch back to the provider used before the current one, like `cd -`
+    Last {
+        /// Print shell commands that export the provider's environment variables
+        #[arg(long)]
+        eval: bool,
+        /// Shell syntax for --eval output (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+    },
+
+    /// Set the provider applied automatically in new shells
+    Default {
+        /// Name of provider to use as default (shows the current default if omitted)
+        #[arg(conflicts_with = "clear", add = ArgValueCandidates::new(provider_candidates))]
+        name: Option<String>,
+        /// Unset the default provider
+        #[arg(long)]
+        clear: bool,
+        /// Print shell commands that export the default provider's environment variables
+        #[arg(long, conflicts_with_all = ["name", "clear"])]
+        eval: bool,
+        /// Shell syntax for --eval output (defaults to detecting from $SHELL)
+        #[arg(long, value_enum, requires = "eval")]
+        shell: Option<Shell>,
+    },
+
+    /// Print the active provider name (exits 1 if none)
+    Current {
+        /// Also print the API URL
+        #[arg(short, long)]
+        url: bool,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the presets available to `cce add --template`
+    Templates,
+
+    /// Manage providers in a full-screen terminal UI
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Print shell commands that export the provider selected on exit
+        #[arg(long)]
+        eval: bool,
+        /// Shell syntax for --eval output (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
     },
 
     /// Check current environment variable status
-    Check,
+    Check {
+        /// Print as JSON without colors or decoration; never includes the token
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Diagnose common setup problems and suggest fixes
+    Doctor,
+
+    /// Check the config file for problems without changing it (e.g. in a pre-commit hook)
+    Validate,
+
+    /// Print a JSON Schema of the config file, for editor completion and validation
+    Schema,
+
+    /// Show recent provider switches, newest first
+    History {
+        /// Delete the recorded history
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Show a compact summary (exits 1 if the environment differs from the config)
+    Status {
+        /// How to print the status; `table` and `json` are printed even with --quiet
+        #[arg(long, value_enum, default_value_t = OutputFormat::Pretty)]
+        format: OutputFormat,
+        /// Print as JSON, even with --quiet (same as --format json)
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// Quiet check for prompt hooks: never reads secrets, exits 0 when no provider is active
+        #[arg(long, conflicts_with_all = ["json", "format"])]
+        fast: bool,
+    },
 
     /// Output shell integration function
-    Shellenv,
+    Shellenv {
+        /// Shell to generate the function for (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+        /// Also re-export the active provider at each prompt if its variables drifted
+        #[arg(long)]
+        hook: bool,
+    },
 
     /// Clear environment variables to use official Claude client
-    Clear,
+    #[command(visible_alias = "unset")]
+    Clear {
+        /// Print shell commands that unset the environment variables
+        #[arg(long)]
+        eval: bool,
+        /// Shell syntax for --eval output (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+    },
+
+    /// Print a provider's environment variables without switching to it
+    ExportEnv {
+        /// Name of provider to print (defaults to the current provider)
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: Option<String>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = EnvFormat::Dotenv)]
+        format: EnvFormat,
+        /// Shell syntax for --format shell (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+    },
+
+    /// Run a command with a provider's variables, without switching to it
+    Run {
+        /// Provider whose variables the command gets
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+        /// Command and its arguments, after `--`
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<OsString>,
+    },
+
+    /// Print the shell commands exporting a provider, without saving it as current
+    PrintEnv {
+        /// Name of provider to print (defaults to the current provider)
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: Option<String>,
+        /// Shell syntax to print (defaults to detecting from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+    },
+
+    /// Write a provider's environment variables to a .env file
+    Envfile {
+        /// Name of provider to write
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+        /// File to write
+        #[arg(long, default_value = ".env")]
+        out: PathBuf,
+        /// Keep the file's other variables, replacing only the provider's
+        #[arg(long)]
+        merge: bool,
+    },
+
+    /// Show which account a provider's token belongs to
+    Whoami {
+        /// Name of provider to query (defaults to the current provider)
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: Option<String>,
+    },
+
+    /// Copy a provider's token to the clipboard without showing it
+    CpToken {
+        /// Name of provider to copy from (defaults to the current provider)
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: Option<String>,
+        /// Seconds until the clipboard is cleared (0 leaves the token there)
+        #[arg(long, value_name = "SECS")]
+        clear_after: Option<u64>,
+        /// Print the token to stdout when no clipboard is available
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Open a provider's web console in the default browser
+    Open {
+        /// Name of provider to open (defaults to the current provider)
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: Option<String>,
+    },
+
+    /// Test connectivity and authentication against a provider's API
+    Test {
+        /// Name of provider to test
+        #[arg(
+            required_unless_present = "all",
+            conflicts_with = "all",
+            add = ArgValueCandidates::new(provider_candidates)
+        )]
+        name: Option<String>,
+        /// Test every configured provider and print a summary
+        #[arg(long)]
+        all: bool,
+        /// Re-check providers whose cached result is still fresh; a single
+        /// provider is always re-checked
+        #[arg(long)]
+        refresh: bool,
+        /// With --all, how many providers to test at once
+        #[arg(
+            short,
+            long,
+            value_name = "N",
+            default_value_t = DEFAULT_JOBS,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        jobs: usize,
+        /// With --all, how to print the results (default: pretty)
+        #[arg(long, value_enum, conflicts_with = "name")]
+        format: Option<OutputFormat>,
+    },
+
+    /// Save a timestamped copy of the config file, or list the saved copies
+    Backup {
+        /// List existing backups instead of creating one
+        #[arg(long)]
+        list: bool,
+        /// Number of backups to keep; older ones are deleted
+        #[arg(
+            long,
+            default_value_t = BACKUP_KEEP,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+            conflicts_with = "list"
+        )]
+        keep: usize,
+    },
+
+    /// Replace the config file with a backup made by `cce backup`
+    Restore {
+        /// Backup file to restore (a path, or a file name from `cce backup --list`)
+        #[arg(long, required_unless_present = "latest", conflicts_with = "latest")]
+        file: Option<PathBuf>,
+        /// Restore the most recent backup
+        #[arg(long)]
+        latest: bool,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Export service providers to a portable JSON or YAML file
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Include real tokens instead of redacting them
+        #[arg(long)]
+        with_tokens: bool,
+        /// Print a shell script of `cce` commands that recreates the providers
+        #[arg(long, conflicts_with = "format")]
+        script: bool,
+    },
+
+    /// Import service providers from an exported JSON or YAML file
+    Import {
+        /// File produced by `cce export`
+        file: PathBuf,
+        /// How to handle providers that already exist
+        #[arg(long, value_enum, default_value_t = ImportStrategy::Merge)]
+        strategy: ImportStrategy,
+        /// Also switch to the current provider recorded in the file
+        #[arg(long)]
+        adopt_current: bool,
+        /// Overwrite or remove providers even when they are locked
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Create a provider named `imported` from an existing Claude setup
+    ImportExisting {
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+        /// Overwrite `imported` if it already exists, even when locked
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Encrypt stored tokens with a master password
+    Encrypt,
+
+    /// Decrypt stored tokens and disable master password protection
+    Decrypt,
+
+    /// Manage OS keyring storage for tokens
+    Keyring {
+        #[command(subcommand)]
+        action: KeyringCommands,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
 
     /// Install shell integration for immediate environment variable effects
     Install {
@@ -56,10 +807,443 @@ pub enum Commands {
         #[arg(long)]
         force: bool,
     },
+
+    /// Install shell integration into the rc file of the given shell
+    Init {
+        /// Shell whose rc file to update (bash, zsh, or fish)
+        #[arg(value_enum)]
+        shell: Shell,
+        /// Replace the integration even if it is already installed
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Output of `cce list`, `cce status`, and `cce test --all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored, human-friendly output
+    Pretty,
+    /// Aligned columns without colors
+    Table,
+    /// JSON without colors or decoration
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListSort {
+    /// Alphabetically by name
+    Name,
+    /// Most recently used first
+    Recent,
+    /// Alphabetically by API URL
+    Url,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EnvFormat {
+    /// `KEY=VALUE` lines, as in a .env file
+    Dotenv,
+    /// Export commands for --shell, as printed by `cce use --eval`
+    Shell,
+    /// A JSON object of variable names to values
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProxyScheme {
+    /// HTTP_PROXY / http_proxy
+    Http,
+    /// HTTPS_PROXY / https_proxy
+    Https,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless NO_COLOR is set
+    Auto,
+    /// Always emit colors, even through a pipe
+    Always,
+    /// Never emit colors
+    Never,
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommands {
+    /// Create an empty profile
+    Create {
+        /// Profile name
+        name: String,
+    },
+    /// Switch to a profile
+    Use {
+        /// Profile name
+        name: String,
+    },
+    /// List all profiles
+    List,
+    /// Remove a profile and its providers
+    Remove {
+        /// Profile name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the config file location, even if it does not exist yet
+    Path,
+    /// Print the parsed config with tokens masked
+    Show,
+    /// Show or set the default timeout for requests to providers
+    Timeout {
+        /// Timeout in seconds
+        #[arg(
+            value_parser = RangedU64ValueParser::<u64>::new().range(1..),
+            conflicts_with = "reset"
+        )]
+        secs: Option<u64>,
+        /// Go back to the built-in default
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Show or set which variable(s) tokens are exported as
+    EnvScheme {
+        /// Variable scheme for providers without their own token variable
+        #[arg(value_enum, conflicts_with = "reset")]
+        scheme: Option<EnvScheme>,
+        /// Go back to ANTHROPIC_AUTH_TOKEN
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Show or set a shell command run after each switch (not with --eval)
+    OnSwitch {
+        /// Command to run; it gets CCE_HOOK_PROVIDER and CCE_HOOK_URL
+        #[arg(conflicts_with = "reset")]
+        command: Option<String>,
+        /// Remove the hook
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Show or set whether `cce use` checks reachability by default (not with --eval)
+    VerifyOnUse {
+        /// `on` or `off`
+        #[arg(value_parser = BoolishValueParser::new())]
+        enabled: Option<bool>,
+    },
+    /// Show or set how long `cce cp-token` leaves the token in the clipboard
+    ClipboardClear {
+        /// Delay in seconds; 0 never clears the clipboard
+        #[arg(conflicts_with = "reset")]
+        secs: Option<u64>,
+        /// Go back to the built-in default
+        #[arg(long)]
+        reset: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AliasCmdCommands {
+    /// List command aliases
+    List,
+    /// Add or replace a command alias
+    Add {
+        /// Alias name, typed in place of a subcommand
+        name: String,
+        /// Subcommand and arguments the alias stands for
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Remove a command alias
+    Remove {
+        /// Alias name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TokenCommands {
+    /// Add a backup token to a provider
+    Add {
+        /// Provider to add the token to
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+        /// API token; prompted for if omitted, `-` reads it from stdin
+        #[arg(conflicts_with = "token_file")]
+        token: Option<String>,
+        /// Read the API token from a file
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+    },
+    /// List a provider's tokens, masked, marking the active one
+    List {
+        /// Provider to list the tokens of
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+    },
+    /// Switch a provider to its next token, e.g. after hitting a rate limit
+    Next {
+        /// Provider to advance
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeyringCommands {
+    /// Move all plaintext tokens from the config file into the OS keyring
+    Migrate,
 }
 
 impl Cli {
-    pub fn parse_args() -> Self {
-        Self::parse()
+    /// Parses the command line, expanding a command alias in place of an
+    /// unknown subcommand. Built-in subcommands always win.
+    pub fn parse_args() -> Result<Self> {
+        let args: Vec<OsString> = std::env::args_os().collect();
+        let error = match Self::try_parse_from(&args) {
+            Ok(cli) => return Ok(cli),
+            Err(error) => error,
+        };
+
+        if error.kind() == ErrorKind::InvalidSubcommand {
+            if let Some(expanded) = Self::expand_alias(&args)? {
+                return Ok(Self::parse_from(expanded));
+            }
+        }
+        error.exit()
+    }
+
+    /// Replaces the subcommand in `args` with the command alias it names, if
+    /// any. The config file is the one `--config` or `CCE_CONFIG` point at.
+    fn expand_alias(args: &[OsString]) -> Result<Option<Vec<OsString>>> {
+        // Skip the global options in front of the subcommand
+        let mut index = 1;
+        while let Some(arg) = args.get(index).and_then(|arg| arg.to_str()) {
+            if !arg.starts_with('-') || arg == "-" || arg == "--" {
+                break;
+            }
+            if let Some(path) = arg.strip_prefix("--config=") {
+                Config::set_path_override(PathBuf::from(path));
+            }
+            if matches!(arg, "--config" | "--timeout" | "--color") {
+                if let (true, Some(path)) = (arg == "--config", args.get(index + 1)) {
+                    Config::set_path_override(PathBuf::from(path));
+                }
+                index += 1;
+            }
+            index += 1;
+        }
+        let Some(name) = args.get(index).and_then(|arg| arg.to_str()) else {
+            return Ok(None);
+        };
+
+        // An unreadable config leaves clap's error about the unknown command
+        let Ok(aliases) = Config::read_command_aliases() else {
+            return Ok(None);
+        };
+        let Some(words) = expand_command_alias(&aliases, name)? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            args[..index]
+                .iter()
+                .cloned()
+                .chain(words.into_iter().map(OsString::from))
+                .chain(args[index + 1..].iter().cloned())
+                .collect(),
+        ))
+    }
+
+    /// Whether the command can write the config file and therefore has to
+    /// hold the config lock.
+    pub fn modifies_config(&self) -> bool {
+        !matches!(
+            self.command,
+            Commands::List { .. }
+                | Commands::Search { .. }
+                | Commands::Diff { .. }
+                | Commands::Use { from_env: true, .. }
+                | Commands::Use { no_save: true, .. }
+                | Commands::Use { print: true, .. }
+                | Commands::Current { .. }
+                | Commands::Check { .. }
+                | Commands::Doctor
+                | Commands::Validate
+                | Commands::Schema
+                | Commands::Templates
+                | Commands::Status { .. }
+                | Commands::Shellenv { .. }
+                | Commands::Test { .. }
+                | Commands::Whoami { .. }
+                | Commands::Token {
+                    action: TokenCommands::List { .. }
+                }
+                | Commands::CpToken { .. }
+                | Commands::Open { .. }
+                | Commands::ExportEnv { .. }
+                | Commands::Run { .. }
+                | Commands::PrintEnv { .. }
+                | Commands::Envfile { .. }
+                | Commands::Export { .. }
+                | Commands::Completions { .. }
+                | Commands::Config {
+                    action: ConfigCommands::Path | ConfigCommands::Show
+                }
+                | Commands::Install { .. }
+                | Commands::Init { .. }
+                | Commands::AliasCmd {
+                    action: AliasCmdCommands::List
+                }
+        )
+    }
+
+    /// Applies the color choice globally; `auto` leaves the decision to
+    /// `colored`, which already honors NO_COLOR and checks for a terminal.
+    pub fn apply_color_choice(&self) {
+        let choice = if self.no_color {
+            ColorChoice::Never
+        } else {
+            self.color
+        };
+
+        match choice {
+            ColorChoice::Auto => {}
+            ColorChoice::Always => colored::control::set_override(true),
+            ColorChoice::Never => colored::control::set_override(false),
+        }
+    }
+}
+
+/// Whether `name` is a subcommand (or a subcommand alias) of cce itself.
+pub fn is_builtin_command(name: &str) -> bool {
+    name == "help" || Cli::command().find_subcommand(name).is_some()
+}
+
+/// The words a command alias stands for, following aliases whose expansion
+/// starts with another alias. `None` when `name` is a built-in subcommand
+/// or no alias at all.
+pub fn expand_command_alias(
+    aliases: &HashMap<String, Vec<String>>,
+    name: &str,
+) -> Result<Option<Vec<String>>> {
+    let mut words = vec![name.to_string()];
+    let mut visited = vec![name];
+
+    while !is_builtin_command(&words[0]) {
+        let Some(expansion) = aliases.get(&words[0]).filter(|words| !words.is_empty()) else {
+            break;
+        };
+        if visited.contains(&expansion[0].as_str()) {
+            anyhow::bail!(
+                "Command alias cycle detected: {} -> {}",
+                visited.join(" -> "),
+                expansion[0]
+            );
+        }
+        visited.push(&expansion[0]);
+        words.splice(0..1, expansion.iter().cloned());
+    }
+
+    Ok((visited.len() > 1).then_some(words))
+}
+
+fn template_candidates() -> Vec<CompletionCandidate> {
+    TEMPLATES
+        .iter()
+        .map(|template| {
+            CompletionCandidate::new(template.name).help(Some(template.description.into()))
+        })
+        .collect()
+}
+
+/// Completes provider names and aliases from the config file; completion must
+/// never fail, so an unreadable config just yields no candidates. The file is
+/// only read, as completion runs mid-prompt and without the config lock.
+fn provider_candidates() -> Vec<CompletionCandidate> {
+    let Ok(config) = Config::read_only() else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<CompletionCandidate> = config
+        .providers
+        .values()
+        .map(|provider| {
+            CompletionCandidate::new(&provider.name).help(Some(provider.api_url.clone().into()))
+        })
+        .collect();
+    candidates.extend(config.aliases.iter().map(|(alias, target)| {
+        CompletionCandidate::new(alias).help(Some(format!("alias for {}", target).into()))
+    }));
+    candidates.sort_by(|a, b| a.get_value().cmp(b.get_value()));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(name, words)| {
+                (
+                    name.to_string(),
+                    words.iter().map(|word| word.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn alias_expanding_to_itself_is_a_cycle() {
+        let aliases = aliases(&[("a", &["a", "x"])]);
+        let error = expand_command_alias(&aliases, "a").unwrap_err();
+        assert_eq!(error.to_string(), "Command alias cycle detected: a -> a");
+    }
+
+    #[test]
+    fn indirect_alias_cycle_is_detected() {
+        let aliases = aliases(&[("a", &["b"]), ("b", &["c", "x"]), ("c", &["a"])]);
+        let error = expand_command_alias(&aliases, "a").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Command alias cycle detected: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn alias_chains_keep_trailing_words() {
+        let aliases = aliases(&[
+            ("p", &["prod", "--no-save"]),
+            ("prod", &["use", "production"]),
+        ]);
+        assert_eq!(
+            expand_command_alias(&aliases, "p").unwrap(),
+            Some(vec![
+                "use".to_string(),
+                "production".to_string(),
+                "--no-save".to_string(),
+            ])
+        );
+        assert_eq!(expand_command_alias(&aliases, "other").unwrap(), None);
+    }
+
+    #[test]
+    fn builtins_win_over_aliases() {
+        let aliases = aliases(&[
+            ("use", &["delete"]),
+            ("remove", &["list"]),
+            ("help", &["list"]),
+        ]);
+        assert_eq!(expand_command_alias(&aliases, "use").unwrap(), None);
+        // Subcommand aliases and `help` are built-ins too
+        assert_eq!(expand_command_alias(&aliases, "remove").unwrap(), None);
+        assert_eq!(expand_command_alias(&aliases, "help").unwrap(), None);
+        // Expansion stops at the first built-in word
+        let aliases = self::aliases(&[("x", &["list", "x"])]);
+        assert_eq!(
+            expand_command_alias(&aliases, "x").unwrap(),
+            Some(vec!["list".to_string(), "x".to_string()])
+        );
     }
 }
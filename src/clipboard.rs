@@ -0,0 +1,42 @@
+use anyhow::{Context, Result};
+use arboard::Clipboard;
+use std::time::Duration;
+
+/// Seconds `cce cp-token` leaves a token in the clipboard by default.
+pub const DEFAULT_CLEAR_SECS: u64 = 30;
+
+/// System clipboard holding a copied secret. On X11 and Wayland the text is
+/// served by this process, so it stays pasteable only while it runs (and
+/// afterwards if a clipboard manager takes it over on drop).
+pub struct CopiedSecret {
+    clipboard: Clipboard,
+    secret: String,
+}
+
+/// Copies `secret` to the clipboard. Fails when there is no clipboard, e.g.
+/// over SSH or without a display server.
+pub fn copy(secret: &str) -> Result<CopiedSecret> {
+    let mut clipboard = Clipboard::new().with_context(|| "No clipboard available")?;
+    clipboard
+        .set_text(secret)
+        .with_context(|| "Failed to write to the clipboard")?;
+    Ok(CopiedSecret {
+        clipboard,
+        secret: secret.to_string(),
+    })
+}
+
+impl CopiedSecret {
+    /// Waits `delay`, then empties the clipboard unless something else was
+    /// copied meanwhile. Returns whether it was cleared.
+    pub fn clear_after(mut self, delay: Duration) -> Result<bool> {
+        std::thread::sleep(delay);
+        if self.clipboard.get_text().ok().as_deref() != Some(self.secret.as_str()) {
+            return Ok(false);
+        }
+        self.clipboard
+            .clear()
+            .with_context(|| "Failed to clear the clipboard")?;
+        Ok(true)
+    }
+}
@@ -1,21 +1,599 @@
-use anyhow::{Context, Result};
+use crate::clipboard::DEFAULT_CLEAR_SECS;
+use crate::connectivity::DEFAULT_TIMEOUT_SECS;
+use crate::crypto::{self, MasterKey};
+use crate::secret_store;
+use crate::toml_merge;
+use crate::transfer::REDACTED_TOKEN;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use colored::Colorize;
+use fs2::FileExt;
+use log::{debug, trace};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub const DEFAULT_TOKEN_ENV: &str = "ANTHROPIC_AUTH_TOKEN";
+pub const API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+pub const DEFAULT_URL_ENV: &str = "ANTHROPIC_BASE_URL";
+
+/// Environment variable overriding the config file location.
+pub const CONFIG_ENV: &str = "CCE_CONFIG";
+
+/// Environment variable forcing the config file format (`toml` or `yaml`).
+pub const CONFIG_FORMAT_ENV: &str = "CCE_CONFIG_FORMAT";
+
+/// Environment variables defining the transient provider of `cce use --from-env`.
+pub const EPHEMERAL_URL_ENV: &str = "CCE_PROVIDER_URL";
+pub const EPHEMERAL_TOKEN_ENV: &str = "CCE_PROVIDER_TOKEN";
+pub const EPHEMERAL_MODEL_ENV: &str = "CCE_PROVIDER_MODEL";
+
+static CONFIG_PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set by `--dry-run`; `save()` then reports the changes instead of writing.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Which variable(s) tokens are exported as, for providers that do not set
+/// their own `token_env`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvScheme {
+    /// ANTHROPIC_AUTH_TOKEN, sent as a bearer token
+    #[default]
+    AuthToken,
+    /// ANTHROPIC_API_KEY, sent as an x-api-key header
+    ApiKey,
+    /// Both ANTHROPIC_AUTH_TOKEN and ANTHROPIC_API_KEY
+    Both,
+}
+
+impl EnvScheme {
+    pub fn token_vars(self) -> &'static [&'static str] {
+        match self {
+            EnvScheme::AuthToken => &[DEFAULT_TOKEN_ENV],
+            EnvScheme::ApiKey => &[API_KEY_ENV],
+            EnvScheme::Both => &[DEFAULT_TOKEN_ENV, API_KEY_ENV],
+        }
+    }
+}
+
+/// Serialization used for the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// `CCE_CONFIG_FORMAT` wins; otherwise `.yaml`/`.yml` files are YAML and
+    /// everything else is TOML.
+    pub fn detect(path: &Path) -> Result<Self> {
+        if let Some(format) = std::env::var(CONFIG_FORMAT_ENV)
+            .ok()
+            .filter(|format| !format.is_empty())
+        {
+            return match format.to_ascii_lowercase().as_str() {
+                "toml" => Ok(ConfigFormat::Toml),
+                "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+                _ => Err(anyhow!(
+                    "Unsupported {} '{}': expected 'toml' or 'yaml'",
+                    CONFIG_FORMAT_ENV,
+                    format
+                )),
+            };
+        }
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml") => {
+                Ok(ConfigFormat::Yaml)
+            }
+            _ => Ok(ConfigFormat::Toml),
+        }
+    }
+
+    /// Format of the resolved config file.
+    pub fn current() -> Result<Self> {
+        Self::detect(&Config::get_config_path()?)
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, content: &str) -> Result<T> {
+        match self {
+            ConfigFormat::Toml => {
+                toml::from_str(content).with_context(|| "Invalid config file format")
+            }
+            ConfigFormat::Yaml => {
+                serde_yaml::from_str(content).with_context(|| "Invalid YAML config file")
+            }
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String> {
+        match self {
+            ConfigFormat::Toml => {
+                toml::to_string_pretty(value).with_context(|| "Failed to serialize config")
+            }
+            ConfigFormat::Yaml => {
+                serde_yaml::to_string(value).with_context(|| "Failed to serialize config")
+            }
+        }
+    }
+}
+
+/// Held while a command modifies the config; dropping it unlocks the file.
+pub struct ConfigLock {
+    _file: fs::File,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Provider {
     pub name: String,
+    /// Provider or base whose URL, model, proxies, and extra variables are
+    /// used where this provider leaves them unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// May be left out of the file when inherited through `extends`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub api_url: String,
     pub token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// When the provider was last switched to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<DateTime<Utc>>,
+    /// Environment variable the token is exported as (defaults to ANTHROPIC_AUTH_TOKEN)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    /// Environment variable the API URL is exported as (defaults to ANTHROPIC_BASE_URL)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_env: Option<String>,
+    /// Additional variables exported alongside the token and URL
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra_env: HashMap<String, String>,
+    /// HTTP headers sent with every request, for gateways that authenticate
+    /// or route by header; exported as `ANTHROPIC_CUSTOM_HEADERS`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Proxy exported as HTTP_PROXY/http_proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    /// Proxy exported as HTTPS_PROXY/https_proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<String>,
+    /// Lowercase labels for grouping providers, kept sorted and unique
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Free-form note on what the provider is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Web dashboard opened by `cce open`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console_url: Option<String>,
+    /// Relative chance of being picked by `cce use --random` (unset means 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// Guards against removing or overwriting the provider without `--force`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+    /// Every token of a provider holding several, stored like `token` but
+    /// never in the keyring; `token` is the one at `active_token`. Empty for
+    /// providers with a single token
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub active_token: usize,
+    /// When the token stops working, so its rotation can be flagged ahead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Values filled in from `extends` at load time, left out again on save
+    #[serde(skip)]
+    inherited: Option<ProviderBase>,
+}
+
+/// Settings several providers share through `extends`, either under
+/// `[bases.<name>]` or taken from another provider.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ProviderBase {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra_env: HashMap<String, String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+impl ProviderBase {
+    /// The inheritable settings of an (already resolved) provider.
+    fn of(provider: &Provider) -> Self {
+        Self {
+            api_url: Some(provider.api_url.clone()).filter(|url| !url.is_empty()),
+            model: provider.model.clone(),
+            http_proxy: provider.http_proxy.clone(),
+            https_proxy: provider.https_proxy.clone(),
+            extra_env: provider.extra_env.clone(),
+        }
+    }
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+impl Provider {
+    pub fn new(name: String, api_url: String, token: String) -> Self {
+        Self {
+            name,
+            extends: None,
+            api_url,
+            token,
+            model: None,
+            last_used: None,
+            token_env: None,
+            url_env: None,
+            extra_env: HashMap::new(),
+            headers: HashMap::new(),
+            http_proxy: None,
+            https_proxy: None,
+            tags: Vec::new(),
+            description: None,
+            console_url: None,
+            weight: None,
+            locked: false,
+            tokens: Vec::new(),
+            active_token: 0,
+            expires_at: None,
+            inherited: None,
+        }
+    }
+
+    /// Fills the fields this provider leaves unset from `base`, remembering
+    /// what was taken over so that saving leaves it out again.
+    fn inherit(&mut self, base: &ProviderBase) {
+        fn take(own: &mut Option<String>, base: &Option<String>, inherited: &mut Option<String>) {
+            if own.is_none() && base.is_some() {
+                own.clone_from(base);
+                inherited.clone_from(base);
+            }
+        }
+
+        let mut inherited = ProviderBase::default();
+        if self.api_url.is_empty() {
+            if let Some(api_url) = &base.api_url {
+                self.api_url = api_url.clone();
+                inherited.api_url = Some(api_url.clone());
+            }
+        }
+        take(&mut self.model, &base.model, &mut inherited.model);
+        take(
+            &mut self.http_proxy,
+            &base.http_proxy,
+            &mut inherited.http_proxy,
+        );
+        take(
+            &mut self.https_proxy,
+            &base.https_proxy,
+            &mut inherited.https_proxy,
+        );
+        for (key, value) in &base.extra_env {
+            if !self.extra_env.contains_key(key) {
+                self.extra_env.insert(key.clone(), value.clone());
+                inherited.extra_env.insert(key.clone(), value.clone());
+            }
+        }
+        self.inherited = Some(inherited);
+    }
+
+    /// Undoes `inherit` for the values that still equal what was inherited;
+    /// values changed since then are kept as the provider's own.
+    fn strip_inherited(&mut self) {
+        let Some(inherited) = self.inherited.take() else {
+            return;
+        };
+        if inherited.api_url.as_ref() == Some(&self.api_url) {
+            self.api_url.clear();
+        }
+        for (own, inherited) in [
+            (&mut self.model, inherited.model),
+            (&mut self.http_proxy, inherited.http_proxy),
+            (&mut self.https_proxy, inherited.https_proxy),
+        ] {
+            if inherited.is_some() && *own == inherited {
+                *own = None;
+            }
+        }
+        self.extra_env
+            .retain(|key, value| inherited.extra_env.get(key) != Some(value));
+    }
+
+    /// Fields whose value still comes from `extends`, for `cce list`.
+    pub fn inherited_fields(&self) -> Vec<&'static str> {
+        let Some(inherited) = &self.inherited else {
+            return Vec::new();
+        };
+        let mut fields = Vec::new();
+        if inherited.api_url.as_ref() == Some(&self.api_url) {
+            fields.push("URL");
+        }
+        if inherited.model.is_some() && inherited.model == self.model {
+            fields.push("model");
+        }
+        if (inherited.http_proxy.is_some() && inherited.http_proxy == self.http_proxy)
+            || (inherited.https_proxy.is_some() && inherited.https_proxy == self.https_proxy)
+        {
+            fields.push("proxy");
+        }
+        if inherited
+            .extra_env
+            .iter()
+            .any(|(key, value)| self.extra_env.get(key) == Some(value))
+        {
+            fields.push("extra env");
+        }
+        fields
+    }
+
+    /// Turns inherited values into the provider's own, e.g. before the
+    /// provider it extends is removed.
+    pub fn detach(&mut self) {
+        self.extends = None;
+        self.inherited = None;
+    }
+
+    /// Variables the token is exported as: the provider's own `token_env`,
+    /// otherwise those of the global scheme.
+    pub fn token_vars(&self, scheme: EnvScheme) -> Vec<&str> {
+        match &self.token_env {
+            Some(name) => vec![name.as_str()],
+            None => scheme.token_vars().to_vec(),
+        }
+    }
+
+    pub fn url_var(&self) -> &str {
+        self.url_env.as_deref().unwrap_or(DEFAULT_URL_ENV)
+    }
+
+    /// Whether the token has expired or expires within
+    /// `EXPIRY_WARNING_DAYS`; `None` when it has no expiry or is far from it.
+    pub fn expiry(&self) -> Option<Expiry> {
+        let remaining = self.expires_at? - Utc::now();
+        if remaining <= chrono::Duration::zero() {
+            Some(Expiry::Expired)
+        } else if remaining.num_days() < EXPIRY_WARNING_DAYS {
+            Some(Expiry::Soon)
+        } else {
+            None
+        }
+    }
+
+    /// The headers as `Name: Value` lines sorted by name, the format Claude
+    /// Code reads from `ANTHROPIC_CUSTOM_HEADERS`. `None` without headers.
+    pub fn custom_headers(&self) -> Option<String> {
+        if self.headers.is_empty() {
+            return None;
+        }
+        let mut lines: Vec<String> = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        lines.sort();
+        Some(lines.join("\n"))
+    }
+
+    /// The proxy requests to the API URL should go through, picked by scheme.
+    pub fn proxy_for_api(&self) -> Option<&str> {
+        if self.api_url.starts_with("http://") {
+            self.http_proxy.as_deref()
+        } else {
+            self.https_proxy.as_deref()
+        }
+    }
+}
+
+/// Providers whose token expires within this many days are flagged.
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    Expired,
+    Soon,
+}
+
+/// Never prints the token, nor variables and headers that look like secrets,
+/// so a provider that ends up in an error context or a log line cannot leak it.
+impl fmt::Debug for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn redacted(
+            values: &HashMap<String, String>,
+            is_secret: fn(&str) -> bool,
+        ) -> BTreeMap<&str, &str> {
+            values
+                .iter()
+                .map(|(name, value)| {
+                    let value = if is_secret(name) {
+                        REDACTED_TOKEN
+                    } else {
+                        value.as_str()
+                    };
+                    (name.as_str(), value)
+                })
+                .collect()
+        }
+
+        f.debug_struct("Provider")
+            .field("name", &self.name)
+            .field("extends", &self.extends)
+            .field("api_url", &self.api_url)
+            .field("token", &REDACTED_TOKEN)
+            .field("model", &self.model)
+            .field("last_used", &self.last_used)
+            .field("token_env", &self.token_env)
+            .field("url_env", &self.url_env)
+            .field("extra_env", &redacted(&self.extra_env, is_secret_var))
+            .field("headers", &redacted(&self.headers, is_secret_header))
+            .field("http_proxy", &self.http_proxy)
+            .field("https_proxy", &self.https_proxy)
+            .field("tags", &self.tags)
+            .field("description", &self.description)
+            .field("console_url", &self.console_url)
+            .field("weight", &self.weight)
+            .field("locked", &self.locked)
+            .field("tokens", &self.tokens.len())
+            .field("active_token", &self.active_token)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Schema version written by this build; `Config::load` upgrades older files.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Number of provider switches kept by `cce history`.
+pub const HISTORY_LIMIT: usize = 50;
+
+/// Number of timestamped backups `cce backup` keeps by default.
+pub const BACKUP_KEEP: usize = 10;
+
+/// Fixed-width, so backups sort chronologically by name; the milliseconds
+/// keep two backups taken in the same second apart.
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S-%3f";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryEntry {
+    pub provider: String,
+    pub used_at: DateTime<Utc>,
+}
+
+/// Name of the profile flat (pre-profile) configs are loaded as.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A named, independent set of providers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Profile {
+    #[serde(default)]
+    pub providers: HashMap<String, Provider>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_provider: Option<String>,
+}
+
+/// Everything stored in the cce config file (`~/.cce/config.toml` by default).
+#[derive(Clone, Serialize, Deserialize, Default, JsonSchema)]
 pub struct Config {
+    /// Schema version; files without one are version 0
+    #[serde(default)]
+    pub version: u32,
+    /// Providers of the active profile
+    #[serde(default)]
     pub providers: HashMap<String, Provider>,
     pub current_provider: Option<String>,
+    /// Provider that was current before the last switch, for `cce last`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_provider: Option<String>,
+    /// Profile whose providers are in `providers`; unset means `default`,
+    /// which is how flat configs written before profiles existed load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    /// Inactive profiles; switching swaps one with the top-level providers
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+    /// Provider exported automatically by the shell integration in fresh shells
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_provider: Option<String>,
+    /// Short names that resolve to a provider (or another alias)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+    /// Shortcuts for whole commands, e.g. `prod = ["use", "production"]`,
+    /// expanded before the arguments are parsed
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub command_aliases: HashMap<String, Vec<String>>,
+    /// Shared settings providers of every profile can `extends`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub bases: HashMap<String, ProviderBase>,
+    /// Whether provider tokens are stored encrypted with a master password
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_check: Option<String>,
+    /// Whether newly added tokens are stored in the OS keyring
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyring: bool,
+    /// Provider switches, oldest first, capped at `HISTORY_LIMIT` entries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryEntry>,
+    /// Timeout for requests to providers; unset means `DEFAULT_TIMEOUT_SECS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Variable(s) tokens are exported as; unset means `auth_token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_scheme: Option<EnvScheme>,
+    /// Shell command run after `cce use` switches provider, outside `--eval`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_switch: Option<String>,
+    /// Next position of `cce use --round-robin`, keyed by tag (empty for
+    /// all providers)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub round_robin: HashMap<String, usize>,
+    /// Whether `cce use` checks that the provider is reachable before
+    /// switching, as with `--verify` (never for `--eval`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verify_on_use: bool,
+    /// Seconds `cce cp-token` leaves a token in the clipboard; unset means
+    /// `DEFAULT_CLEAR_SECS`, 0 never clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clipboard_clear_secs: Option<u64>,
+    #[serde(skip)]
+    master_key: OnceCell<MasterKey>,
+    /// Keys of keyring entries the file stopped referring to since the last
+    /// save, deleted once it is written
+    #[serde(skip)]
+    stale_keyring_entries: RefCell<Vec<String>>,
+}
+
+/// Leaves out the cached master key; tokens are masked by `Provider`'s impl.
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("version", &self.version)
+            .field("providers", &self.providers)
+            .field("current_provider", &self.current_provider)
+            .field("previous_provider", &self.previous_provider)
+            .field("active_profile", &self.active_profile)
+            .field("profiles", &self.profiles)
+            .field("default_provider", &self.default_provider)
+            .field("aliases", &self.aliases)
+            .field("command_aliases", &self.command_aliases)
+            .field("bases", &self.bases)
+            .field("encrypted", &self.encrypted)
+            .field("keyring", &self.keyring)
+            .field("history", &self.history)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("on_switch", &self.on_switch)
+            .field("round_robin", &self.round_robin)
+            .field("verify_on_use", &self.verify_on_use)
+            .field("clipboard_clear_secs", &self.clipboard_clear_secs)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Config {
@@ -23,49 +601,418 @@ impl Config {
         let config_path = Self::get_config_path()?;
 
         if !config_path.exists() {
-            return Ok(Self::default());
+            debug!(
+                "Config file {:?} does not exist; starting empty",
+                config_path
+            );
+            return Ok(Self {
+                version: CONFIG_VERSION,
+                ..Self::default()
+            });
         }
 
         let content = fs::read_to_string(&config_path)
             .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
 
-        let config: Config =
-            toml::from_str(&content).with_context(|| "Invalid config file format")?;
+        if let Some(mode) = loose_permissions(&config_path) {
+            eprintln!(
+                "{} Config file {:?} is accessible by other users (mode {:o}); run 'chmod 600 {}'",
+                "⚠️".yellow(),
+                config_path,
+                mode,
+                config_path.display()
+            );
+        }
+
+        let format = ConfigFormat::detect(&config_path)?;
+        debug!("Loading config from {:?} as {:?}", config_path, format);
+        let mut config: Config = format.parse(&content)?;
+        config.resolve_inheritance()?;
+
+        if config.version > CONFIG_VERSION {
+            return Err(anyhow!(
+                "Config file {:?} uses schema version {}, but this cce only supports up to {}; please upgrade cce",
+                config_path,
+                config.version,
+                CONFIG_VERSION
+            ));
+        }
+
+        if config.version < CONFIG_VERSION {
+            // Keep the original so users can recover if the upgrade goes wrong
+            if !Self::is_dry_run() {
+                let mut backup_path = config_path.clone().into_os_string();
+                backup_path.push(".bak");
+                fs::copy(&config_path, &backup_path).with_context(|| {
+                    format!("Failed to back up config file to {:?}", backup_path)
+                })?;
+            }
+
+            debug!("Migrating config from schema version {}", config.version);
+            config.migrate();
+            config.save()?;
+        }
+
+        if config.validate() {
+            config.save()?;
+        }
 
         Ok(config)
     }
 
-    pub fn save(&self) -> Result<()> {
+    /// Parses the config file as it is, without migrating, repairing,
+    /// saving, or warning about it, and without taking the lock. For
+    /// callers like tab completion that must neither write nor print.
+    pub fn read_only() -> Result<Self> {
         let config_path = Self::get_config_path()?;
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+        ConfigFormat::detect(&config_path)?.parse(&content)
+    }
+
+    /// Reads only the command aliases, so they can be expanded before the
+    /// arguments are parsed.
+    pub fn read_command_aliases() -> Result<HashMap<String, Vec<String>>> {
+        Ok(Self::read_only()?.command_aliases)
+    }
+
+    /// Repairs a `current_provider` that points at a provider which no
+    /// longer exists, e.g. after a hand edit. Returns whether anything changed.
+    fn validate(&mut self) -> bool {
+        let mut repaired = false;
+
+        if let Some(current) = &self.current_provider {
+            if !self.providers.contains_key(current) {
+                eprintln!(
+                    "{} Current provider '{}' no longer exists; clearing it",
+                    "⚠️".yellow(),
+                    current.yellow()
+                );
+                self.current_provider = None;
+                repaired = true;
+            }
+        }
+
+        for (profile_name, profile) in &mut self.profiles {
+            if let Some(current) = &profile.current_provider {
+                if !profile.providers.contains_key(current) {
+                    eprintln!(
+                        "{} Current provider '{}' of profile '{}' no longer exists; clearing it",
+                        "⚠️".yellow(),
+                        current.yellow(),
+                        profile_name
+                    );
+                    profile.current_provider = None;
+                    repaired = true;
+                }
+            }
+        }
+
+        repaired
+    }
+
+    /// Fills in what providers inherit through `extends`, in the active and
+    /// every inactive profile. A provider can extend a provider of its own
+    /// profile or, when no provider has that name, a base.
+    pub fn resolve_inheritance(&mut self) -> Result<()> {
+        resolve_inheritance(&mut self.providers, &self.bases)?;
+        for profile in self.profiles.values_mut() {
+            resolve_inheritance(&mut profile.providers, &self.bases)?;
+        }
+        Ok(())
+    }
+
+    fn uses_inheritance(&self) -> bool {
+        self.providers
+            .values()
+            .chain(
+                self.profiles
+                    .values()
+                    .flat_map(|profile| profile.providers.values()),
+            )
+            .any(|provider| provider.inherited.is_some())
+    }
+
+    /// A copy holding only what is written to the file: inherited values
+    /// are left out so they keep following the provider or base.
+    fn without_inherited(&self) -> Config {
+        let mut config = self.clone();
+        for provider in config.providers.values_mut().chain(
+            config
+                .profiles
+                .values_mut()
+                .flat_map(|profile| profile.providers.values_mut()),
+        ) {
+            provider.strip_inherited();
+        }
+        config
+    }
+
+    /// Upgrades a config parsed from an older schema one version at a time.
+    fn migrate(&mut self) {
+        if self.version == 0 {
+            // v0 files predate versioning; their layout is already v1
+            self.version = 1;
+        }
+        if self.version == 1 {
+            self.move_legacy_keyring_entries();
+            self.version = 2;
+        }
+    }
+
+    /// Copies each keyring token from its version 1 entry, keyed by the
+    /// provider name alone, to the entry of its profile. The old entries are
+    /// deleted once the migrated file is saved; a token that cannot be
+    /// copied keeps its old entry.
+    fn move_legacy_keyring_entries(&mut self) {
+        let mut moved = Vec::new();
+        for (profile, name) in self.keyring_providers() {
+            let copied = secret_store::fetch(&name)
+                .and_then(|token| secret_store::store(&secret_store::key(&profile, &name), &token));
+            match copied {
+                Ok(()) => moved.push(name),
+                Err(e) => eprintln!(
+                    "{} {:#}; store the token of '{}' in profile '{}' again with 'cce rotate {}'",
+                    "⚠️".yellow(),
+                    e,
+                    name,
+                    profile,
+                    name
+                ),
+            }
+        }
+        moved.sort();
+        moved.dedup();
+        self.stale_keyring_entries.get_mut().extend(moved);
+    }
+
+    /// `(profile, name)` of every provider whose token is in the keyring,
+    /// sorted.
+    fn keyring_providers(&self) -> Vec<(String, String)> {
+        let active = std::iter::once((self.active_profile(), &self.providers));
+        let inactive = self
+            .profiles
+            .iter()
+            .map(|(name, profile)| (name.as_str(), &profile.providers));
+        let mut found: Vec<(String, String)> = active
+            .chain(inactive)
+            .flat_map(|(profile, providers)| {
+                providers
+                    .values()
+                    .filter(|provider| secret_store::is_keyring_token(&provider.token))
+                    .map(move |provider| (profile.to_string(), provider.name.clone()))
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&Self::get_config_path()?)
+    }
+
+    fn save_to(&self, config_path: &Path) -> Result<()> {
+        if Self::is_dry_run() {
+            debug!("Dry run: not writing {:?}", config_path);
+            return self.report_dry_run(config_path);
+        }
 
         // Ensure config directory exists
         if let Some(parent) = config_path.parent() {
-            fs::create_dir_all(parent)
+            create_private_dir(parent)
                 .with_context(|| format!("Failed to create config directory: {:?}", parent))?;
         }
 
-        let content = toml::to_string_pretty(self).with_context(|| "Failed to serialize config")?;
+        let format = ConfigFormat::detect(config_path)?;
+        let stripped;
+        let saved = if self.uses_inheritance() {
+            stripped = self.without_inherited();
+            &stripped
+        } else {
+            self
+        };
+        let mut content = format.serialize(saved)?;
+        // Update a hand-edited TOML file in place so its comments survive
+        if format == ConfigFormat::Toml {
+            if let Ok(existing) = fs::read_to_string(config_path) {
+                match toml_merge::merge(&existing, &content) {
+                    Some(merged) => content = merged,
+                    None => debug!("Existing config does not parse; rewriting it"),
+                }
+            }
+        }
 
-        fs::write(&config_path, content)
+        write_private(config_path, &content)
             .with_context(|| format!("Failed to write config file: {:?}", config_path))?;
+        debug!("Saved config to {:?}", config_path);
 
+        self.delete_stale_keyring_entries();
         Ok(())
     }
 
-    pub fn add_provider(
-        &mut self,
-        name: String,
-        api_url: String,
-        token: String,
-        model: Option<String>,
-    ) {
-        let provider = Provider {
-            name: name.clone(),
-            api_url,
-            token,
-            model,
+    /// Deletes the keyring entry of `name` in the active profile after the
+    /// next successful save, so a dry run or a failed write cannot leave the
+    /// file pointing at a token that is gone.
+    pub fn forget_keyring_entry(&mut self, name: &str) {
+        let key = self.keyring_key(name);
+        self.stale_keyring_entries.get_mut().push(key);
+    }
+
+    /// Like `forget_keyring_entry`, for a provider of another profile.
+    pub fn forget_profile_keyring_entry(&mut self, profile: &str, name: &str) {
+        self.stale_keyring_entries
+            .get_mut()
+            .push(secret_store::key(profile, name));
+    }
+
+    fn delete_stale_keyring_entries(&self) {
+        let stale = self.stale_keyring_entries.take();
+        if stale.is_empty() {
+            return;
+        }
+
+        let live: HashSet<String> = self
+            .keyring_providers()
+            .iter()
+            .map(|(profile, name)| secret_store::key(profile, name))
+            .collect();
+        for key in stale {
+            // The name may hold a keyring token again, e.g. after a swap
+            if live.contains(&key) {
+                continue;
+            }
+            if let Err(e) = secret_store::delete(&key) {
+                eprintln!("{} {:#}", "⚠️".yellow(), e);
+            }
+        }
+    }
+
+    /// Keyring key of provider `name` in the active profile.
+    pub fn keyring_key(&self, name: &str) -> String {
+        secret_store::key(self.active_profile(), name)
+    }
+
+    /// Prints what `save()` would change compared to the file on disk.
+    fn report_dry_run(&self, config_path: &Path) -> Result<()> {
+        let on_disk = if config_path.exists() {
+            let content = fs::read_to_string(config_path)
+                .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+            let mut on_disk: Config = ConfigFormat::detect(config_path)?.parse(&content)?;
+            on_disk.resolve_inheritance()?;
+            on_disk
+        } else {
+            Config::default()
         };
-        self.providers.insert(name, provider);
+
+        let changes = self.describe_changes(&on_disk)?;
+        if changes.is_empty() {
+            eprintln!("{} Dry run: the config file would not change", "🔍".blue());
+        } else {
+            eprintln!(
+                "{} Dry run: nothing was saved. Without --dry-run, cce would:",
+                "🔍".blue()
+            );
+            for change in changes {
+                eprintln!("  - {}", change);
+            }
+        }
+        Ok(())
+    }
+
+    fn describe_changes(&self, old: &Config) -> Result<Vec<String>> {
+        let mut changes = Vec::new();
+
+        if self.version != old.version {
+            changes.push(format!(
+                "upgrade the config schema to version {}",
+                self.version
+            ));
+        }
+        if self.active_profile != old.active_profile {
+            changes.push(format!("switch to profile '{}'", self.active_profile()));
+        }
+
+        let mut names: Vec<&String> = self.providers.keys().chain(old.providers.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            match (old.providers.get(name), self.providers.get(name)) {
+                (None, Some(_)) => changes.push(format!("add provider '{}'", name)),
+                (Some(_), None) => changes.push(format!("remove provider '{}'", name)),
+                (Some(before), Some(after))
+                    if toml::Value::try_from(before)? != toml::Value::try_from(after)? =>
+                {
+                    changes.push(format!("update provider '{}'", name))
+                }
+                _ => {}
+            }
+        }
+
+        if self.current_provider != old.current_provider {
+            changes.push(match &self.current_provider {
+                Some(name) => format!("switch current to '{}'", name),
+                None => "clear the current provider".to_string(),
+            });
+        }
+        if self.default_provider != old.default_provider {
+            changes.push(match &self.default_provider {
+                Some(name) => format!("set the default provider to '{}'", name),
+                None => "clear the default provider".to_string(),
+            });
+        }
+        if self.aliases != old.aliases {
+            changes.push("update aliases".to_string());
+        }
+        if self.command_aliases != old.command_aliases {
+            changes.push("update command aliases".to_string());
+        }
+
+        if changes.is_empty() && toml::Value::try_from(self)? != toml::Value::try_from(old)? {
+            changes.push("update other settings in the config file".to_string());
+        }
+
+        Ok(changes)
+    }
+
+    /// Request timeout: the `--timeout` flag, then the configured default,
+    /// then `DEFAULT_TIMEOUT_SECS`.
+    pub fn timeout(&self, flag: Option<u64>) -> Duration {
+        Duration::from_secs(flag.or(self.timeout_secs).unwrap_or(DEFAULT_TIMEOUT_SECS))
+    }
+
+    /// How long a copied token stays in the clipboard: the `--clear-after`
+    /// flag, then the configured default, then `DEFAULT_CLEAR_SECS`. `None`
+    /// leaves it there.
+    pub fn clipboard_clear(&self, flag: Option<u64>) -> Option<Duration> {
+        let secs = flag
+            .or(self.clipboard_clear_secs)
+            .unwrap_or(DEFAULT_CLEAR_SECS);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    pub fn env_scheme(&self) -> EnvScheme {
+        self.env_scheme.unwrap_or_default()
+    }
+
+    pub fn add_provider(&mut self, provider: Provider) {
+        self.providers.insert(provider.name.clone(), provider);
+    }
+
+    /// Detaches providers that extend something that no longer exists, e.g.
+    /// after an import replaced the provider they extended.
+    pub fn detach_orphans(&mut self) {
+        let names: HashSet<String> = self.providers.keys().cloned().collect();
+        for provider in self.providers.values_mut() {
+            if let Some(parent) = &provider.extends {
+                if !names.contains(parent) && !self.bases.contains_key(parent) {
+                    provider.detach();
+                }
+            }
+        }
     }
 
     pub fn remove_provider(&mut self, name: &str) -> bool {
@@ -74,18 +1021,157 @@ impl Config {
                 self.current_provider = None;
             }
         }
+        if self.previous_provider.as_deref() == Some(name) {
+            self.previous_provider = None;
+        }
+        if self.default_provider.as_deref() == Some(name) {
+            self.default_provider = None;
+        }
+        self.aliases.retain(|_, target| target != name);
+        for provider in self.providers.values_mut() {
+            if provider.extends.as_deref() == Some(name) {
+                provider.detach();
+            }
+        }
         self.providers.remove(name).is_some()
     }
 
+    pub fn rename_provider(&mut self, old: &str, new: &str) -> bool {
+        let Some(mut provider) = self.providers.remove(old) else {
+            return false;
+        };
+
+        provider.name = new.to_string();
+        self.providers.insert(new.to_string(), provider);
+
+        if self.current_provider.as_deref() == Some(old) {
+            self.current_provider = Some(new.to_string());
+        }
+        if self.previous_provider.as_deref() == Some(old) {
+            self.previous_provider = Some(new.to_string());
+        }
+        if self.default_provider.as_deref() == Some(old) {
+            self.default_provider = Some(new.to_string());
+        }
+        for target in self.aliases.values_mut() {
+            if target == old {
+                *target = new.to_string();
+            }
+        }
+        for provider in self.providers.values_mut() {
+            if provider.extends.as_deref() == Some(old) {
+                provider.extends = Some(new.to_string());
+            }
+        }
+        true
+    }
+
+    /// Applies several renames at once. Every source is taken out before any
+    /// target is inserted, so chains like `a -> b, b -> c` and swaps work;
+    /// the caller ensures targets do not collide.
+    pub fn rename_providers(&mut self, renames: &[(String, String)]) {
+        let renamed = |name: &str| {
+            renames
+                .iter()
+                .find(|(old, _)| old == name)
+                .map(|(_, new)| new.clone())
+        };
+
+        let moved: Vec<(String, Provider)> = renames
+            .iter()
+            .filter_map(|(old, new)| Some((new.clone(), self.providers.remove(old)?)))
+            .collect();
+        for (new, mut provider) in moved {
+            provider.name = new.clone();
+            self.providers.insert(new, provider);
+        }
+
+        if let Some(new) = self.current_provider.as_deref().and_then(renamed) {
+            self.current_provider = Some(new);
+        }
+        if let Some(new) = self.previous_provider.as_deref().and_then(renamed) {
+            self.previous_provider = Some(new);
+        }
+        if let Some(new) = self.default_provider.as_deref().and_then(renamed) {
+            self.default_provider = Some(new);
+        }
+        for target in self.aliases.values_mut() {
+            if let Some(new) = renamed(target) {
+                *target = new;
+            }
+        }
+        for provider in self.providers.values_mut() {
+            if let Some(new) = provider.extends.as_deref().and_then(renamed) {
+                provider.extends = Some(new);
+            }
+        }
+    }
+
+    /// Follows aliases to a canonical provider name. Names that are neither a
+    /// provider nor an alias are returned unchanged.
+    pub fn resolve_name(&self, name: &str) -> Result<String> {
+        let mut resolved = name;
+        let mut visited = vec![name];
+
+        while !self.providers.contains_key(resolved) {
+            let Some(target) = self.aliases.get(resolved) else {
+                break;
+            };
+            if visited.contains(&target.as_str()) {
+                anyhow::bail!(
+                    "Alias cycle detected: {} -> {}",
+                    visited.join(" -> "),
+                    target
+                );
+            }
+            visited.push(target);
+            resolved = target;
+        }
+
+        if resolved != name {
+            trace!("Resolved alias '{}' to provider '{}'", name, resolved);
+        }
+        Ok(resolved.to_string())
+    }
+
+    /// Returns the aliases that resolve to the given provider, sorted.
+    pub fn aliases_for(&self, name: &str) -> Vec<&String> {
+        let mut aliases: Vec<&String> = self
+            .aliases
+            .keys()
+            .filter(|alias| self.resolve_name(alias).is_ok_and(|target| target == name))
+            .collect();
+        aliases.sort();
+        aliases
+    }
+
     pub fn set_current_provider(&mut self, name: &str) -> bool {
-        if self.providers.contains_key(name) {
-            self.current_provider = Some(name.to_string());
+        if let Some(provider) = self.providers.get_mut(name) {
+            let now = Utc::now();
+            provider.last_used = Some(now);
+            let previous = self.current_provider.replace(name.to_string());
+            if previous.as_deref() != Some(name) {
+                self.previous_provider = previous;
+            }
+            self.record_history(name, now);
             true
         } else {
             false
         }
     }
 
+    fn record_history(&mut self, name: &str, used_at: DateTime<Utc>) {
+        self.history.push(HistoryEntry {
+            provider: name.to_string(),
+            used_at,
+        });
+
+        if self.history.len() > HISTORY_LIMIT {
+            let excess = self.history.len() - HISTORY_LIMIT;
+            self.history.drain(..excess);
+        }
+    }
+
     pub fn clear_current_provider(&mut self) {
         self.current_provider = None;
     }
@@ -97,10 +1183,1010 @@ impl Config {
             .and_then(|name| self.providers.get(name))
     }
 
-    fn get_config_path() -> Result<PathBuf> {
+    pub fn active_profile(&self) -> &str {
+        self.active_profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        name == self.active_profile() || self.profiles.contains_key(name)
+    }
+
+    /// All profile names, sorted.
+    pub fn profile_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+        names.push(self.active_profile());
+        names.sort();
+        names
+    }
+
+    /// The providers of `profile`, active or not.
+    pub fn profile_providers(&self, profile: &str) -> Option<&HashMap<String, Provider>> {
+        if profile == self.active_profile() {
+            Some(&self.providers)
+        } else {
+            self.profiles.get(profile).map(|p| &p.providers)
+        }
+    }
+
+    pub fn profile_providers_mut(
+        &mut self,
+        profile: &str,
+    ) -> Option<&mut HashMap<String, Provider>> {
+        if profile == self.active_profile() {
+            Some(&mut self.providers)
+        } else {
+            self.profiles.get_mut(profile).map(|p| &mut p.providers)
+        }
+    }
+
+    pub fn provider_count(&self, profile: &str) -> usize {
+        if profile == self.active_profile() {
+            self.providers.len()
+        } else {
+            self.profiles.get(profile).map_or(0, |p| p.providers.len())
+        }
+    }
+
+    /// Makes `name` the active profile, stashing the current providers under
+    /// the previously active profile. Returns false if it does not exist.
+    pub fn switch_profile(&mut self, name: &str) -> bool {
+        if name == self.active_profile() {
+            return true;
+        }
+
+        let Some(next) = self.profiles.remove(name) else {
+            return false;
+        };
+
+        let previous = Profile {
+            providers: std::mem::replace(&mut self.providers, next.providers),
+            current_provider: std::mem::replace(&mut self.current_provider, next.current_provider),
+        };
+        self.profiles
+            .insert(self.active_profile().to_string(), previous);
+        self.active_profile = Some(name.to_string());
+        // It named a provider of the profile switched away from
+        self.previous_provider = None;
+        true
+    }
+
+    /// Every provider across all profiles, for operations such as
+    /// encryption that must cover tokens in inactive profiles too.
+    fn all_providers_mut(&mut self) -> impl Iterator<Item = &mut Provider> {
+        self.providers.values_mut().chain(
+            self.profiles
+                .values_mut()
+                .flat_map(|profile| profile.providers.values_mut()),
+        )
+    }
+
+    /// Returns the plaintext token for a provider, prompting for the master
+    /// password the first time an encrypted token is needed.
+    pub fn provider_token(&self, provider: &Provider) -> Result<String> {
+        if secret_store::is_keyring_token(&provider.token) {
+            return secret_store::fetch(&self.keyring_key(&provider.name));
+        }
+
+        self.unseal_token(&provider.token)
+            .with_context(|| format!("Failed to decrypt token for '{}'", provider.name))
+    }
+
+    /// A copy of `provider` ready to be exported or probed: the token is in
+    /// plaintext and `${VAR}` references in the token and API URL are
+    /// expanded from the environment.
+    pub fn resolved_provider(&self, provider: &Provider) -> Result<Provider> {
+        let mut resolved = provider.clone();
+        resolved.token = interpolate_env(&self.provider_token(provider)?)
+            .with_context(|| format!("Failed to expand the token of '{}'", provider.name))?;
+        resolved.api_url = interpolate_env(&provider.api_url)
+            .with_context(|| format!("Failed to expand the API URL of '{}'", provider.name))?;
+        Ok(resolved)
+    }
+
+    /// Reverses `seal_token` for a token kept in the config file.
+    pub fn unseal_token(&self, sealed: &str) -> Result<String> {
+        if !self.encrypted {
+            return Ok(sealed.to_string());
+        }
+
+        crypto::decrypt(self.master_key()?, sealed)
+    }
+
+    /// Prepares a plaintext token for storage, encrypting it when enabled.
+    pub fn seal_token(&self, token: String) -> Result<String> {
+        if !self.encrypted {
+            return Ok(token);
+        }
+
+        crypto::encrypt(self.master_key()?, &token)
+    }
+
+    pub fn enable_encryption(&mut self, password: &str) -> Result<()> {
+        let salt = crypto::generate_salt();
+        let key = crypto::derive_key(password, &salt)?;
+
+        for provider in self.all_providers_mut() {
+            for token in &mut provider.tokens {
+                *token = crypto::encrypt(&key, token)?;
+            }
+            if secret_store::is_keyring_token(&provider.token) {
+                continue;
+            }
+            provider.token = crypto::encrypt(&key, &provider.token)?;
+        }
+
+        self.key_check = Some(crypto::key_check(&key)?);
+        self.salt = Some(salt);
+        self.encrypted = true;
+        self.master_key = OnceCell::from(key);
+        Ok(())
+    }
+
+    pub fn disable_encryption(&mut self) -> Result<()> {
+        let key = *self.master_key()?;
+
+        for provider in self.all_providers_mut() {
+            for token in &mut provider.tokens {
+                *token = crypto::decrypt(&key, token)
+                    .with_context(|| format!("Failed to decrypt token for '{}'", provider.name))?;
+            }
+            if secret_store::is_keyring_token(&provider.token) {
+                continue;
+            }
+            provider.token = crypto::decrypt(&key, &provider.token)
+                .with_context(|| format!("Failed to decrypt token for '{}'", provider.name))?;
+        }
+
+        self.encrypted = false;
+        self.salt = None;
+        self.key_check = None;
+        self.master_key = OnceCell::new();
+        Ok(())
+    }
+
+    /// Prompts for the master password now rather than on first token access.
+    #[cfg(feature = "tui")]
+    pub fn unlock(&self) -> Result<()> {
+        if self.encrypted {
+            self.master_key()?;
+        }
+        Ok(())
+    }
+
+    fn master_key(&self) -> Result<&MasterKey> {
+        if let Some(key) = self.master_key.get() {
+            return Ok(key);
+        }
+
+        let salt = self
+            .salt
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Config is marked encrypted but has no salt"))?;
+
+        let password = crypto::prompt_password("🔐 Master password: ")?;
+        let key = crypto::derive_key(&password, salt)?;
+
+        if let Some(check) = &self.key_check {
+            crypto::verify_key(&key, check)?;
+        }
+
+        Ok(self.master_key.get_or_init(|| key))
+    }
+
+    /// Timestamped backups next to the config file
+    /// (`config.toml.<timestamp>.bak`), oldest first.
+    pub fn backup_paths() -> Result<Vec<PathBuf>> {
+        let config_path = Self::get_config_path()?;
+        let (Some(dir), Some(file_name)) = (config_path.parent(), config_path.file_name()) else {
+            return Ok(Vec::new());
+        };
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let prefix = format!("{}.", file_name.to_string_lossy());
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read config directory: {:?}", dir))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Skips the plain `config.toml.bak` left by schema migrations
+                name.strip_prefix(&prefix)
+                    .and_then(|rest| rest.strip_suffix(".bak"))
+                    .is_some_and(|stamp| {
+                        !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit() || c == '-')
+                    })
+            })
+            .map(|entry| entry.path())
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Copies the config file byte for byte to a new timestamped backup and
+    /// deletes all but the newest `keep` backups. Returns the new backup and
+    /// the pruned ones.
+    pub fn create_backup(keep: usize) -> Result<(PathBuf, Vec<PathBuf>)> {
+        let config_path = Self::get_config_path()?;
+        if !config_path.exists() {
+            return Err(anyhow!("No config file to back up at {:?}", config_path));
+        }
+
+        let file_name = config_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let backup_path = config_path.with_file_name(format!(
+            "{}.{}.bak",
+            file_name,
+            Local::now().format(BACKUP_TIMESTAMP_FORMAT)
+        ));
+
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+        write_private(&backup_path, &content)
+            .with_context(|| format!("Failed to write backup: {:?}", backup_path))?;
+
+        let backups = Self::backup_paths()?;
+        let excess = backups.len().saturating_sub(keep);
+        let pruned = backups[..excess].to_vec();
+        for path in &pruned {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove old backup: {:?}", path))?;
+        }
+
+        Ok((backup_path, pruned))
+    }
+
+    /// Replaces the config file with `backup` after checking that it parses,
+    /// first backing up the current file so the restore can be undone; that
+    /// backup's path is returned. The write is atomic, so an interrupted
+    /// restore leaves the old file in place.
+    pub fn restore_backup(backup: &Path) -> Result<Option<PathBuf>> {
+        // Read before backing up: pruning may delete `backup` itself
+        let content = fs::read_to_string(backup)
+            .with_context(|| format!("Failed to read backup: {:?}", backup))?;
+        let config_path = Self::get_config_path()?;
+        ConfigFormat::detect(&config_path)?
+            .parse::<Config>(&content)
+            .and_then(|mut config| config.resolve_inheritance())
+            .with_context(|| format!("Backup {:?} is not a valid config file", backup))?;
+
+        let previous = if config_path.exists() {
+            Some(Self::create_backup(BACKUP_KEEP)?.0)
+        } else {
+            None
+        };
+
+        write_private(&config_path, &content)
+            .with_context(|| format!("Failed to write config file: {:?}", config_path))?;
+        Ok(previous)
+    }
+
+    /// Takes an exclusive advisory lock guarding the load-modify-save cycle;
+    /// it is released when the returned guard is dropped.
+    pub fn lock() -> Result<ConfigLock> {
+        let mut lock_path = Self::get_config_path()?.into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        if let Some(parent) = lock_path.parent() {
+            create_private_dir(parent)
+                .with_context(|| format!("Failed to create config directory: {:?}", parent))?;
+        }
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file: {:?}", lock_path))?;
+
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => {
+                    trace!("Acquired config lock {:?}", lock_path);
+                    return Ok(ConfigLock { _file: file });
+                }
+                Err(_) if Instant::now() < deadline => thread::sleep(LOCK_RETRY_INTERVAL),
+                Err(_) => {
+                    return Err(anyhow!(
+                        "Config is locked by another cce process ({:?}); try again shortly",
+                        lock_path
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Points `load`/`save` at an explicit file, taking precedence over
+    /// `CCE_CONFIG`. Only the first call has an effect.
+    pub fn set_path_override(path: PathBuf) {
+        let _ = CONFIG_PATH_OVERRIDE.set(path);
+    }
+
+    pub fn set_dry_run(enabled: bool) {
+        DRY_RUN.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_dry_run() -> bool {
+        DRY_RUN.load(Ordering::Relaxed)
+    }
+
+    /// Resolves the config file location: `--config`, then `CCE_CONFIG`, then
+    /// `~/.cce/config.toml`. The file's format follows [`ConfigFormat::detect`].
+    pub fn get_config_path() -> Result<PathBuf> {
+        if let Some(path) = CONFIG_PATH_OVERRIDE.get() {
+            return Ok(path.clone());
+        }
+
+        if let Some(path) = std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) {
+            return Ok(PathBuf::from(path));
+        }
+
         let home_dir =
             dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Failed to get user home directory"))?;
 
         Ok(home_dir.join(".cce").join("config.toml"))
     }
 }
+
+/// Custom variables whose name suggests they hold a credential.
+pub fn is_secret_var(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    ["TOKEN", "KEY", "SECRET", "PASSWORD"]
+        .iter()
+        .any(|word| key.contains(word))
+}
+
+/// Headers whose name suggests they carry a credential.
+pub fn is_secret_header(name: &str) -> bool {
+    is_secret_var(name) || name.to_ascii_uppercase().contains("AUTH")
+}
+
+/// Whether `value` refers to environment variables as `${VAR}`; escaped
+/// `$${` does not count.
+pub fn is_env_template(value: &str) -> bool {
+    value.replace("$${", "").contains("${")
+}
+
+/// Expands `${VAR}` references in `value` from the environment, so a config
+/// can point at a secret instead of holding it. Fails if a referenced
+/// variable is unset. `$${` stands for a literal `${`, and a `$` not
+/// followed by `{` is kept as is.
+pub fn interpolate_env(value: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(escaped) = after.strip_prefix("${") {
+            expanded.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let end = reference.find('}').ok_or_else(|| {
+                anyhow!("Unterminated '${{' (expected '${{VAR}}', or '$${{' for a literal '${{')")
+            })?;
+            let var = &reference[..end];
+            if var.is_empty() {
+                return Err(anyhow!("Empty variable name in '${{}}'"));
+            }
+
+            let resolved = std::env::var(var)
+                .map_err(|_| anyhow!("Environment variable {} is not set", var))?;
+            expanded.push_str(&resolved);
+            rest = &reference[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = after;
+        }
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it into
+/// place, so an interrupted save leaves either the old or the new file intact.
+/// On Unix the file is only readable and writable by its owner, since configs,
+/// backups, and env files hold plaintext tokens; the temporary file is created
+/// with that mode, so the content is never exposed with looser permissions.
+pub(crate) fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        // On Windows this uses MoveFileEx with MOVEFILE_REPLACE_EXISTING, so
+        // renaming over an existing config works there as well.
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Creates `path` and its missing parents; on Unix the new directories are
+/// only accessible by their owner.
+pub(crate) fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)
+}
+
+/// The permission bits of `path` when group or other users have any access
+/// to it. Always `None` off Unix, where modes do not apply.
+pub fn loose_permissions(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(path).ok()?.permissions().mode() & 0o777;
+        (mode & 0o077 != 0).then_some(mode)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Resolves `extends` within one profile's providers. Providers are
+/// resolved after the provider they extend, so chains inherit transitively.
+fn resolve_inheritance(
+    providers: &mut HashMap<String, Provider>,
+    bases: &HashMap<String, ProviderBase>,
+) -> Result<()> {
+    let mut names: Vec<String> = providers.keys().cloned().collect();
+    names.sort();
+
+    let mut resolved = HashSet::new();
+    for name in &names {
+        resolve_provider(providers, bases, name, &mut resolved, &mut Vec::new())?;
+    }
+
+    for name in &names {
+        if providers[name].api_url.is_empty() {
+            return Err(anyhow!(
+                "Provider '{}' has no api_url; set one or extend a provider or base that has it",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn resolve_provider(
+    providers: &mut HashMap<String, Provider>,
+    bases: &HashMap<String, ProviderBase>,
+    name: &str,
+    resolved: &mut HashSet<String>,
+    chain: &mut Vec<String>,
+) -> Result<()> {
+    if resolved.contains(name) {
+        return Ok(());
+    }
+    let Some(parent) = providers[name].extends.clone() else {
+        resolved.insert(name.to_string());
+        return Ok(());
+    };
+    if chain.iter().any(|link| link == name) {
+        return Err(anyhow!(
+            "Provider inheritance cycle detected: {} -> {}",
+            chain.join(" -> "),
+            name
+        ));
+    }
+
+    chain.push(name.to_string());
+    let base = if providers.contains_key(&parent) {
+        resolve_provider(providers, bases, &parent, resolved, chain)?;
+        ProviderBase::of(&providers[&parent])
+    } else if let Some(base) = bases.get(&parent) {
+        base.clone()
+    } else {
+        return Err(anyhow!(
+            "Provider '{}' extends '{}', which is neither a provider nor a base",
+            name,
+            parent
+        ));
+    };
+    chain.pop();
+
+    providers.get_mut(name).unwrap().inherit(&base);
+    resolved.insert(name.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_write_keeps_existing_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "version = 1\n").unwrap();
+        // A directory in the way of the temporary file makes the write fail
+        let tmp_path = dir
+            .path()
+            .join(format!(".config.toml.{}.tmp", std::process::id()));
+        fs::create_dir(&tmp_path).unwrap();
+
+        assert!(write_private(&path, "version = 2\n").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version = 1\n");
+    }
+
+    #[test]
+    fn write_replaces_existing_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "version = 1\n").unwrap();
+
+        write_private(&path, "version = 2\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version = 2\n");
+        // Only the config itself is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    fn config_with(names: &[&str]) -> Config {
+        let mut config = Config::default();
+        for name in names {
+            config.add_provider(Provider::new(
+                name.to_string(),
+                format!("https://{}.example.com", name),
+                format!("tok-{}", name),
+            ));
+        }
+        config
+    }
+
+    #[test]
+    fn removing_a_provider_clears_every_pointer_to_it() {
+        let mut config = config_with(&["a", "b"]);
+        config.set_current_provider("b");
+        config.set_current_provider("a");
+        config.default_provider = Some("a".to_string());
+        config.aliases.insert("main".to_string(), "a".to_string());
+        config.aliases.insert("other".to_string(), "b".to_string());
+
+        assert!(config.remove_provider("a"));
+        assert_eq!(config.current_provider, None);
+        assert_eq!(config.previous_provider.as_deref(), Some("b"));
+        assert_eq!(config.default_provider, None);
+        assert_eq!(
+            config.aliases,
+            HashMap::from([("other".to_string(), "b".to_string())])
+        );
+
+        assert!(config.remove_provider("b"));
+        assert_eq!(config.previous_provider, None);
+        assert!(config.aliases.is_empty());
+        assert!(!config.remove_provider("b"));
+    }
+
+    #[test]
+    fn removing_an_extended_provider_detaches_its_children() {
+        let mut config = config_with(&["parent"]);
+        let mut child = Provider::new("child".to_string(), String::new(), "tok".to_string());
+        child.extends = Some("parent".to_string());
+        config.add_provider(child);
+        config.resolve_inheritance().unwrap();
+
+        config.remove_provider("parent");
+        let child = &config.providers["child"];
+        assert_eq!(child.extends, None);
+        // The inherited URL is kept as the child's own
+        assert_eq!(child.api_url, "https://parent.example.com");
+        assert!(child.inherited_fields().is_empty());
+    }
+
+    #[test]
+    fn validate_clears_a_stale_current_provider() {
+        let mut config = config_with(&["a"]);
+        config.current_provider = Some("gone".to_string());
+        assert!(config.validate());
+        assert_eq!(config.current_provider, None);
+        assert!(!config.validate());
+    }
+
+    #[test]
+    fn keyring_entries_are_deleted_only_by_a_real_save() {
+        let _keyring = secret_store::mock::install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let name = "keyring-forget";
+        let key = &secret_store::key(DEFAULT_PROFILE, name);
+
+        let mut config = config_with(&[]);
+        config.add_provider(Provider::new(
+            name.to_string(),
+            "https://keyring.example.com".to_string(),
+            secret_store::KEYRING_SENTINEL.to_string(),
+        ));
+        secret_store::store(key, "tok-keyring").unwrap();
+        config.save_to(&path).unwrap();
+        let saved = fs::read(&path).unwrap();
+
+        crate::provider::ProviderManager::forget_provider(&mut config, name);
+        assert_eq!(
+            secret_store::mock::token(key).as_deref(),
+            Some("tok-keyring")
+        );
+
+        Config::set_dry_run(true);
+        let dry_run = config.save_to(&path);
+        Config::set_dry_run(false);
+        dry_run.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        assert_eq!(
+            secret_store::mock::token(key).as_deref(),
+            Some("tok-keyring")
+        );
+
+        // A failed write keeps the token the file still refers to
+        let tmp_path = dir
+            .path()
+            .join(format!(".config.toml.{}.tmp", std::process::id()));
+        fs::create_dir(&tmp_path).unwrap();
+        assert!(config.save_to(&path).is_err());
+        assert_eq!(
+            secret_store::mock::token(key).as_deref(),
+            Some("tok-keyring")
+        );
+        fs::remove_dir(&tmp_path).unwrap();
+
+        config.save_to(&path).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains(name));
+        assert_eq!(secret_store::mock::token(key), None);
+    }
+
+    #[test]
+    fn provider_debug_output_hides_secrets() {
+        let mut provider = Provider::new(
+            "secretive".to_string(),
+            "https://secretive.example.com".to_string(),
+            "sk-ant-do-not-print".to_string(),
+        );
+        provider.tokens = vec![
+            "sk-ant-do-not-print".to_string(),
+            "sk-ant-backup-token".to_string(),
+        ];
+        provider
+            .extra_env
+            .insert("MY_API_KEY".to_string(), "extra-secret".to_string());
+        provider
+            .extra_env
+            .insert("REGION".to_string(), "eu-west-1".to_string());
+        provider
+            .headers
+            .insert("X-Gateway-Auth".to_string(), "header-secret".to_string());
+        provider
+            .headers
+            .insert("X-Team".to_string(), "platform".to_string());
+
+        let debug = format!("{:?}", provider);
+        for secret in [
+            "sk-ant-do-not-print",
+            "sk-ant-backup-token",
+            "extra-secret",
+            "header-secret",
+        ] {
+            assert!(!debug.contains(secret), "{} leaked: {}", secret, debug);
+        }
+        for shown in [
+            "secretive.example.com",
+            "eu-west-1",
+            "X-Gateway-Auth",
+            "platform",
+        ] {
+            assert!(debug.contains(shown), "{} missing: {}", shown, debug);
+        }
+        assert!(!format!("{:#?}", provider).contains("sk-ant-do-not-print"));
+    }
+
+    /// A config with every field set, so a round trip covers all of them.
+    fn full_config() -> Config {
+        let now = Utc::now();
+        let mut provider = Provider::new(
+            "full".to_string(),
+            "https://full.example.com".to_string(),
+            "tok-full".to_string(),
+        );
+        provider.extends = Some("shared".to_string());
+        provider.model = Some("claude-sonnet-4".to_string());
+        provider.last_used = Some(now);
+        provider.token_env = Some("ANTHROPIC_API_KEY".to_string());
+        provider.url_env = Some("CUSTOM_BASE_URL".to_string());
+        provider
+            .extra_env
+            .insert("REGION".to_string(), "eu".to_string());
+        provider
+            .headers
+            .insert("X-Team".to_string(), "platform".to_string());
+        provider.http_proxy = Some("http://proxy.example.com:8080".to_string());
+        provider.https_proxy = Some("http://proxy.example.com:8443".to_string());
+        provider.tags = vec!["prod".to_string(), "work".to_string()];
+        provider.description = Some("Everything \"quoted\" and\nmultiline".to_string());
+        provider.console_url = Some("https://console.example.com".to_string());
+        provider.weight = Some(3);
+        provider.locked = true;
+        provider.tokens = vec!["tok-full".to_string(), "tok-spare".to_string()];
+        provider.active_token = 1;
+        provider.expires_at = Some(now + chrono::Duration::days(30));
+
+        let mut config = Config {
+            version: CONFIG_VERSION,
+            current_provider: Some("full".to_string()),
+            previous_provider: Some("full".to_string()),
+            active_profile: Some("work".to_string()),
+            default_provider: Some("full".to_string()),
+            aliases: HashMap::from([("f".to_string(), "full".to_string())]),
+            command_aliases: HashMap::from([(
+                "f".to_string(),
+                vec!["use".to_string(), "full".to_string()],
+            )]),
+            bases: HashMap::from([(
+                "shared".to_string(),
+                ProviderBase {
+                    api_url: Some("https://base.example.com".to_string()),
+                    model: Some("claude-opus-4".to_string()),
+                    http_proxy: Some("http://base-proxy.example.com".to_string()),
+                    https_proxy: Some("http://base-proxy.example.com".to_string()),
+                    extra_env: HashMap::from([("BASE".to_string(), "1".to_string())]),
+                },
+            )]),
+            encrypted: true,
+            salt: Some("c2FsdA".to_string()),
+            key_check: Some("Y2hlY2s".to_string()),
+            keyring: true,
+            history: vec![HistoryEntry {
+                provider: "full".to_string(),
+                used_at: now,
+            }],
+            timeout_secs: Some(20),
+            env_scheme: Some(EnvScheme::Both),
+            on_switch: Some("echo switched to $1".to_string()),
+            round_robin: HashMap::from([("prod".to_string(), 2)]),
+            verify_on_use: true,
+            clipboard_clear_secs: Some(0),
+            ..Config::default()
+        };
+        config.profiles.insert(
+            "personal".to_string(),
+            Profile {
+                providers: HashMap::from([("full".to_string(), provider.clone())]),
+                current_provider: Some("full".to_string()),
+            },
+        );
+        config.add_provider(provider);
+        config
+    }
+
+    fn round_trip(format: ConfigFormat) {
+        let config = full_config();
+        let expected = serde_json::to_value(&config).unwrap();
+
+        // Fails when a new field is left out of `full_config`
+        let schema = serde_json::to_value(schemars::schema_for!(Config)).unwrap();
+        for (definition, value) in [
+            (&schema, &expected),
+            (&schema["$defs"]["Provider"], &expected["providers"]["full"]),
+        ] {
+            for field in definition["properties"].as_object().unwrap().keys() {
+                assert!(
+                    value.get(field).is_some(),
+                    "full_config leaves {} unset",
+                    field
+                );
+            }
+        }
+
+        let serialized = format.serialize(&config).unwrap();
+        let parsed: Config = format.parse(&serialized).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            expected,
+            "{}",
+            serialized
+        );
+    }
+
+    #[test]
+    fn toml_round_trip_keeps_every_field() {
+        round_trip(ConfigFormat::Toml);
+    }
+
+    #[test]
+    fn yaml_round_trip_keeps_every_field() {
+        round_trip(ConfigFormat::Yaml);
+    }
+
+    #[test]
+    fn interpolation_expands_set_variables() {
+        std::env::set_var("CCE_TEST_INTERPOLATE_HOST", "gateway.example.com");
+        std::env::set_var("CCE_TEST_INTERPOLATE_EMPTY", "");
+        assert_eq!(
+            interpolate_env("https://${CCE_TEST_INTERPOLATE_HOST}/v1").unwrap(),
+            "https://gateway.example.com/v1"
+        );
+        assert_eq!(
+            interpolate_env("${CCE_TEST_INTERPOLATE_HOST}${CCE_TEST_INTERPOLATE_EMPTY}:${CCE_TEST_INTERPOLATE_HOST}")
+                .unwrap(),
+            "gateway.example.com:gateway.example.com"
+        );
+        assert_eq!(interpolate_env("no references").unwrap(), "no references");
+        assert_eq!(interpolate_env("cost: $5, $").unwrap(), "cost: $5, $");
+    }
+
+    #[test]
+    fn interpolation_fails_for_unset_variables() {
+        std::env::remove_var("CCE_TEST_INTERPOLATE_UNSET");
+        let error = interpolate_env("${CCE_TEST_INTERPOLATE_UNSET}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Environment variable CCE_TEST_INTERPOLATE_UNSET is not set"
+        );
+        assert!(interpolate_env("${UNTERMINATED").is_err());
+        assert!(interpolate_env("${}").is_err());
+    }
+
+    #[test]
+    fn interpolation_keeps_escaped_references() {
+        std::env::remove_var("CCE_TEST_INTERPOLATE_ESCAPED");
+        assert_eq!(
+            interpolate_env("ab$${CCE_TEST_INTERPOLATE_ESCAPED}cd").unwrap(),
+            "ab${CCE_TEST_INTERPOLATE_ESCAPED}cd"
+        );
+        assert_eq!(interpolate_env("$${").unwrap(), "${");
+        assert_eq!(interpolate_env("$$x").unwrap(), "$$x");
+        assert!(!is_env_template("ab$${cd"));
+        assert!(is_env_template("ab$${cd${CCE_TEST_INTERPOLATE_ESCAPED}"));
+        assert!(!is_env_template("plain-token"));
+    }
+
+    /// A provider named `name` with its token in the keyring.
+    fn keyring_provider(name: &str) -> Provider {
+        Provider::new(
+            name.to_string(),
+            format!("https://{}.example.com", name),
+            secret_store::KEYRING_SENTINEL.to_string(),
+        )
+    }
+
+    #[test]
+    fn keyring_entries_are_kept_per_profile() {
+        let _keyring = secret_store::mock::install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let name = "keyring-shared";
+
+        let mut config = config_with(&[]);
+        config
+            .profiles
+            .insert("work".to_string(), Profile::default());
+        config.add_provider(keyring_provider(name));
+        secret_store::store(&config.keyring_key(name), "tok-default").unwrap();
+        assert!(config.switch_profile("work"));
+        config.add_provider(keyring_provider(name));
+        secret_store::store(&config.keyring_key(name), "tok-work").unwrap();
+
+        assert_eq!(
+            config.provider_token(&config.providers[name]).unwrap(),
+            "tok-work"
+        );
+        config.providers.remove(name);
+        config.forget_keyring_entry(name);
+        config.save_to(&path).unwrap();
+
+        assert!(config.switch_profile(DEFAULT_PROFILE));
+        assert_eq!(
+            config.provider_token(&config.providers[name]).unwrap(),
+            "tok-default"
+        );
+        assert_eq!(
+            secret_store::mock::token(&secret_store::key("work", name)),
+            None
+        );
+    }
+
+    #[test]
+    fn migration_moves_keyring_entries_into_their_profiles() {
+        let _keyring = secret_store::mock::install();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let (shared, missing) = ("keyring-legacy", "keyring-legacy-missing");
+
+        let mut config = config_with(&[]);
+        config.version = 1;
+        config.add_provider(keyring_provider(shared));
+        config.add_provider(keyring_provider(missing));
+        let mut work = Profile::default();
+        work.providers
+            .insert(shared.to_string(), keyring_provider(shared));
+        config.profiles.insert("work".to_string(), work);
+        // Version 1 keyed entries by provider name alone
+        secret_store::store(shared, "tok-legacy").unwrap();
+
+        config.migrate();
+        assert_eq!(config.version, CONFIG_VERSION);
+        for profile in [DEFAULT_PROFILE, "work"] {
+            assert_eq!(
+                secret_store::mock::token(&secret_store::key(profile, shared)).as_deref(),
+                Some("tok-legacy"),
+                "{}",
+                profile
+            );
+        }
+        // The old entry stays until the migrated file is saved
+        assert!(secret_store::mock::token(shared).is_some());
+
+        config.save_to(&path).unwrap();
+        assert_eq!(secret_store::mock::token(shared), None);
+        assert_eq!(
+            secret_store::mock::token(&secret_store::key(DEFAULT_PROFILE, missing)),
+            None
+        );
+    }
+
+    #[test]
+    fn encryption_round_trip_covers_every_profile() {
+        let mut config = config_with(&["a"]);
+        config.providers.get_mut("a").unwrap().tokens = vec!["tok-pooled".to_string()];
+        config.add_provider(keyring_provider("keyring-encrypted"));
+        let mut work = Profile::default();
+        work.providers.insert(
+            "w".to_string(),
+            Provider::new(
+                "w".to_string(),
+                "https://w.example.com".to_string(),
+                "tok-work".to_string(),
+            ),
+        );
+        config.profiles.insert("work".to_string(), work);
+        let plain = config.clone();
+
+        config.enable_encryption("correct horse").unwrap();
+        assert!(config.encrypted);
+        let a = &config.providers["a"];
+        assert_ne!(a.token, plain.providers["a"].token);
+        assert_eq!(
+            config.provider_token(a).unwrap(),
+            plain.providers["a"].token
+        );
+        assert_eq!(config.unseal_token(&a.tokens[0]).unwrap(), "tok-pooled");
+        assert_ne!(config.profiles["work"].providers["w"].token, "tok-work");
+        // The keyring keeps its own tokens
+        assert_eq!(
+            config.providers["keyring-encrypted"].token,
+            secret_store::KEYRING_SENTINEL
+        );
+
+        config.disable_encryption().unwrap();
+        assert!(!config.encrypted);
+        assert_eq!(
+            (config.salt.as_ref(), config.key_check.as_ref()),
+            (None, None)
+        );
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::to_value(&plain).unwrap()
+        );
+    }
+}
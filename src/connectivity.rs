@@ -0,0 +1,321 @@
+use anyhow::{Context, Result};
+use log::debug;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Proxy, StatusCode};
+use std::collections::HashMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use url::Url;
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+/// Number of providers `cce test --all` and `cce list --health` check at once.
+pub const DEFAULT_JOBS: usize = 8;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Debug)]
+pub enum ProbeStatus {
+    /// The endpoint answered with a 2xx status
+    Ok(StatusCode),
+    /// The endpoint rejected the token (401/403)
+    AuthFailed(StatusCode),
+    /// The endpoint answered, but with an unexpected status
+    Unexpected(StatusCode),
+    /// The request never got a response
+    Unreachable(String),
+}
+
+#[derive(Debug)]
+pub struct ProbeResult {
+    pub status: ProbeStatus,
+    pub latency: Duration,
+}
+
+/// One provider to check with `probe_all`.
+#[derive(Debug)]
+pub struct ProbeTarget {
+    pub api_url: String,
+    pub token: String,
+    pub proxy: Option<String>,
+    pub headers: HashMap<String, String>,
+}
+
+/// Identifying details reported by a provider, as `(field, value)` pairs in
+/// the order received. Empty when the provider reports nothing useful.
+#[derive(Debug)]
+pub struct AccountInfo {
+    pub status: ProbeStatus,
+    pub fields: Vec<(String, String)>,
+}
+
+/// Response headers that identify the account or describe its limits.
+const ACCOUNT_HEADER_PREFIXES: [&str; 4] = [
+    "anthropic-organization",
+    "anthropic-ratelimit-",
+    "openai-organization",
+    "x-ratelimit-",
+];
+
+/// Sends a lightweight authenticated request (`GET /v1/models`) to the
+/// provider, through `proxy` when given and with the provider's extra
+/// `headers`, and classifies the outcome.
+pub fn probe(
+    api_url: &str,
+    token: &str,
+    proxy: Option<&str>,
+    headers: &HashMap<String, String>,
+    timeout: Duration,
+) -> Result<ProbeResult> {
+    let client = build_client(proxy, timeout)?;
+
+    let started = Instant::now();
+    let response = authenticated_get(&client, &models_url(api_url), token, headers)?.send();
+    let latency = started.elapsed();
+
+    let status = match response {
+        Ok(response) => classify(response.status()),
+        Err(e) => ProbeStatus::Unreachable(describe_error(&e, timeout)),
+    };
+
+    Ok(ProbeResult { status, latency })
+}
+
+/// Checks that the host of `url` resolves and accepts a TCP connection on
+/// its port, without sending a request. The error says which step failed.
+pub fn reachable(url: &str, timeout: Duration) -> Result<()> {
+    let parsed = Url::parse(url).with_context(|| format!("Invalid URL '{}'", url))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("URL '{}' has no host", url))?;
+    let port = parsed
+        .port_or_known_default()
+        .ok_or_else(|| anyhow::anyhow!("URL '{}' has no port", url))?;
+
+    debug!("Resolving {}:{}", host, port);
+    let addrs: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("cannot resolve host '{}'", host))?
+        .collect();
+
+    let mut last_error = None;
+    for addr in addrs {
+        debug!("Connecting to {}", addr);
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    match last_error {
+        Some(e) if e.kind() == std::io::ErrorKind::TimedOut => Err(anyhow::anyhow!(
+            "{}:{} did not accept a connection within {}s",
+            host,
+            port,
+            timeout.as_secs()
+        )),
+        Some(e) => Err(anyhow::anyhow!(
+            "cannot connect to {}:{} ({})",
+            host,
+            port,
+            e
+        )),
+        None => Err(anyhow::anyhow!("host '{}' has no addresses", host)),
+    }
+}
+
+/// Probes every target on a pool of at most `jobs` threads, each request
+/// with its own `timeout`, and returns the results in the order of `targets`.
+pub fn probe_all(
+    targets: &[ProbeTarget],
+    timeout: Duration,
+    jobs: usize,
+) -> Vec<Result<ProbeResult>> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<ProbeResult>>>> =
+        Mutex::new(targets.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, targets.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(target) = targets.get(index) else {
+                    break;
+                };
+                let result = probe(
+                    &target.api_url,
+                    &target.token,
+                    target.proxy.as_deref(),
+                    &target.headers,
+                    timeout,
+                );
+                results.lock().unwrap_or_else(PoisonError::into_inner)[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .into_iter()
+        .map(|result| result.expect("every target is probed"))
+        .collect()
+}
+
+/// Asks the provider who the token belongs to. Gateways with a known
+/// key/balance endpoint are queried there; otherwise the identifying headers
+/// of `GET /v1/models` (organization, rate limits) are collected.
+pub fn account_info(
+    api_url: &str,
+    token: &str,
+    proxy: Option<&str>,
+    headers: &HashMap<String, String>,
+    timeout: Duration,
+) -> Result<AccountInfo> {
+    let client = build_client(proxy, timeout)?;
+    let url = account_url(api_url).unwrap_or_else(|| models_url(api_url));
+
+    let response = match authenticated_get(&client, &url, token, headers)?.send() {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(AccountInfo {
+                status: ProbeStatus::Unreachable(describe_error(&e, timeout)),
+                fields: Vec::new(),
+            })
+        }
+    };
+
+    let status = classify(response.status());
+    let mut fields: Vec<(String, String)> = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            ACCOUNT_HEADER_PREFIXES
+                .iter()
+                .any(|prefix| name.as_str().starts_with(prefix))
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    if account_url(api_url).is_some() && matches!(status, ProbeStatus::Ok(_)) {
+        let body = response
+            .text()
+            .with_context(|| "Failed to read account response")?;
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
+            // OpenRouter wraps the key details in `data`
+            let json = json.get("data").cloned().unwrap_or(json);
+            flatten_json("", &json, &mut fields);
+        }
+    }
+
+    Ok(AccountInfo { status, fields })
+}
+
+fn build_client(proxy: Option<&str>, timeout: Duration) -> Result<Client> {
+    let mut builder = Client::builder().timeout(timeout);
+    if let Some(proxy) = proxy {
+        builder = builder
+            .proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy URL '{}'", proxy))?);
+    }
+    builder
+        .build()
+        .with_context(|| "Failed to build HTTP client")
+}
+
+/// A GET request carrying the token the usual ways. The provider's own
+/// `headers` come last and replace standard ones of the same name, for
+/// gateways that expect e.g. a different `x-api-key`.
+fn authenticated_get(
+    client: &Client,
+    url: &str,
+    token: &str,
+    headers: &HashMap<String, String>,
+) -> Result<RequestBuilder> {
+    debug!("GET {}", url);
+    let mut extra = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::try_from(name.as_str())
+            .with_context(|| format!("Invalid header name '{}'", name))?;
+        let value = HeaderValue::try_from(value.as_str())
+            .with_context(|| format!("Invalid value for header '{}'", name))?;
+        extra.insert(name, value);
+    }
+
+    Ok(client
+        .get(url)
+        .header("x-api-key", token)
+        .bearer_auth(token)
+        .header("anthropic-version", ANTHROPIC_VERSION)
+        .headers(extra))
+}
+
+fn classify(code: StatusCode) -> ProbeStatus {
+    debug!("Provider answered {}", code);
+    if code.is_success() {
+        ProbeStatus::Ok(code)
+    } else if code == StatusCode::UNAUTHORIZED || code == StatusCode::FORBIDDEN {
+        ProbeStatus::AuthFailed(code)
+    } else {
+        ProbeStatus::Unexpected(code)
+    }
+}
+
+/// Account endpoints of gateways that have one, keyed by host.
+fn account_url(api_url: &str) -> Option<String> {
+    let host = Url::parse(api_url).ok()?.host_str()?.to_string();
+    match host.as_str() {
+        "openrouter.ai" => Some("https://openrouter.ai/api/v1/key".to_string()),
+        "api.deepseek.com" => Some("https://api.deepseek.com/user/balance".to_string()),
+        _ => None,
+    }
+}
+
+/// Flattens nested objects and arrays into dotted `a.b.0.c` keys, skipping nulls.
+fn flatten_json(prefix: &str, value: &serde_json::Value, fields: &mut Vec<(String, String)>) {
+    let key = |child: &str| {
+        if prefix.is_empty() {
+            child.to_string()
+        } else {
+            format!("{}.{}", prefix, child)
+        }
+    };
+
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::Object(map) => {
+            for (name, child) in map {
+                flatten_json(&key(name), child, fields);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (index, child) in items.iter().enumerate() {
+                flatten_json(&key(&index.to_string()), child, fields);
+            }
+        }
+        serde_json::Value::String(text) => fields.push((prefix.to_string(), text.clone())),
+        other => fields.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+fn models_url(api_url: &str) -> String {
+    let base = api_url.trim_end_matches('/');
+    if base.ends_with("/v1") {
+        format!("{}/models", base)
+    } else {
+        format!("{}/v1/models", base)
+    }
+}
+
+fn describe_error(error: &reqwest::Error, timeout: Duration) -> String {
+    if error.is_timeout() {
+        format!("provider did not respond within {}s", timeout.as_secs())
+    } else if error.is_connect() {
+        "connection failed".to_string()
+    } else if error.is_builder() {
+        "invalid URL".to_string()
+    } else {
+        error.to_string()
+    }
+}
@@ -0,0 +1,141 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, Context, Result};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Known plaintext stored encrypted in the config so a wrong master password
+/// is rejected before any token is decrypted or re-encrypted.
+const KEY_CHECK_PLAINTEXT: &str = "cce-master-key-check";
+
+pub type MasterKey = [u8; 32];
+
+pub fn generate_salt() -> String {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    STANDARD.encode(salt)
+}
+
+pub fn derive_key(password: &str, salt: &str) -> Result<MasterKey> {
+    let salt = STANDARD
+        .decode(salt)
+        .with_context(|| "Invalid encryption salt in config file")?;
+
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), &salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive master key: {}", e))?;
+
+    Ok(key)
+}
+
+pub fn encrypt(key: &MasterKey, plaintext: &str) -> Result<String> {
+    let cipher = Aes256Gcm::new(key.into());
+
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| anyhow!("Failed to encrypt token"))?;
+
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(payload))
+}
+
+pub fn decrypt(key: &MasterKey, encoded: &str) -> Result<String> {
+    let payload = STANDARD
+        .decode(encoded)
+        .with_context(|| "Encrypted token is not valid base64")?;
+
+    if payload.len() < NONCE_LEN {
+        return Err(anyhow!("Encrypted token is truncated"));
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(key.into());
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Incorrect master password or corrupted token"))?;
+
+    String::from_utf8(plaintext).with_context(|| "Decrypted token is not valid UTF-8")
+}
+
+pub fn key_check(key: &MasterKey) -> Result<String> {
+    encrypt(key, KEY_CHECK_PLAINTEXT)
+}
+
+pub fn verify_key(key: &MasterKey, check: &str) -> Result<()> {
+    match decrypt(key, check) {
+        Ok(value) if value == KEY_CHECK_PLAINTEXT => Ok(()),
+        _ => Err(anyhow!("Incorrect master password")),
+    }
+}
+
+pub fn prompt_password(prompt: &str) -> Result<String> {
+    rpassword::prompt_password(prompt).with_context(|| "Failed to read master password")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_for(password: &str, salt: &str) -> MasterKey {
+        derive_key(password, salt).unwrap()
+    }
+
+    #[test]
+    fn encrypted_tokens_decrypt_to_the_original() {
+        let key = key_for("correct horse", &generate_salt());
+
+        let sealed = encrypt(&key, "sk-ant-secret").unwrap();
+        assert!(!sealed.contains("sk-ant-secret"));
+        assert_eq!(decrypt(&key, &sealed).unwrap(), "sk-ant-secret");
+        // A fresh nonce each time, so equal tokens do not look equal
+        assert_ne!(encrypt(&key, "sk-ant-secret").unwrap(), sealed);
+        assert_eq!(decrypt(&key, &encrypt(&key, "").unwrap()).unwrap(), "");
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let salt = generate_salt();
+        let key = key_for("correct horse", &salt);
+        let check = key_check(&key).unwrap();
+        assert!(verify_key(&key, &check).is_ok());
+
+        let wrong = key_for("battery staple", &salt);
+        let error = verify_key(&wrong, &check).unwrap_err();
+        assert_eq!(error.to_string(), "Incorrect master password");
+        assert!(decrypt(&wrong, &encrypt(&key, "sk-ant-secret").unwrap()).is_err());
+        // The same password with another salt is another key
+        assert!(verify_key(&key_for("correct horse", &generate_salt()), &check).is_err());
+    }
+
+    #[test]
+    fn damaged_payloads_are_rejected() {
+        let key = key_for("correct horse", &generate_salt());
+        let sealed = encrypt(&key, "sk-ant-secret").unwrap();
+        let payload = STANDARD.decode(&sealed).unwrap();
+
+        let truncated = STANDARD.encode(&payload[..NONCE_LEN - 1]);
+        assert_eq!(
+            decrypt(&key, &truncated).unwrap_err().to_string(),
+            "Encrypted token is truncated"
+        );
+        // The nonce alone, or a cut-off tag, fails authentication
+        assert!(decrypt(&key, &STANDARD.encode(&payload[..NONCE_LEN])).is_err());
+        assert!(decrypt(&key, &STANDARD.encode(&payload[..payload.len() - 1])).is_err());
+
+        let mut garbled = payload.clone();
+        *garbled.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&key, &STANDARD.encode(&garbled)).is_err());
+        assert!(decrypt(&key, "not base64!").is_err());
+        assert!(verify_key(&key, "not base64!").is_err());
+    }
+}
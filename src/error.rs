@@ -0,0 +1,56 @@
+use colored::*;
+use std::fmt;
+
+/// Failures with a well-known cause. `main` prints them and exits non-zero,
+/// and callers can downcast an `anyhow::Error` to match on the cause.
+#[derive(Debug)]
+pub enum CceError {
+    ProviderNotFound(String),
+    /// The target name is taken and `--force` was not given
+    ProviderExists(String),
+    AliasNotFound(String),
+    CommandAliasNotFound(String),
+    ProfileNotFound(String),
+    ProfileExists(String),
+    TemplateNotFound(String),
+    /// The provider is locked and `--force` was not given
+    ProviderLocked(String),
+}
+
+impl fmt::Display for CceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CceError::ProviderNotFound(name) => {
+                write!(f, "Service provider '{}' does not exist", name.red())
+            }
+            CceError::ProviderExists(name) => write!(
+                f,
+                "Service provider '{}' already exists; use {} to overwrite it",
+                name.red(),
+                "--force".yellow()
+            ),
+            CceError::AliasNotFound(alias) => write!(f, "Alias '{}' does not exist", alias.red()),
+            CceError::CommandAliasNotFound(name) => {
+                write!(f, "Command alias '{}' does not exist", name.red())
+            }
+            CceError::ProfileNotFound(name) => {
+                write!(f, "Profile '{}' does not exist", name.red())
+            }
+            CceError::ProfileExists(name) => write!(f, "Profile '{}' already exists", name.red()),
+            CceError::TemplateNotFound(name) => write!(
+                f,
+                "Unknown template '{}'; run 'cce templates' to list them",
+                name.red()
+            ),
+            CceError::ProviderLocked(name) => write!(
+                f,
+                "Service provider '{}' is locked; use {} or run 'cce unlock {}' first",
+                name.red(),
+                "--force".yellow(),
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CceError {}
@@ -0,0 +1,157 @@
+use crate::config::{self, Config};
+use crate::connectivity::{ProbeResult, ProbeStatus};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::debug;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long a cached result is trusted before `cce list --health` and
+/// `cce test --all` check the provider again.
+pub const HEALTH_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Ok,
+    AuthFailed,
+    Unexpected,
+    Unreachable,
+}
+
+impl Health {
+    /// The name used in JSON, for `--format table`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Health::Ok => "ok",
+            Health::AuthFailed => "auth_failed",
+            Health::Unexpected => "unexpected",
+            Health::Unreachable => "unreachable",
+        }
+    }
+}
+
+/// Outcome of the last connectivity check of one provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthEntry {
+    pub health: Health,
+    /// HTTP status, when the provider answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<u16>,
+    /// Why the request failed, for unreachable providers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub latency_ms: u64,
+    pub checked_at: DateTime<Utc>,
+    /// API URL that was checked; editing the URL invalidates the entry
+    pub api_url: String,
+}
+
+impl HealthEntry {
+    pub fn age(&self) -> Duration {
+        (Utc::now() - self.checked_at).to_std().unwrap_or_default()
+    }
+
+    /// The cached result in the form `connectivity::probe` returns it.
+    pub fn status(&self) -> ProbeStatus {
+        let code = self
+            .code
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::OK);
+        match self.health {
+            Health::Ok => ProbeStatus::Ok(code),
+            Health::AuthFailed => ProbeStatus::AuthFailed(code),
+            Health::Unexpected => ProbeStatus::Unexpected(code),
+            Health::Unreachable => {
+                ProbeStatus::Unreachable(self.reason.clone().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Last connectivity result per provider. It lives in `health.json` next to
+/// the config rather than inside it, so a damaged cache can never break the
+/// config, and checks never rewrite the config file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HealthCache {
+    #[serde(default)]
+    providers: BTreeMap<String, HealthEntry>,
+}
+
+impl HealthCache {
+    pub fn path() -> Result<PathBuf> {
+        Ok(Config::get_config_path()?.with_file_name("health.json"))
+    }
+
+    /// Loads the cache; a missing or unreadable file is treated as empty,
+    /// since every entry can be rebuilt by checking again.
+    pub fn load() -> Self {
+        let Ok(path) = Self::path() else {
+            return Self::default();
+        };
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            debug!("Ignoring unreadable health cache {:?}: {}", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if Config::is_dry_run() {
+            return Ok(());
+        }
+
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            config::create_private_dir(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        config::write_private(&path, &content)
+            .with_context(|| format!("Failed to write health cache {:?}", path))
+    }
+
+    /// The cached result for `name`, unless it is for another API URL.
+    pub fn get(&self, name: &str, api_url: &str) -> Option<&HealthEntry> {
+        self.providers
+            .get(name)
+            .filter(|entry| entry.api_url == api_url)
+    }
+
+    /// The cached result for `name` when it is younger than `HEALTH_TTL`.
+    pub fn fresh(&self, name: &str, api_url: &str) -> Option<&HealthEntry> {
+        self.get(name, api_url)
+            .filter(|entry| entry.age() < HEALTH_TTL)
+    }
+
+    pub fn record(&mut self, name: &str, api_url: &str, result: &ProbeResult) -> &HealthEntry {
+        let (health, code, reason) = match &result.status {
+            ProbeStatus::Ok(code) => (Health::Ok, Some(code.as_u16()), None),
+            ProbeStatus::AuthFailed(code) => (Health::AuthFailed, Some(code.as_u16()), None),
+            ProbeStatus::Unexpected(code) => (Health::Unexpected, Some(code.as_u16()), None),
+            ProbeStatus::Unreachable(reason) => (Health::Unreachable, None, Some(reason.clone())),
+        };
+        let entry = HealthEntry {
+            health,
+            code,
+            reason,
+            latency_ms: result.latency.as_millis() as u64,
+            checked_at: Utc::now(),
+            api_url: api_url.to_string(),
+        };
+        self.providers.insert(name.to_string(), entry);
+        &self.providers[name]
+    }
+
+    /// Drops entries of providers that no longer exist in `config`.
+    pub fn prune(&mut self, config: &Config) {
+        self.providers
+            .retain(|name, _| config.providers.contains_key(name));
+    }
+}
@@ -1,54 +1,639 @@
 mod cli;
+mod clipboard;
 mod config;
+mod connectivity;
+mod crypto;
+mod error;
+mod health;
 mod provider;
+mod secret_store;
+mod shell;
+mod table;
+mod templates;
+mod toml_merge;
+mod transfer;
+#[cfg(feature = "tui")]
+mod tui;
 
-use anyhow::Result;
-use cli::{Cli, Commands};
-use config::Config;
-use provider::ProviderManager;
+use anyhow::{anyhow, Result};
+use clap::CommandFactory;
+use clap_complete::CompleteEnv;
+use cli::{
+    AliasCmdCommands, Cli, Commands, ConfigCommands, EnvFormat, KeyringCommands, OutputFormat,
+    ProfileCommands, TokenCommands,
+};
+use colored::*;
+use config::{Config, Provider};
+use provider::{ListFilter, ProviderEdit, ProviderManager, PruneCriteria, Rotation};
+use shell::Shell;
+
+fn main() {
+    // Errors, including CceError's friendly messages, carry the exit status
+    if let Err(err) = run() {
+        eprintln!("{} {:#}", "❌".red(), err);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    // Answers dynamic completion requests from the shell and exits
+    CompleteEnv::with_factory(Cli::command).complete();
+
+    let cli = Cli::parse_args()?;
+    cli.apply_color_choice();
+    init_logging(cli.verbose);
+    if let Some(path) = &cli.config {
+        Config::set_path_override(path.clone());
+    }
+    Config::set_dry_run(cli.dry_run);
+    ProviderManager::set_quiet(cli.quiet);
+
+    // Held until main returns so concurrent commands cannot clobber each other
+    let _lock = if cli.modifies_config() {
+        Some(Config::lock()?)
+    } else {
+        None
+    };
+
+    // Runs before loading so that it can report a config that fails to load
+    if let Commands::Doctor = cli.command {
+        if !ProviderManager::run_doctor()? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Commands::Validate = cli.command {
+        if !ProviderManager::validate_config()? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Describes the file format, so it needs no config at all
+    if let Commands::Schema = cli.command {
+        ProviderManager::print_config_schema()?;
+        return Ok(());
+    }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse_args();
     let mut config = Config::load()?;
+    let timeout = config.timeout(cli.timeout);
 
     match cli.command {
-        Commands::List => {
-            ProviderManager::list_providers(&config)?;
+        Commands::List {
+            sort,
+            filter,
+            tag,
+            health,
+            format,
+            json,
+        } => {
+            let filter = ListFilter {
+                text: filter.as_deref(),
+                tag: tag.as_deref(),
+            };
+            let health = health.then_some(timeout);
+            let format = if json { OutputFormat::Json } else { format };
+            ProviderManager::list_providers(&config, sort, filter, format, health)?;
+        }
+
+        Commands::Search { query, json } => {
+            ProviderManager::search_providers(&config, &query, json)?;
         }
 
         Commands::Add {
             name,
             api_url,
             token,
+            token_file,
+            template,
+            url,
             model,
+            token_env,
+            url_env,
+            description,
+            console_url,
+            weight,
+            no_validate,
+            yes,
+            force,
         } => {
-            ProviderManager::add_provider(&mut config, name, api_url, token, model)?;
+            let template = template.as_deref().map(templates::find).transpose()?;
+            let api_url = api_url
+                .or(url)
+                .or_else(|| template.map(|template| template.api_url.to_string()))
+                .ok_or_else(|| anyhow!("An API URL or --template is required"))?;
+
+            let token = ProviderManager::read_token(token, token_file.as_deref())?;
+            let mut provider = Provider::new(name, api_url, token);
+            provider.model =
+                model.or_else(|| template.and_then(|template| template.model.map(String::from)));
+            if let Some(template) = template {
+                for (key, value) in template.extra_env {
+                    provider
+                        .extra_env
+                        .insert(key.to_string(), value.to_string());
+                }
+            }
+            provider.token_env = token_env;
+            provider.url_env = url_env;
+            provider.description = description.filter(|description| !description.is_empty());
+            provider.console_url = console_url;
+            provider.weight = weight;
+            ProviderManager::add_provider(&mut config, provider, !no_validate, yes, force)?;
         }
 
-        Commands::Delete { name } => {
-            ProviderManager::remove_provider(&mut config, &name)?;
+        Commands::Ensure {
+            name,
+            url,
+            token,
+            token_file,
+            model,
+            no_validate,
+        } => {
+            let token = ProviderManager::read_token(token, token_file.as_deref())?;
+            ProviderManager::ensure_provider(&mut config, &name, url, token, model, !no_validate)?;
+        }
+
+        Commands::Delete {
+            name,
+            all,
+            tag,
+            yes,
+            force,
+        } => match (name, tag) {
+            (_, Some(tag)) => ProviderManager::remove_tagged(&mut config, &tag, yes, force)?,
+            (Some(name), None) if all => {
+                ProviderManager::remove_matching(&mut config, &name, yes, force)?
+            }
+            (Some(name), None) => ProviderManager::remove_provider(&mut config, &name, yes, force)?,
+            (None, None) => unreachable!("clap requires a name or --tag"),
+        },
+
+        Commands::Prune {
+            dead,
+            expired,
+            unused,
+            yes,
+        } => {
+            let criteria = PruneCriteria {
+                dead: dead.then_some(timeout),
+                expired,
+                unused_days: unused,
+            };
+            ProviderManager::prune_providers(&mut config, criteria, yes)?;
         }
 
-        Commands::Use { name } => {
-            ProviderManager::use_provider(&mut config, &name)?;
+        Commands::Edit {
+            name,
+            api_url,
+            token,
+            token_file,
+            token_env,
+            url_env,
+            description,
+            console_url,
+            weight,
+            expires,
+            no_validate,
+            force,
+        } => {
+            let token = if token.is_some() || token_file.is_some() {
+                Some(ProviderManager::read_token(token, token_file.as_deref())?)
+            } else {
+                None
+            };
+            let edit = ProviderEdit {
+                api_url,
+                token,
+                token_env,
+                url_env,
+                description,
+                console_url,
+                weight,
+                expires,
+            };
+            ProviderManager::edit_provider(&mut config, &name, edit, !no_validate, force)?;
         }
 
-        Commands::Check => {
-            ProviderManager::check_environment(&config)?;
+        Commands::Rotate {
+            name,
+            token,
+            token_file,
+            force,
+        } => {
+            let token = ProviderManager::read_token(token, token_file.as_deref())?;
+            ProviderManager::rotate_token(&mut config, &name, token, timeout, force)?;
         }
 
-        Commands::Shellenv => {
-            ProviderManager::output_shellenv()?;
+        Commands::Token { action } => match action {
+            TokenCommands::Add {
+                name,
+                token,
+                token_file,
+            } => {
+                let token = ProviderManager::read_token(token, token_file.as_deref())?;
+                ProviderManager::add_token(&mut config, &name, token)?;
+            }
+            TokenCommands::List { name } => ProviderManager::list_tokens(&config, &name)?,
+            TokenCommands::Next { name } => ProviderManager::next_token(&mut config, &name)?,
+        },
+
+        Commands::Rename { old, new, force } => {
+            ProviderManager::rename_provider(&mut config, &old, &new, force)?;
         }
 
-        Commands::Clear => {
-            ProviderManager::clear_provider(&mut config)?;
+        Commands::RenameBulk {
+            from,
+            to,
+            regex,
+            yes,
+        } => ProviderManager::rename_bulk(&mut config, &from, &to, regex, yes)?,
+
+        Commands::Copy {
+            src,
+            dest,
+            api_url,
+            token,
+            force,
+        } => {
+            ProviderManager::copy_provider(&mut config, &src, &dest, api_url, token, force)?;
+        }
+
+        Commands::Diff { a, b, all_env } => {
+            ProviderManager::diff_providers(&config, &a, &b, all_env)?;
+        }
+
+        Commands::Tag { provider, tags } => {
+            ProviderManager::tag_provider(&mut config, &provider, &tags)?;
+        }
+
+        Commands::Lock { name } => ProviderManager::set_locked(&mut config, &name, true)?,
+
+        Commands::Unlock { name } => ProviderManager::set_locked(&mut config, &name, false)?,
+
+        Commands::Untag { provider, tags } => {
+            ProviderManager::untag_provider(&mut config, &provider, &tags)?;
+        }
+
+        Commands::SetModel {
+            provider,
+            model,
+            clear,
+        } => {
+            let model = if clear { None } else { model };
+            ProviderManager::set_model(&mut config, &provider, model)?;
+        }
+
+        Commands::SetProxy {
+            provider,
+            url,
+            only,
+            clear,
+        } => {
+            let url = if clear { None } else { url };
+            ProviderManager::set_proxy(&mut config, &provider, url, only)?;
+        }
+
+        Commands::SetEnv {
+            provider,
+            assignment,
+        } => {
+            ProviderManager::set_extra_env(&mut config, &provider, &assignment)?;
+        }
+
+        Commands::UnsetEnv { provider, key } => {
+            ProviderManager::unset_extra_env(&mut config, &provider, &key)?;
+        }
+
+        Commands::SetHeader { provider, header } => {
+            ProviderManager::set_header(&mut config, &provider, &header.join(" "))?;
+        }
+
+        Commands::UnsetHeader { provider, name } => {
+            ProviderManager::unset_header(&mut config, &provider, &name)?;
+        }
+
+        Commands::Alias {
+            alias,
+            provider,
+            remove,
+        } => match provider {
+            Some(provider) if !remove => {
+                ProviderManager::add_alias(&mut config, &alias, &provider)?;
+            }
+            _ => ProviderManager::remove_alias(&mut config, &alias)?,
+        },
+
+        Commands::AliasCmd { action } => match action {
+            AliasCmdCommands::List => ProviderManager::list_command_aliases(&config),
+            AliasCmdCommands::Add { name, command } => {
+                ProviderManager::add_command_alias(&mut config, &name, command)?
+            }
+            AliasCmdCommands::Remove { name } => {
+                ProviderManager::remove_command_alias(&mut config, &name)?
+            }
+        },
+
+        Commands::Use {
+            from_env: true,
+            eval,
+            shell,
+            ..
+        } => {
+            ProviderManager::use_ephemeral_provider(&config, eval, shell)?;
+        }
+
+        Commands::Use {
+            name,
+            eval,
+            no_save,
+            print,
+            random,
+            round_robin,
+            tag,
+            verify,
+            force,
+            shell,
+            ..
+        } => {
+            let rotation = if random {
+                Some(Rotation::Random)
+            } else if round_robin {
+                Some(Rotation::RoundRobin)
+            } else {
+                None
+            };
+            let name = match (name, rotation) {
+                (_, Some(rotation)) => Some(ProviderManager::pick_provider(
+                    &mut config,
+                    tag.as_deref(),
+                    rotation,
+                )?),
+                (Some(name), None) if name == "-" => {
+                    Some(ProviderManager::previous_provider(&config)?)
+                }
+                (Some(name), None) => Some(name),
+                (None, None) if eval => {
+                    return Err(anyhow!("A provider name is required with --eval"))
+                }
+                (None, None) => ProviderManager::select_provider(&config)?,
+            };
+            let verify = ProviderManager::verify_timeout(&config, verify, eval, timeout);
+            match name {
+                Some(name) if print => ProviderManager::print_provider_env(&config, &name)?,
+                Some(name) => ProviderManager::use_provider(
+                    &mut config,
+                    &name,
+                    eval,
+                    !no_save,
+                    shell,
+                    verify,
+                    force,
+                )?,
+                None => {}
+            }
         }
 
+        Commands::Last { eval, shell } => {
+            let name = ProviderManager::previous_provider(&config)?;
+            let verify = ProviderManager::verify_timeout(&config, false, eval, timeout);
+            ProviderManager::use_provider(&mut config, &name, eval, true, shell, verify, false)?;
+        }
+
+        #[cfg(feature = "tui")]
+        Commands::Tui { eval, shell } => {
+            if tui::run(&mut config, timeout)? {
+                ProviderManager::export_current_provider(&config, eval, shell)?;
+            }
+        }
+
+        Commands::Default {
+            name,
+            clear,
+            eval,
+            shell,
+        } => {
+            if eval {
+                ProviderManager::emit_default_provider(&config, shell)?;
+            } else if clear {
+                ProviderManager::clear_default_provider(&mut config)?;
+            } else if let Some(name) = name {
+                ProviderManager::set_default_provider(&mut config, &name)?;
+            } else {
+                ProviderManager::show_default_provider(&config)?;
+            }
+        }
+
+        Commands::Current { url, json } => {
+            if !ProviderManager::print_current_provider(&config, url, json)? {
+                std::process::exit(1);
+            }
+        }
+
+        Commands::Templates => {
+            ProviderManager::list_templates()?;
+        }
+
+        Commands::Check { json } => {
+            ProviderManager::check_environment(&config, json)?;
+        }
+
+        Commands::History { clear } => {
+            if clear {
+                ProviderManager::clear_history(&mut config)?;
+            } else {
+                ProviderManager::show_history(&config)?;
+            }
+        }
+
+        Commands::Doctor | Commands::Validate | Commands::Schema => {
+            unreachable!("handled before the config is loaded")
+        }
+
+        // `status --quiet` prints nothing at all, only the exit code
+        Commands::Status { format, json, fast } => {
+            let format = if json { OutputFormat::Json } else { format };
+            let quiet = cli.quiet && format == OutputFormat::Pretty;
+            if !ProviderManager::print_status(&config, format, quiet, fast)? {
+                std::process::exit(1);
+            }
+        }
+
+        Commands::Shellenv { shell, hook } => {
+            ProviderManager::output_shellenv(&config, shell, hook)?;
+        }
+
+        Commands::Clear { eval, shell } => {
+            ProviderManager::clear_provider(&mut config, eval, shell)?;
+        }
+
+        Commands::ExportEnv {
+            name,
+            format,
+            shell,
+        } => {
+            ProviderManager::export_env(&config, name.as_deref(), format, shell)?;
+        }
+
+        Commands::Run { name, command } => {
+            let code = ProviderManager::run_with_provider(&config, &name, &command)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+
+        Commands::PrintEnv { name, shell } => {
+            ProviderManager::export_env(&config, name.as_deref(), EnvFormat::Shell, shell)?;
+        }
+
+        Commands::Envfile { name, out, merge } => {
+            ProviderManager::write_env_file(&config, &name, &out, merge)?;
+        }
+
+        Commands::Whoami { name } => {
+            ProviderManager::whoami(&config, name.as_deref(), timeout)?;
+        }
+
+        Commands::CpToken {
+            name,
+            clear_after,
+            force,
+        } => {
+            let clear_after = config.clipboard_clear(clear_after);
+            ProviderManager::copy_token(&config, name.as_deref(), clear_after, force)?;
+        }
+
+        Commands::Open { name } => {
+            ProviderManager::open_console(&config, name.as_deref())?;
+        }
+
+        Commands::Test {
+            name,
+            all,
+            refresh,
+            jobs,
+            format,
+        } => match name {
+            Some(name) if !all => ProviderManager::test_provider(&config, &name, timeout)?,
+            _ => {
+                let format = format.unwrap_or(OutputFormat::Pretty);
+                ProviderManager::test_all_providers(&config, timeout, refresh, jobs, format)?
+            }
+        },
+
+        Commands::Backup { list, keep } => {
+            if list {
+                ProviderManager::list_backups()?;
+            } else {
+                ProviderManager::backup_config(keep)?;
+            }
+        }
+
+        Commands::Restore { file, latest, yes } => {
+            ProviderManager::restore_config(file.as_deref(), latest, yes)?;
+        }
+
+        Commands::Export {
+            format,
+            out,
+            with_tokens,
+            script,
+        } => {
+            if script {
+                ProviderManager::export_script(&config, out.as_deref(), with_tokens)?;
+            } else {
+                ProviderManager::export_config(&config, format, out.as_deref(), with_tokens)?;
+            }
+        }
+
+        Commands::Import {
+            file,
+            strategy,
+            adopt_current,
+            force,
+        } => {
+            ProviderManager::import_config(&mut config, &file, strategy, adopt_current, force)?;
+        }
+
+        Commands::ImportExisting { yes, force } => {
+            ProviderManager::import_existing(&mut config, yes, force)?;
+        }
+
+        Commands::Encrypt => {
+            ProviderManager::encrypt_config(&mut config)?;
+        }
+
+        Commands::Decrypt => {
+            ProviderManager::decrypt_config(&mut config)?;
+        }
+
+        Commands::Completions { shell } => {
+            ProviderManager::output_completions(shell)?;
+        }
+
+        Commands::Profile { action } => match action {
+            ProfileCommands::Create { name } => {
+                ProviderManager::create_profile(&mut config, &name)?
+            }
+            ProfileCommands::Use { name } => ProviderManager::use_profile(&mut config, &name)?,
+            ProfileCommands::List => ProviderManager::list_profiles(&config)?,
+            ProfileCommands::Remove { name } => {
+                ProviderManager::remove_profile(&mut config, &name)?
+            }
+        },
+
+        Commands::Config { action } => match action {
+            ConfigCommands::Path => ProviderManager::print_config_path()?,
+            ConfigCommands::Show => ProviderManager::show_config(&config)?,
+            ConfigCommands::Timeout { secs, reset } => {
+                ProviderManager::set_timeout(&mut config, secs, reset)?
+            }
+            ConfigCommands::EnvScheme { scheme, reset } => {
+                ProviderManager::set_env_scheme(&mut config, scheme, reset)?
+            }
+            ConfigCommands::OnSwitch { command, reset } => {
+                ProviderManager::set_on_switch(&mut config, command, reset)?
+            }
+            ConfigCommands::VerifyOnUse { enabled } => {
+                ProviderManager::set_verify_on_use(&mut config, enabled)?
+            }
+            ConfigCommands::ClipboardClear { secs, reset } => {
+                ProviderManager::set_clipboard_clear(&mut config, secs, reset)?
+            }
+        },
+
+        Commands::Keyring { action } => match action {
+            KeyringCommands::Migrate => {
+                ProviderManager::migrate_to_keyring(&mut config)?;
+            }
+        },
+
         Commands::Install { force } => {
-            ProviderManager::install_shell_integration(force)?;
+            ProviderManager::install_shell_integration(Shell::detect(), force)?;
+        }
+
+        Commands::Init { shell, force } => {
+            ProviderManager::install_shell_integration(shell, force)?;
         }
     }
 
     Ok(())
 }
+
+/// Diagnostics go to stderr so `--eval` output stays clean. `RUST_LOG` is
+/// honored; `-v`/`-vv` raise cce's own level on top of it.
+fn init_logging(verbose: u8) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("off"));
+    match verbose {
+        0 => {}
+        1 => {
+            builder.filter_module("cce", log::LevelFilter::Debug);
+        }
+        _ => {
+            builder.filter_module("cce", log::LevelFilter::Trace);
+        }
+    }
+    builder.format_timestamp(None).init();
+}
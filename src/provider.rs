@@ -1,20 +1,338 @@
-use crate::config::{Config, Provider};
-use anyhow::Result;
+use crate::cli::{self, EnvFormat, ListSort, OutputFormat, ProxyScheme};
+use crate::clipboard;
+use crate::config::{
+    self, interpolate_env, is_env_template, is_secret_header, is_secret_var, Config, ConfigFormat,
+    EnvScheme, Expiry, Profile, Provider, DEFAULT_TOKEN_ENV, DEFAULT_URL_ENV, EPHEMERAL_MODEL_ENV,
+    EPHEMERAL_TOKEN_ENV, EPHEMERAL_URL_ENV,
+};
+use crate::connectivity;
+use crate::crypto;
+use crate::error::CceError;
+use crate::health::{Health, HealthCache, HealthEntry};
+use crate::secret_store::{self, KEYRING_SENTINEL};
+use crate::shell::Shell;
+use crate::table::Table;
+use crate::templates::TEMPLATES;
+use crate::transfer::{ExportFormat, ExportedConfig, ImportStrategy, REDACTED_TOKEN};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use clap_complete::env::Shells;
 use colored::*;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, FuzzySelect};
+use log::{debug, trace};
+use regex::Regex;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::io::{IsTerminal, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use url::Url;
+
+/// Set by `--quiet`; `status!` messages are then dropped.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for decorative confirmations and hints (✅, 🔄, ℹ️, 💡, ...),
+/// which `--quiet` silences. Warnings, errors, and requested output such as
+/// lists, JSON, and `--eval` commands use `println!`/`eprintln!` directly.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !ProviderManager::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `status!` on stderr, for shell mode where stdout is evaluated by the shell.
+macro_rules! status_stderr {
+    ($($arg:tt)*) => {
+        if !ProviderManager::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Variables exported with the provider's model when one is configured.
+const MODEL_ENV_VARS: [&str; 4] = [
+    "ANTHROPIC_MODEL",
+    "ANTHROPIC_DEFAULT_OPUS_MODEL",
+    "ANTHROPIC_DEFAULT_SONNET_MODEL",
+    "ANTHROPIC_DEFAULT_HAIKU_MODEL",
+];
+
+/// Variable holding the provider's extra request headers, one
+/// `Name: Value` per line, as Claude Code reads it.
+const CUSTOM_HEADERS_ENV: &str = "ANTHROPIC_CUSTOM_HEADERS";
+
+/// Variables exported with the provider's HTTP and HTTPS proxies.
+const HTTP_PROXY_ENV_VARS: [&str; 2] = ["HTTP_PROXY", "http_proxy"];
+const HTTPS_PROXY_ENV_VARS: [&str; 2] = ["HTTPS_PROXY", "https_proxy"];
+
+/// Fields to change on an existing provider; `None` leaves a field as is.
+#[derive(Debug, Default)]
+pub struct ProviderEdit {
+    pub api_url: Option<String>,
+    pub token: Option<String>,
+    pub token_env: Option<String>,
+    pub url_env: Option<String>,
+    pub description: Option<String>,
+    pub console_url: Option<String>,
+    pub weight: Option<u32>,
+    /// `YYYY-MM-DD` or RFC 3339; empty removes the expiry
+    pub expires: Option<String>,
+}
+
+impl ProviderEdit {
+    fn is_empty(&self) -> bool {
+        self.api_url.is_none()
+            && self.token.is_none()
+            && self.token_env.is_none()
+            && self.url_env.is_none()
+            && self.description.is_none()
+            && self.console_url.is_none()
+            && self.weight.is_none()
+            && self.expires.is_none()
+    }
+}
+
+/// How `cce use --random` / `--round-robin` picks among several providers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Random,
+    RoundRobin,
+}
+
+/// Name of the provider `cce import-existing` creates.
+const IMPORTED_PROVIDER_NAME: &str = "imported";
+
+/// Shell startup files, relative to the home directory, that
+/// `cce import-existing` looks for exports in.
+const SHELL_RC_FILES: [&str; 5] = [
+    ".zshrc",
+    ".bashrc",
+    ".bash_profile",
+    ".profile",
+    ".config/fish/config.fish",
+];
+
+/// Comments around the block `cce init` and `cce install` add to rc files.
+const RC_BLOCK_START: &str = "# >>> cce shell integration >>>";
+const RC_BLOCK_END: &str = "# <<< cce shell integration <<<";
+/// Comment older releases of `cce install` put above the integration line.
+const LEGACY_RC_COMMENT: &str = "# CCE Shell Integration";
+
+/// Exported by the `shellenv` wrapper so `cce doctor` and `cce use` can tell
+/// it is loaded.
+const SHELLENV_MARKER_ENV: &str = "CCE_SHELLENV_LOADED";
+
+/// Tallies `cce doctor` and `cce validate` results while printing them.
+#[derive(Default)]
+struct Doctor {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Doctor {
+    fn pass(&mut self, label: &str) {
+        println!("  {} {}", "✅".green(), label);
+    }
+
+    fn warn(&mut self, label: &str, hint: &str) {
+        self.warnings += 1;
+        println!("  {} {}", "⚠️".yellow(), label);
+        println!("     {} {}", "💡".yellow(), hint);
+    }
+
+    fn fail(&mut self, label: &str, hint: &str) {
+        self.failures += 1;
+        println!("  {} {}", "❌".red(), label);
+        println!("     {} {}", "💡".yellow(), hint);
+    }
+}
+
+/// Name shown for the transient provider built by `cce use --from-env`.
+const EPHEMERAL_PROVIDER_NAME: &str = "(from environment)";
+
+/// How the live environment compares with the active provider's config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EnvStatus {
+    Match,
+    Mismatch,
+    /// The token or URL variable is missing entirely
+    NotSet,
+}
+
+impl EnvStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            EnvStatus::Match => "match",
+            EnvStatus::Mismatch => "mismatch",
+            EnvStatus::NotSet => "not_set",
+        }
+    }
+}
+
+/// Machine-readable result of `cce check --json`. Holds only booleans and
+/// the non-secret URL, never the token.
+#[derive(Serialize)]
+struct EnvReport<'a> {
+    env: LiveEnv,
+    config: ConfiguredEnv<'a>,
+    matches: bool,
+}
+
+#[derive(Serialize)]
+struct LiveEnv {
+    token_set: bool,
+    url_set: bool,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ConfiguredEnv<'a> {
+    current_provider: Option<&'a str>,
+    configured_url: Option<&'a str>,
+}
+
+/// Machine-readable entry printed by `cce list --json`.
+#[derive(Serialize)]
+struct ProviderSummary<'a> {
+    name: &'a str,
+    api_url: &'a str,
+    is_current: bool,
+    token_masked: String,
+    tags: &'a [String],
+    description: Option<&'a str>,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extends: Option<&'a str>,
+    /// Only with `--health`; absent when the provider could not be checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<Health>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Machine-readable entry printed by `cce test --all --format json`.
+#[derive(Serialize)]
+struct TestSummary<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    entry: &'a HealthEntry,
+    /// Whether the result was reused from the health cache
+    cached: bool,
+}
+
+/// Machine-readable entry printed by `cce search --json`.
+#[derive(Serialize)]
+struct SearchResult<'a> {
+    name: &'a str,
+    api_url: &'a str,
+    token_masked: String,
+    score: u32,
+    matched: Vec<&'static str>,
+}
+
+/// Narrows `cce list` to providers matching all of the given criteria.
+#[derive(Clone, Copy, Default)]
+pub struct ListFilter<'a> {
+    /// Case-insensitive substring of the name or API URL
+    pub text: Option<&'a str>,
+    pub tag: Option<&'a str>,
+}
+
+/// What `cce prune` removes; a provider matching any criterion is pruned.
+#[derive(Clone, Copy, Default)]
+pub struct PruneCriteria {
+    /// Check connectivity with this timeout and prune providers that are
+    /// unreachable or reject their token
+    pub dead: Option<Duration>,
+    /// Prune providers past their `expires_at`
+    pub expired: bool,
+    /// Prune providers not used within this many days, or never
+    pub unused_days: Option<u64>,
+}
+
+impl ListFilter<'_> {
+    fn matches(&self, name: &str, provider: &Provider) -> bool {
+        let text_matches = self.text.map(str::to_lowercase).is_none_or(|text| {
+            name.to_lowercase().contains(&text) || provider.api_url.to_lowercase().contains(&text)
+        });
+        let tag_matches = self
+            .tag
+            .is_none_or(|tag| provider.tags.contains(&tag.to_lowercase()));
+        text_matches && tag_matches
+    }
+
+    fn describe(&self) -> String {
+        match (self.text, self.tag) {
+            (Some(text), Some(tag)) => format!("'{}' with tag '{}'", text, tag),
+            (Some(text), None) => format!("'{}'", text),
+            (None, Some(tag)) => format!("tag '{}'", tag),
+            (None, None) => String::new(),
+        }
+    }
+}
 
 pub struct ProviderManager;
 
 impl ProviderManager {
-    pub fn list_providers(config: &Config) -> Result<()> {
+    pub fn set_quiet(enabled: bool) {
+        QUIET.store(enabled, Ordering::Relaxed);
+    }
+
+    fn is_quiet() -> bool {
+        QUIET.load(Ordering::Relaxed)
+    }
+
+    pub fn list_providers(
+        config: &Config,
+        sort: ListSort,
+        filter: ListFilter,
+        format: OutputFormat,
+        health: Option<Duration>,
+    ) -> Result<()> {
+        let providers = Self::sorted_providers(config, sort, filter);
+        let health = match health {
+            Some(timeout) => Some(Self::list_health(config, &providers, timeout)?),
+            None => None,
+        };
+        match format {
+            OutputFormat::Json => {
+                return Self::list_providers_json(config, providers, health.as_ref())
+            }
+            OutputFormat::Table => {
+                Self::list_providers_table(config, &providers, health.as_ref());
+                return Ok(());
+            }
+            OutputFormat::Pretty => {}
+        }
+
         if config.providers.is_empty() {
             println!("{}", "No service providers configured".yellow());
             return Ok(());
         }
 
+        if providers.is_empty() {
+            // Only reachable through a filter; the config has providers
+            println!(
+                "{}",
+                format!("No service providers match {}", filter.describe()).yellow()
+            );
+            return Ok(());
+        }
+
         println!("{}", "Configured service providers:".blue().bold());
+        if !config.profiles.is_empty() {
+            println!("  Profile: {}", config.active_profile().cyan());
+        }
         println!();
 
-        for (name, provider) in &config.providers {
+        for (name, provider) in providers {
             let is_current = config.current_provider.as_ref() == Some(name);
 
             let marker = if is_current {
@@ -28,15 +346,76 @@ impl ProviderManager {
                 name.white()
             };
 
-            println!("  {} {}", marker, name_color);
+            let indicator = match &health {
+                Some(health) => format!(" {}", Self::health_indicator(health.get(name.as_str()))),
+                None => String::new(),
+            };
+            if provider.locked {
+                println!("  {} {} 🔒{}", marker, name_color, indicator);
+            } else {
+                println!("  {} {}{}", marker, name_color, indicator);
+            }
+            if let Some(ref description) = provider.description {
+                println!("    {}", description.dimmed());
+            }
+            if let Some(ref parent) = provider.extends {
+                let inherited = provider.inherited_fields();
+                if inherited.is_empty() {
+                    println!("    Extends: {}", parent.cyan());
+                } else {
+                    println!(
+                        "    Extends: {} {}",
+                        parent.cyan(),
+                        format!("(inherits {})", inherited.join(", ")).dimmed()
+                    );
+                }
+            }
             println!("    API URL: {}", provider.api_url.cyan());
-            println!(
-                "    Token: {}****",
-                &provider.token[..provider.token.len().min(8)].dimmed()
-            );
+            if provider.tokens.len() > 1 {
+                println!(
+                    "    Token: {} ({} of {})",
+                    Self::masked_token(config, provider).dimmed(),
+                    provider.active_token + 1,
+                    provider.tokens.len()
+                );
+            } else {
+                println!(
+                    "    Token: {}",
+                    Self::masked_token(config, provider).dimmed()
+                );
+            }
             if let Some(ref model) = provider.model {
                 println!("    Model: {}", model.cyan());
             }
+            if let Some(ref proxy) = provider.https_proxy {
+                println!("    HTTPS proxy: {}", proxy.cyan());
+            }
+            if let Some(ref proxy) = provider.http_proxy {
+                println!("    HTTP proxy: {}", proxy.cyan());
+            }
+            if !provider.headers.is_empty() {
+                let mut headers: Vec<&str> = provider.headers.keys().map(String::as_str).collect();
+                headers.sort();
+                println!("    Headers: {}", headers.join(", ").cyan());
+            }
+            println!(
+                "    Last used: {}",
+                Self::format_last_used(provider.last_used).dimmed()
+            );
+            if let Some(expiry) = Self::format_expiry(provider) {
+                println!("    Expires: {}", expiry);
+            }
+            if !provider.tags.is_empty() {
+                println!("    Tags: {}", provider.tags.join(", ").cyan());
+            }
+            if let Some(weight) = provider.weight {
+                println!("    Weight: {}", weight.to_string().cyan());
+            }
+            let aliases = config.aliases_for(name);
+            if !aliases.is_empty() {
+                let aliases: Vec<&str> = aliases.iter().map(|alias| alias.as_str()).collect();
+                println!("    Aliases: {}", aliases.join(", ").cyan());
+            }
 
             if is_current {
                 println!("    {}", "(currently active)".green().italic());
@@ -44,206 +423,5677 @@ impl ProviderManager {
             println!();
         }
 
-        Ok(())
-    }
-
-    pub fn add_provider(
-        config: &mut Config,
-        name: String,
-        api_url: String,
-        token: String,
-        model: Option<String>,
-    ) -> Result<()> {
-        if config.providers.contains_key(&name) {
+        for (api_url, names) in Self::duplicate_urls(config) {
             println!(
-                "{} Service provider '{}' already exists, overwriting",
+                "{} {} share the API URL {}",
                 "⚠️".yellow(),
-                name.yellow()
+                Self::quoted_list(&names).yellow(),
+                api_url.cyan()
             );
         }
 
-        config.add_provider(name.clone(), api_url, token, model);
-        config.save()?;
-
-        println!(
-            "{} Successfully added service provider '{}'",
-            "✅".green(),
-            name.green().bold()
-        );
         Ok(())
     }
 
-    pub fn remove_provider(config: &mut Config, name: &str) -> Result<()> {
-        if !config.providers.contains_key(name) {
+    /// Groups of providers (sorted by name) whose API URLs only differ in a
+    /// trailing slash or the case of the scheme and host.
+    fn duplicate_urls(config: &Config) -> Vec<(String, Vec<&str>)> {
+        let mut by_url: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (name, provider) in &config.providers {
+            by_url
+                .entry(Self::comparable_url(&provider.api_url))
+                .or_default()
+                .push(name);
+        }
+
+        by_url
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .map(|(api_url, mut names)| {
+                names.sort_unstable();
+                (api_url, names)
+            })
+            .collect()
+    }
+
+    fn comparable_url(api_url: &str) -> String {
+        let api_url = &interpolate_env(api_url).unwrap_or_else(|_| api_url.to_string());
+        // Parsing lowercases the scheme and host but keeps the path as is
+        match Url::parse(api_url) {
+            Ok(url) => url.as_str().trim_end_matches('/').to_string(),
+            Err(_) => api_url.trim_end_matches('/').to_lowercase(),
+        }
+    }
+
+    fn quoted_list(names: &[&str]) -> String {
+        names
+            .iter()
+            .map(|name| format!("'{}'", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Lists providers whose name, API URL, tags, or description contain
+    /// `query`, best matches first: name matches rank above tags, the URL,
+    /// and the description, and exact or prefix name matches rank highest.
+    pub fn search_providers(config: &Config, query: &str, json: bool) -> Result<()> {
+        let needle = query.to_lowercase();
+        let mut results = Vec::new();
+
+        for (name, provider) in &config.providers {
+            let mut fields: Vec<(&'static str, String)> = vec![
+                ("name", name.clone()),
+                ("api_url", provider.api_url.clone()),
+            ];
+            fields.extend(provider.tags.iter().map(|tag| ("tag", tag.clone())));
+            if let Some(description) = &provider.description {
+                fields.push(("description", description.clone()));
+            }
+            fields.retain(|(_, text)| text.to_lowercase().contains(&needle));
+
+            let score = fields
+                .iter()
+                .map(|(field, text)| match *field {
+                    "name" if text.to_lowercase() == needle => 100,
+                    "name" if text.to_lowercase().starts_with(&needle) => 80,
+                    "name" => 60,
+                    "tag" => 50,
+                    "api_url" => 40,
+                    _ => 30,
+                })
+                .max();
+            if let Some(score) = score {
+                results.push((score, fields, name, provider));
+            }
+        }
+        results.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.2.cmp(b.2)));
+
+        if json {
+            let results: Vec<SearchResult> = results
+                .iter()
+                .map(|(score, fields, name, provider)| SearchResult {
+                    name,
+                    api_url: &provider.api_url,
+                    token_masked: Self::masked_token(config, provider),
+                    score: *score,
+                    matched: fields.iter().map(|(field, _)| *field).collect(),
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&results)?);
+            return Ok(());
+        }
+
+        if results.is_empty() {
             println!(
-                "{} Service provider '{}' does not exist",
-                "❌".red(),
-                name.red()
+                "{}",
+                format!("No service providers match '{}'", query).yellow()
             );
             return Ok(());
         }
 
-        config.remove_provider(name);
-        config.save()?;
-
         println!(
-            "{} Successfully removed service provider '{}'",
-            "🗑️".green(),
-            name.green().bold()
+            "{} {} service provider(s) match '{}':",
+            "🔍".blue(),
+            results.len().to_string().bold(),
+            query
         );
+        println!();
+        for (_, fields, name, provider) in &results {
+            let is_current = config.current_provider.as_ref() == Some(*name);
+            let marker = if is_current {
+                "●".green()
+            } else {
+                "○".white()
+            };
+            println!("  {} {}", marker, Self::highlight(name, &needle));
+            if !fields.iter().any(|(field, _)| *field == "api_url") {
+                println!("    API URL: {}", provider.api_url.cyan());
+            }
+            for (field, text) in fields {
+                match *field {
+                    "api_url" => println!("    API URL: {}", Self::highlight(text, &needle)),
+                    "tag" => println!("    Tag: {}", Self::highlight(text, &needle)),
+                    "description" => {
+                        println!("    Description: {}", Self::highlight(text, &needle))
+                    }
+                    _ => {}
+                }
+            }
+            println!(
+                "    Token: {}",
+                Self::masked_token(config, provider).dimmed()
+            );
+            println!();
+        }
         Ok(())
     }
 
-    pub fn use_provider(config: &mut Config, name: &str) -> Result<()> {
-        if !config.providers.contains_key(name) {
-            println!(
-                "{} Service provider '{}' does not exist",
-                "❌".red(),
-                name.red()
+    /// Marks every occurrence of the lowercase `needle` in `text`. Text whose
+    /// lowercase form has a different length is left unmarked, since byte
+    /// offsets would not line up.
+    fn highlight(text: &str, needle: &str) -> String {
+        let lower = text.to_lowercase();
+        if needle.is_empty() || lower.len() != text.len() {
+            return text.to_string();
+        }
+
+        let mut marked = String::with_capacity(text.len());
+        let mut rest = 0;
+        for (start, _) in lower.match_indices(needle) {
+            if start < rest {
+                continue;
+            }
+            marked.push_str(&text[rest..start]);
+            marked.push_str(
+                &text[start..start + needle.len()]
+                    .yellow()
+                    .bold()
+                    .to_string(),
             );
-            return Ok(());
+            rest = start + needle.len();
         }
+        marked.push_str(&text[rest..]);
+        marked
+    }
 
-        let shell_mode = Self::shell_integration_active();
+    fn list_providers_json(
+        config: &Config,
+        providers: Vec<(&String, &Provider)>,
+        health: Option<&HashMap<String, HealthEntry>>,
+    ) -> Result<()> {
+        let summaries: Vec<ProviderSummary> = providers
+            .into_iter()
+            .map(|(name, provider)| ProviderSummary {
+                name,
+                api_url: &provider.api_url,
+                is_current: config.current_provider.as_ref() == Some(name),
+                token_masked: Self::masked_token(config, provider),
+                tags: &provider.tags,
+                description: provider.description.as_deref(),
+                locked: provider.locked,
+                extends: provider.extends.as_deref(),
+                health: health
+                    .and_then(|health| health.get(name.as_str()))
+                    .map(|entry| entry.health),
+                expires_at: provider.expires_at,
+            })
+            .collect();
 
-        if let Some(current) = &config.current_provider {
-            if current == name && !shell_mode {
-                println!(
-                    "{} Already using service provider '{}'",
-                    "ℹ️".blue(),
-                    name.blue().bold()
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+        Ok(())
+    }
+
+    fn list_providers_table(
+        config: &Config,
+        providers: &[(&String, &Provider)],
+        health: Option<&HashMap<String, HealthEntry>>,
+    ) {
+        let mut headers = vec!["name", "active", "url"];
+        if health.is_some() {
+            headers.push("health");
+        }
+        headers.push("last used");
+
+        let mut table = Table::new(headers);
+        for (name, provider) in providers {
+            let is_current = config.current_provider.as_ref() == Some(*name);
+            let mut row = vec![
+                name.to_string(),
+                if is_current { "yes" } else { "" }.to_string(),
+                provider.api_url.clone(),
+            ];
+            if let Some(health) = health {
+                let entry = health.get(name.as_str());
+                row.push(
+                    entry
+                        .map_or("unknown", |entry| entry.health.as_str())
+                        .to_string(),
                 );
-                return Ok(());
             }
+            row.push(Self::format_last_used(provider.last_used));
+            table.push(row);
         }
+        table.print();
+    }
 
-        let provider = config.providers.get(name).unwrap().clone();
+    /// Health of the listed providers, re-checking only those whose cached
+    /// result is older than the TTL. Providers that cannot be checked, e.g.
+    /// because their token cannot be read, are left out and shown as unknown.
+    fn list_health(
+        config: &Config,
+        providers: &[(&String, &Provider)],
+        timeout: Duration,
+    ) -> Result<HashMap<String, HealthEntry>> {
+        let mut cache = HealthCache::load();
+        let mut health = HashMap::new();
 
-        // Set environment variables
-        config.set_current_provider(name);
-        config.save()?;
+        let names: Vec<&String> = providers.iter().map(|(name, _)| *name).collect();
+        let results = Self::check_health(
+            config,
+            &mut cache,
+            &names,
+            timeout,
+            false,
+            connectivity::DEFAULT_JOBS,
+        );
+        for (name, result) in names.into_iter().zip(results) {
+            match result {
+                Ok((entry, _)) => {
+                    health.insert(name.to_string(), entry);
+                }
+                Err(e) => debug!("Could not check '{}': {:#}", name, e),
+            }
+        }
+
+        cache.prune(config);
+        if let Err(e) = cache.save() {
+            eprintln!("{} {:#}", "⚠️".yellow(), e);
+        }
+        Ok(health)
+    }
 
-        Self::apply_environment_variables(&provider)?;
+    /// The health of each named provider, in order: from the cache when its
+    /// entry is still fresh and `refresh` is not set, otherwise from a new
+    /// probe that is recorded in the cache. Tokens are read one by one, since
+    /// that may prompt, and then up to `jobs` providers are probed at once.
+    /// The flag tells whether the cached result was used.
+    fn check_health(
+        config: &Config,
+        cache: &mut HealthCache,
+        names: &[&String],
+        timeout: Duration,
+        refresh: bool,
+        jobs: usize,
+    ) -> Vec<Result<(HealthEntry, bool)>> {
+        let mut results: Vec<Option<Result<(HealthEntry, bool)>>> = Vec::new();
+        let mut stale = Vec::new();
 
-        if shell_mode {
-            Self::emit_export_commands(&provider);
+        for (index, name) in names.iter().enumerate() {
+            let provider = &config.providers[*name];
+            if let Some(entry) = cache.fresh(name, &provider.api_url).filter(|_| !refresh) {
+                debug!("Using cached health of '{}'", name);
+                results.push(Some(Ok((entry.clone(), true))));
+                continue;
+            }
+
+            match config.resolved_provider(provider) {
+                Ok(resolved) => {
+                    stale.push((
+                        index,
+                        connectivity::ProbeTarget {
+                            api_url: resolved.api_url,
+                            token: resolved.token,
+                            proxy: provider.proxy_for_api().map(String::from),
+                            headers: provider.headers.clone(),
+                        },
+                    ));
+                    results.push(None);
+                }
+                Err(e) => results.push(Some(Err(e))),
+            }
+        }
+
+        let (indices, targets): (Vec<usize>, Vec<connectivity::ProbeTarget>) =
+            stale.into_iter().unzip();
+        let probed = connectivity::probe_all(&targets, timeout, jobs);
+        for (index, result) in indices.into_iter().zip(probed) {
+            let name = names[index];
+            let api_url = &config.providers[name].api_url;
+            results[index] =
+                Some(result.map(|result| (cache.record(name, api_url, &result).clone(), false)));
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every provider is checked"))
+            .collect()
+    }
+
+    fn health_indicator(entry: Option<&HealthEntry>) -> &'static str {
+        match entry.map(|entry| entry.health) {
+            Some(Health::Ok) => "🟢",
+            Some(_) => "🔴",
+            None => "⚪",
+        }
+    }
+
+    /// Providers matching `filter`, in `sort` order with ties broken by name.
+    fn sorted_providers<'a>(
+        config: &'a Config,
+        sort: ListSort,
+        filter: ListFilter,
+    ) -> Vec<(&'a String, &'a Provider)> {
+        let mut providers: Vec<(&String, &Provider)> = config
+            .providers
+            .iter()
+            .filter(|(name, provider)| filter.matches(name, provider))
+            .collect();
+
+        providers.sort_by(|(a_name, a), (b_name, b)| {
+            match sort {
+                ListSort::Name => std::cmp::Ordering::Equal,
+                // Never-used providers sort last
+                ListSort::Recent => b.last_used.cmp(&a.last_used),
+                ListSort::Url => a.api_url.cmp(&b.api_url),
+            }
+            .then_with(|| a_name.cmp(b_name))
+        });
+        providers
+    }
+
+    /// Short, non-secret preview of a stored token for display.
+    pub(crate) fn masked_token(config: &Config, provider: &Provider) -> String {
+        if secret_store::is_keyring_token(&provider.token) {
+            "(keyring)".to_string()
+        } else if config.encrypted {
+            "(encrypted)".to_string()
+        } else if is_env_template(&provider.token) {
+            // A reference holds no secret, and shows where the token comes from
+            provider.token.clone()
         } else {
-            println!(
-                "{} Switched to service provider '{}'",
-                "🔄".green(),
-                name.green().bold()
-            );
-            println!("  API URL: {}", provider.api_url.cyan());
+            Self::mask_secret(&provider.token)
+        }
+    }
+
+    /// Resolves a token given to `add`/`edit`: from a file, from stdin for
+    /// `-`, or by prompting without echo when none is given. A literal token
+    /// still works but leaks into shell history and the process list.
+    pub fn read_token(token: Option<String>, token_file: Option<&Path>) -> Result<String> {
+        if let Some(path) = token_file {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read token file: {:?}", path))?;
+            return Ok(content.trim().to_string());
+        }
+
+        match token {
+            Some(token) if token == "-" => {
+                let mut content = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut content)
+                    .with_context(|| "Failed to read token from stdin")?;
+                Ok(content.trim().to_string())
+            }
+            Some(token) => {
+                eprintln!(
+                    "{} Passing the token as an argument exposes it in shell history and the process list; omit it to be prompted, or use {}",
+                    "⚠️".yellow(),
+                    "--token-file".yellow()
+                );
+                Ok(token)
+            }
+            None => rpassword::prompt_password("🔑 API token: ")
+                .with_context(|| "Failed to read API token"),
         }
+    }
+
+    /// Rejects empty or whitespace-only tokens, which would otherwise be
+    /// exported as an empty variable.
+    pub(crate) fn check_token(token: &str) -> Result<()> {
+        if token.trim().is_empty() {
+            return Err(anyhow!("API token cannot be empty"));
+        }
+        Ok(())
+    }
+
+    /// Strips whitespace dragged in by copy-pasting, which would otherwise
+    /// make a token fail authentication for no visible reason.
+    fn trim_pasted(value: String, label: &str) -> String {
+        let trimmed = value.trim();
+        if trimmed.len() == value.len() {
+            return value;
+        }
+
+        println!(
+            "{} {}",
+            "⚠️".yellow(),
+            format!("Removed whitespace around the {}", label).yellow()
+        );
+        trimmed.to_string()
+    }
+
+    /// Shows at most the first 3 and last 2 characters of a secret, and
+    /// nothing at all for secrets too short for that to be a small fraction.
+    fn mask_secret(secret: &str) -> String {
+        const MIN_REVEAL_LEN: usize = 16;
+
+        let chars: Vec<char> = secret.chars().collect();
+        if chars.is_empty() {
+            return "(empty)".to_string();
+        }
+        if chars.len() < MIN_REVEAL_LEN {
+            return "****".to_string();
+        }
+
+        let head: String = chars[..3].iter().collect();
+        let tail: String = chars[chars.len() - 2..].iter().collect();
+        format!("{}****{}", head, tail)
+    }
+
+    pub(crate) fn format_last_used(last_used: Option<DateTime<Utc>>) -> String {
+        let Some(last_used) = last_used else {
+            return "never".to_string();
+        };
+
+        let elapsed = Utc::now().signed_duration_since(last_used);
+        let plural = |count: i64, unit: &str| {
+            if count == 1 {
+                format!("1 {} ago", unit)
+            } else {
+                format!("{} {}s ago", count, unit)
+            }
+        };
+
+        if elapsed.num_minutes() < 1 {
+            "just now".to_string()
+        } else if elapsed.num_hours() < 1 {
+            plural(elapsed.num_minutes(), "minute")
+        } else if elapsed.num_days() < 1 {
+            plural(elapsed.num_hours(), "hour")
+        } else if elapsed.num_days() < 30 {
+            plural(elapsed.num_days(), "day")
+        } else if elapsed.num_days() < 365 {
+            plural(elapsed.num_days() / 30, "month")
+        } else {
+            plural(elapsed.num_days() / 365, "year")
+        }
+    }
+
+    /// The expiry as a local date and how far off it is.
+    fn expiry_text(provider: &Provider) -> Option<String> {
+        let expires_at = provider.expires_at?;
+        let date = expires_at.with_timezone(&Local).format("%Y-%m-%d");
+        let days = (expires_at - Utc::now()).num_days();
+        let plural = |count: i64| {
+            if count == 1 {
+                "1 day".to_string()
+            } else {
+                format!("{} days", count)
+            }
+        };
+        Some(match days {
+            0 => format!("{} (today)", date),
+            days if days < 0 => format!("{} ({} ago)", date, plural(-days)),
+            days => format!("{} (in {})", date, plural(days)),
+        })
+    }
 
+    /// `expiry_text`, red once expired and yellow within `EXPIRY_WARNING_DAYS`.
+    fn format_expiry(provider: &Provider) -> Option<ColoredString> {
+        let text = Self::expiry_text(provider)?;
+        Some(match provider.expiry() {
+            Some(Expiry::Expired) => text.red().bold(),
+            Some(Expiry::Soon) => text.yellow(),
+            None => text.dimmed(),
+        })
+    }
+
+    /// Accepts RFC 3339 timestamps and plain `YYYY-MM-DD` dates, which mean
+    /// the start of that day in local time.
+    fn parse_expiry(value: &str) -> Result<DateTime<Utc>> {
+        let value = value.trim();
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok(timestamp.with_timezone(&Utc));
+        }
+        chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| {
+                date.and_hms_opt(0, 0, 0)?
+                    .and_local_timezone(Local)
+                    .earliest()
+            })
+            .map(|start| start.with_timezone(&Utc))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid expiry '{}': use a date such as 2025-12-31 or an RFC 3339 timestamp",
+                    value
+                )
+            })
+    }
+
+    /// Adds or replaces a provider. Replacing one shows the URL and masked
+    /// token before and after, and asks first unless `yes` or `force` is given.
+    pub fn add_provider(
+        config: &mut Config,
+        mut provider: Provider,
+        validate: bool,
+        yes: bool,
+        force: bool,
+    ) -> Result<()> {
+        let name = provider.name.clone();
+        Self::ensure_unlocked(config, &name, force)?;
+        Self::check_token(&provider.token)?;
+        provider.token = Self::trim_pasted(provider.token, "token");
+        provider.api_url =
+            Self::normalize_api_url(&Self::trim_pasted(provider.api_url, "API URL"), validate)?;
+        provider.console_url = provider
+            .console_url
+            .map(|url| Self::normalize_api_url(&url, validate))
+            .transpose()?;
+
+        if config.aliases.contains_key(&name) {
+            return Err(anyhow!(
+                "'{}' is already an alias; remove it with 'cce alias --remove {}' first",
+                name,
+                name
+            ));
+        }
+
+        if let Some(existing) = config.providers.get(&name).filter(|_| !force) {
+            println!(
+                "{} Service provider '{}' already exists",
+                "⚠️".yellow(),
+                name.yellow()
+            );
+            println!(
+                "  API URL: {} → {}",
+                existing.api_url.red(),
+                provider.api_url.green()
+            );
+            println!(
+                "  Token:   {} → {}",
+                Self::masked_token(config, existing).red(),
+                Self::mask_secret(&provider.token).green()
+            );
+
+            if !yes {
+                if !std::io::stdin().is_terminal() {
+                    return Err(anyhow!(
+                        "Refusing to overwrite '{}' without confirmation; pass --yes or --force",
+                        name
+                    ));
+                }
+
+                let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Overwrite service provider '{}'?", name))
+                    .default(false)
+                    .interact()
+                    .with_context(|| "Failed to read confirmation")?;
+                if !confirmed {
+                    status!("{} Nothing was changed", "ℹ️".blue());
+                    return Ok(());
+                }
+            }
+        }
+
+        provider.token = Self::store_token(config, &name, provider.token)?;
+        config.add_provider(provider);
+        config.save()?;
+
+        status!(
+            "{} Successfully added service provider '{}'",
+            "✅".green(),
+            name.green().bold()
+        );
+
+        // Sometimes intentional (several tokens for one gateway), so only warn
+        if let Some((_, names)) = Self::duplicate_urls(config)
+            .into_iter()
+            .find(|(_, names)| names.contains(&name.as_str()))
+        {
+            let others: Vec<&str> = names.into_iter().filter(|other| *other != name).collect();
+            println!(
+                "{} {} already uses this API URL",
+                "⚠️".yellow(),
+                Self::quoted_list(&others).yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Makes the provider `name` exist with the given URL, token, and (when
+    /// given) model: creates it if missing and updates only the fields that
+    /// differ otherwise. Never prompts, so provisioning scripts can run it
+    /// repeatedly; other settings of an existing provider are kept.
+    pub fn ensure_provider(
+        config: &mut Config,
+        name: &str,
+        api_url: String,
+        token: String,
+        model: Option<String>,
+        validate: bool,
+    ) -> Result<()> {
+        Self::check_token(&token)?;
+        let token = Self::trim_pasted(token, "token");
+        let api_url = Self::normalize_api_url(&Self::trim_pasted(api_url, "API URL"), validate)?;
+
+        if config.aliases.contains_key(name) {
+            return Err(anyhow!(
+                "'{}' is already an alias; remove it with 'cce alias --remove {}' first",
+                name,
+                name
+            ));
+        }
+
+        let Some(existing) = config.providers.get(name) else {
+            let mut provider = Provider::new(name.to_string(), api_url, String::new());
+            provider.model = model;
+            provider.token = Self::store_token(config, name, token)?;
+            config.add_provider(provider);
+            config.save()?;
+
+            status!(
+                "{} Created service provider '{}'",
+                "✅".green(),
+                name.green().bold()
+            );
+            return Ok(());
+        };
+
+        let url_changed = existing.api_url != api_url;
+        let token_changed = config.provider_token(existing)? != token;
+        let model = model.filter(|model| existing.model.as_ref() != Some(model));
+
+        let mut changed = Vec::new();
+        if url_changed {
+            changed.push("API URL");
+        }
+        if token_changed {
+            changed.push("token");
+        }
+        if model.is_some() {
+            changed.push("model");
+        }
+        if changed.is_empty() {
+            status!(
+                "{} Service provider '{}' is already up to date",
+                "✓".green(),
+                name.green().bold()
+            );
+            return Ok(());
+        }
+        Self::ensure_unlocked(config, name, false)?;
+
+        // Keep the token pool's active entry in step with the new token
+        let pooled = Some(token.clone())
+            .filter(|_| token_changed && !existing.tokens.is_empty())
+            .map(|token| config.seal_token(token))
+            .transpose()?;
+        let token = Some(token)
+            .filter(|_| token_changed)
+            .map(|token| Self::store_token(config, name, token))
+            .transpose()?;
+        let provider = config.providers.get_mut(name).unwrap();
+
+        if let Some(pooled) = pooled {
+            let active = provider.active_token;
+            provider.tokens[active] = pooled;
+        }
+        if url_changed {
+            provider.api_url = api_url;
+        }
+        if let Some(token) = token {
+            provider.token = token;
+        }
+        if model.is_some() {
+            provider.model = model;
+        }
+        config.save()?;
+
+        status!(
+            "{} Updated service provider '{}' ({})",
+            "✏️".green(),
+            name.green().bold(),
+            changed.join(", ")
+        );
+        Ok(())
+    }
+
+    /// Removes a single provider. Removing the current or the last provider
+    /// asks for confirmation first unless `yes` is set.
+    pub fn remove_provider(config: &mut Config, name: &str, yes: bool, force: bool) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        if !config.providers.contains_key(name) {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        }
+        Self::ensure_unlocked(config, name, force)?;
+
+        let reason = if yes {
+            None
+        } else if config.current_provider.as_ref() == Some(name) {
+            Some("This is your currently active provider")
+        } else if config.providers.len() == 1 {
+            Some("This is your last provider")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            println!("{} {}", "⚠️".yellow(), reason.yellow());
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to remove '{}' without confirmation; pass --yes",
+                    name
+                ));
+            }
+
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Remove service provider '{}'?", name))
+                .default(false)
+                .interact()
+                .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                status!("{} Nothing was removed", "ℹ️".blue());
+                return Ok(());
+            }
+        }
+
+        Self::forget_provider(config, name);
+        config.save()?;
+
+        status!(
+            "{} Successfully removed service provider '{}'",
+            "🗑️".green(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Removes every provider whose name matches a `*` glob pattern, after
+    /// listing them and asking for confirmation unless `yes` is set.
+    pub fn remove_matching(
+        config: &mut Config,
+        pattern: &str,
+        yes: bool,
+        force: bool,
+    ) -> Result<()> {
+        let names: Vec<String> = config
+            .providers
+            .keys()
+            .filter(|name| Self::glob_match(pattern, name))
+            .cloned()
+            .collect();
+        Self::remove_selected(config, names, &format!("'{}'", pattern), yes, force)
+    }
+
+    /// Removes every provider tagged `tag`, with the same confirmation as
+    /// `remove_matching`.
+    pub fn remove_tagged(config: &mut Config, tag: &str, yes: bool, force: bool) -> Result<()> {
+        let tag = tag.to_lowercase();
+        let names: Vec<String> = config
+            .providers
+            .iter()
+            .filter(|(_, provider)| provider.tags.contains(&tag))
+            .map(|(name, _)| name.clone())
+            .collect();
+        Self::remove_selected(config, names, &format!("tag '{}'", tag), yes, force)
+    }
+
+    fn remove_selected(
+        config: &mut Config,
+        mut names: Vec<String>,
+        selector: &str,
+        yes: bool,
+        force: bool,
+    ) -> Result<()> {
+        names.sort();
+
+        if names.is_empty() {
+            return Err(anyhow!("No service providers match {}", selector));
+        }
+
+        let locked: Vec<&str> = names
+            .iter()
+            .filter(|name| config.providers[*name].locked)
+            .map(String::as_str)
+            .collect();
+        if !locked.is_empty() && !force {
+            return Err(anyhow!(
+                "{} matches locked provider(s) {}; use --force to remove them too",
+                selector,
+                Self::quoted_list(&locked)
+            ));
+        }
+
+        println!(
+            "{} {} service provider(s) match {}:",
+            "🔍".blue(),
+            names.len().to_string().bold(),
+            selector
+        );
+        for name in &names {
+            println!("  - {}", name.yellow());
+        }
+
+        if !yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to remove providers without confirmation; pass --yes"
+                ));
+            }
+
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Remove these service providers?")
+                .default(false)
+                .interact()
+                .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                status!("{} Nothing was removed", "ℹ️".blue());
+                return Ok(());
+            }
+        }
+
+        for name in &names {
+            Self::forget_provider(config, name);
+        }
+        config.save()?;
+
+        status!(
+            "{} Removed {} service provider(s)",
+            "🗑️".green(),
+            names.len().to_string().green().bold()
+        );
+        Ok(())
+    }
+
+    /// Removes the providers matching `criteria` after listing them with the
+    /// reasons and asking for confirmation unless `yes` is set. Locked
+    /// providers are kept, and so is the current one unless a second prompt
+    /// confirms it, which `yes` does not answer.
+    pub fn prune_providers(config: &mut Config, criteria: PruneCriteria, yes: bool) -> Result<()> {
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+
+        let mut reasons: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        if let Some(timeout) = criteria.dead {
+            let mut cache = HealthCache::load();
+            let results = Self::check_health(
+                config,
+                &mut cache,
+                &names,
+                timeout,
+                false,
+                connectivity::DEFAULT_JOBS,
+            );
+            for (name, result) in names.iter().zip(results) {
+                let reason = match result {
+                    Ok((entry, _)) => match entry.health {
+                        Health::Unreachable => format!(
+                            "unreachable: {}",
+                            entry.reason.as_deref().unwrap_or("no response")
+                        ),
+                        Health::AuthFailed => {
+                            format!("token rejected (HTTP {})", entry.code.unwrap_or_default())
+                        }
+                        Health::Ok | Health::Unexpected => continue,
+                    },
+                    Err(e) => {
+                        println!("{} Could not check '{}': {:#}", "⚠️".yellow(), name, e);
+                        continue;
+                    }
+                };
+                reasons.entry(name.to_string()).or_default().push(reason);
+            }
+            if let Err(e) = cache.save() {
+                eprintln!("{} {:#}", "⚠️".yellow(), e);
+            }
+        }
+
+        for name in &names {
+            let provider = &config.providers[*name];
+            if criteria.expired && provider.expiry() == Some(Expiry::Expired) {
+                let text = Self::expiry_text(provider).unwrap_or_default();
+                reasons
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(format!("expired on {}", text));
+            }
+            if let Some(days) = criteria.unused_days {
+                let reason = match provider.last_used {
+                    None => "never used".to_string(),
+                    Some(last_used) if (Utc::now() - last_used).num_days() >= days as i64 => {
+                        format!("last used {}", Self::format_last_used(Some(last_used)))
+                    }
+                    Some(_) => continue,
+                };
+                reasons.entry(name.to_string()).or_default().push(reason);
+            }
+        }
+
+        let locked: Vec<String> = reasons
+            .keys()
+            .filter(|name| config.providers[*name].locked)
+            .cloned()
+            .collect();
+        for name in &locked {
+            reasons.remove(name);
+        }
+
+        if !locked.is_empty() {
+            let locked: Vec<&str> = locked.iter().map(String::as_str).collect();
+            println!(
+                "{} Keeping locked provider(s) {}; unlock them to prune them",
+                "🔒".blue(),
+                Self::quoted_list(&locked)
+            );
+        }
+        if reasons.is_empty() {
+            status!("{} Nothing to prune", "✨".green());
+            return Ok(());
+        }
+
+        println!(
+            "{} {} service provider(s) to prune:",
+            "🔍".blue(),
+            reasons.len().to_string().bold(),
+        );
+        for (name, reasons) in &reasons {
+            let marker = if config.current_provider.as_ref() == Some(name) {
+                format!(" {}", "(current)".green())
+            } else {
+                String::new()
+            };
+            println!(
+                "  - {}{} {}",
+                name.yellow(),
+                marker,
+                format!("({})", reasons.join("; ")).dimmed()
+            );
+        }
+
+        if Config::is_dry_run() {
+            eprintln!("{} Dry run: nothing was removed", "🔍".blue());
+            return Ok(());
+        }
+
+        let interactive = std::io::stdin().is_terminal();
+        if !yes {
+            if !interactive {
+                return Err(anyhow!(
+                    "Refusing to remove providers without confirmation; pass --yes"
+                ));
+            }
+
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Remove these service providers?")
+                .default(false)
+                .interact()
+                .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                status!("{} Nothing was removed", "ℹ️".blue());
+                return Ok(());
+            }
+        }
+
+        let mut kept_current = None;
+        if let Some(current) = config
+            .current_provider
+            .clone()
+            .filter(|current| reasons.contains_key(current))
+        {
+            let confirmed = interactive
+                && Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!(
+                        "'{}' is your currently active provider; remove it too?",
+                        current
+                    ))
+                    .default(false)
+                    .interact()
+                    .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                reasons.remove(&current);
+                kept_current = Some(current);
+            }
+        }
+
+        for name in reasons.keys() {
+            Self::forget_provider(config, name);
+        }
+        if !reasons.is_empty() {
+            config.save()?;
+
+            let mut cache = HealthCache::load();
+            cache.prune(config);
+            if let Err(e) = cache.save() {
+                eprintln!("{} {:#}", "⚠️".yellow(), e);
+            }
+        }
+
+        let pruned: Vec<&str> = reasons.keys().map(String::as_str).collect();
+        status!(
+            "{} Pruned {} service provider(s){}",
+            "🗑️".green(),
+            pruned.len().to_string().green().bold(),
+            if pruned.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", Self::quoted_list(&pruned))
+            }
+        );
+        if let Some(current) = kept_current {
+            println!(
+                "{} Kept the current provider '{}'; remove it with 'cce delete {}'",
+                "ℹ️".blue(),
+                current.blue().bold(),
+                current
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails with `ProviderLocked` when `name` exists, is locked, and
+    /// `force` is not set.
+    pub(crate) fn ensure_unlocked(config: &Config, name: &str, force: bool) -> Result<()> {
+        match config.providers.get(name) {
+            Some(provider) if provider.locked && !force => {
+                Err(CceError::ProviderLocked(name.to_string()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set_locked(config: &mut Config, name: &str, locked: bool) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        if provider.locked == locked {
+            status!(
+                "{} Service provider '{}' is already {}",
+                "ℹ️".blue(),
+                name.blue().bold(),
+                if locked { "locked" } else { "unlocked" }
+            );
+            return Ok(());
+        }
+
+        provider.locked = locked;
+        config.save()?;
+
+        if locked {
+            status!(
+                "{} Service provider '{}' is locked; removing or overwriting it now needs --force",
+                "🔒".green(),
+                name.green().bold()
+            );
+        } else {
+            status!(
+                "{} Service provider '{}' is unlocked",
+                "🔓".green(),
+                name.green().bold()
+            );
+        }
+        Ok(())
+    }
+
+    /// Drops a provider from the config; its keyring entry is deleted once
+    /// the config is saved.
+    pub(crate) fn forget_provider(config: &mut Config, name: &str) {
+        if config
+            .providers
+            .get(name)
+            .is_some_and(|provider| secret_store::is_keyring_token(&provider.token))
+        {
+            config.forget_keyring_entry(name);
+        }
+
+        config.remove_provider(name);
+    }
+
+    /// Matches `name` against a pattern where `*` stands for any run of
+    /// characters (including none) and everything else is literal.
+    fn glob_match(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+
+        let (mut p, mut n) = (0, 0);
+        // Position of the last `*` seen and the name index it was tried at
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while n < name.len() {
+            if p < pattern.len() && pattern[p] == '*' {
+                backtrack = Some((p, n));
+                p += 1;
+            } else if p < pattern.len() && pattern[p] == name[n] {
+                p += 1;
+                n += 1;
+            } else if let Some((star, matched)) = backtrack {
+                // Let the last `*` swallow one more character and retry
+                p = star + 1;
+                n = matched + 1;
+                backtrack = Some((star, matched + 1));
+            } else {
+                return false;
+            }
+        }
+
+        pattern[p..].iter().all(|&c| c == '*')
+    }
+
+    pub fn edit_provider(
+        config: &mut Config,
+        name: &str,
+        edit: ProviderEdit,
+        validate: bool,
+        force: bool,
+    ) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        if !config.providers.contains_key(name) {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        }
+        Self::ensure_unlocked(config, name, force)?;
+
+        if edit.is_empty() {
+            println!(
+                "{} Nothing to change: pass at least one of {}, {}, {}, {}, {}, {}, {}, {}",
+                "⚠️".yellow(),
+                "--url".yellow(),
+                "--token".yellow(),
+                "--token-env".yellow(),
+                "--url-env".yellow(),
+                "--desc".yellow(),
+                "--console-url".yellow(),
+                "--weight".yellow(),
+                "--expires".yellow()
+            );
+            return Ok(());
+        }
+
+        if let Some(token) = &edit.token {
+            Self::check_token(token)?;
+        }
+
+        let api_url = edit
+            .api_url
+            .map(|api_url| {
+                Self::normalize_api_url(&Self::trim_pasted(api_url, "API URL"), validate)
+            })
+            .transpose()?;
+        // An empty URL removes the console
+        let console_url = edit
+            .console_url
+            .map(|url| {
+                if url.is_empty() {
+                    Ok(None)
+                } else {
+                    Self::normalize_api_url(&url, validate).map(Some)
+                }
+            })
+            .transpose()?;
+        // An empty date removes the expiry
+        let expires_at = edit
+            .expires
+            .map(|date| {
+                if date.trim().is_empty() {
+                    Ok(None)
+                } else {
+                    Self::parse_expiry(&date).map(Some)
+                }
+            })
+            .transpose()?;
+        let token = edit.token.map(|token| Self::trim_pasted(token, "token"));
+        // Keep the token pool's active entry in step with the new token
+        let pooled = token
+            .clone()
+            .filter(|_| !config.providers[name].tokens.is_empty())
+            .map(|token| config.seal_token(token))
+            .transpose()?;
+        let token = token
+            .map(|token| Self::store_token(config, name, token))
+            .transpose()?;
+        let provider = config.providers.get_mut(name).unwrap();
+
+        if let Some(pooled) = pooled {
+            let active = provider.active_token;
+            provider.tokens[active] = pooled;
+        }
+
+        if let Some(api_url) = api_url {
+            provider.api_url = api_url;
+        }
+        if let Some(token) = token {
+            provider.token = token;
+        }
+        // An empty variable name restores the default
+        if let Some(token_env) = edit.token_env {
+            provider.token_env = Some(token_env).filter(|var| !var.is_empty());
+        }
+        if let Some(url_env) = edit.url_env {
+            provider.url_env = Some(url_env).filter(|var| !var.is_empty());
+        }
+        if let Some(description) = edit.description {
+            provider.description = Some(description).filter(|text| !text.is_empty());
+        }
+        if let Some(console_url) = console_url {
+            provider.console_url = console_url;
+        }
+        if let Some(weight) = edit.weight {
+            provider.weight = Some(weight);
+        }
+        if let Some(expires_at) = expires_at {
+            provider.expires_at = expires_at;
+        }
+
+        config.save()?;
+
+        status!(
+            "{} Successfully updated service provider '{}'",
+            "✅".green(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Probes the provider with `token` and only stores it when the endpoint
+    /// accepts it (or `force` is set), so a bad rotation keeps the old token.
+    pub fn rotate_token(
+        config: &mut Config,
+        name: &str,
+        token: String,
+        timeout: Duration,
+        force: bool,
+    ) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+        Self::ensure_unlocked(config, name, force)?;
+        Self::check_token(&token)?;
+        // `${VAR}` references in the URL are probed expanded, as by `cce test`
+        let provider = &config.resolved_provider(provider)?;
+
+        println!(
+            "{} Testing new token for service provider '{}'",
+            "🔌".blue(),
+            name.blue().bold()
+        );
+        println!("  API URL: {}", provider.api_url.cyan());
+        if let Some(proxy) = provider.proxy_for_api() {
+            println!("  Proxy: {}", proxy.cyan());
+        }
+        let result = connectivity::probe(
+            &provider.api_url,
+            &token,
+            provider.proxy_for_api(),
+            &provider.headers,
+            timeout,
+        )?;
+
+        println!("  Status: {}", Self::describe_probe(&result.status));
+        println!("  Latency: {} ms", result.latency.as_millis());
+
+        if !matches!(result.status, connectivity::ProbeStatus::Ok(_)) {
+            if !force {
+                return Err(anyhow!(
+                    "The new token failed the connectivity test; kept the old token (use --force to save it anyway)"
+                ));
+            }
+            println!(
+                "{} Saving the new token anyway because of --force",
+                "⚠️".yellow()
+            );
+        }
+
+        // The expiry belonged to the old token
+        let had_expiry = config.providers[name].expires_at.is_some();
+        let edit = ProviderEdit {
+            api_url: None,
+            token: Some(token),
+            expires: had_expiry.then(String::new),
+            ..ProviderEdit::default()
+        };
+        Self::edit_provider(config, name, edit, false, force)?;
+        if had_expiry {
+            status!(
+                "{} The old token's expiry was removed; set the new one with 'cce edit {} --expires <date>'",
+                "💡".yellow(),
+                name
+            );
+        }
+        Ok(())
+    }
+
+    pub fn rename_provider(config: &mut Config, old: &str, new: &str, force: bool) -> Result<()> {
+        if !config.providers.contains_key(old) {
+            return Err(CceError::ProviderNotFound(old.to_string()).into());
+        }
+
+        if old == new {
+            status!(
+                "{} Service provider is already named '{}'",
+                "ℹ️".blue(),
+                new.blue().bold()
+            );
+            return Ok(());
+        }
+
+        if config.aliases.contains_key(new) {
+            return Err(anyhow!(
+                "'{}' is already an alias; remove it with 'cce alias --remove {}' first",
+                new,
+                new
+            ));
+        }
+
+        if config.providers.contains_key(new) && !force {
+            return Err(CceError::ProviderExists(new.to_string()).into());
+        }
+
+        if secret_store::is_keyring_token(&config.providers[old].token) {
+            let token = secret_store::fetch(&config.keyring_key(old))?;
+            secret_store::store(&config.keyring_key(new), &token)?;
+            config.forget_keyring_entry(old);
+        }
+
+        config.rename_provider(old, new);
+        config.save()?;
+
+        status!(
+            "{} Renamed service provider '{}' to '{}'",
+            "✏️".green(),
+            old.yellow(),
+            new.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Renames every provider matching `from` after previewing the renames.
+    /// `from` is a `*` glob whose wildcards are substituted, in order, for
+    /// the `*`s in `to`; with `regex`, `from` is a regular expression whose
+    /// first match is replaced by `to`, which may refer to groups as `$1`.
+    pub fn rename_bulk(
+        config: &mut Config,
+        from: &str,
+        to: &str,
+        regex: bool,
+        yes: bool,
+    ) -> Result<()> {
+        let (pattern, replacement) = if regex {
+            (from.to_string(), to.to_string())
+        } else {
+            Self::glob_substitution(from, to)?
+        };
+        let pattern =
+            Regex::new(&pattern).with_context(|| format!("Invalid pattern '{}'", from))?;
+
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+        let renames: Vec<(String, String)> = names
+            .into_iter()
+            .filter(|name| pattern.is_match(name))
+            .map(|name| {
+                let new = pattern.replace(name, replacement.as_str()).into_owned();
+                (name.clone(), new)
+            })
+            .filter(|(old, new)| old != new)
+            .collect();
+
+        if renames.is_empty() {
+            return Err(anyhow!(
+                "No service providers would be renamed by '{}'",
+                from
+            ));
+        }
+        Self::check_bulk_renames(config, &renames)?;
+
+        println!(
+            "{} {} service provider(s) will be renamed:",
+            "🔍".blue(),
+            renames.len().to_string().bold()
+        );
+        let width = renames.iter().map(|(old, _)| old.len()).max().unwrap_or(0);
+        for (old, new) in &renames {
+            println!(
+                "  {:<width$} -> {}",
+                old.yellow(),
+                new.green(),
+                width = width
+            );
+        }
+
+        if !yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to rename providers without confirmation; pass --yes"
+                ));
+            }
+
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Rename these service providers?")
+                .default(false)
+                .interact()
+                .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                status!("{} Nothing was renamed", "ℹ️".blue());
+                return Ok(());
+            }
+        }
+
+        // Old keyring entries are only deleted after the save, and not at all
+        // when a later rename reuses the name
+        let keyring_tokens: Vec<(&str, String)> = renames
+            .iter()
+            .filter(|(old, _)| secret_store::is_keyring_token(&config.providers[old].token))
+            .map(|(old, new)| Ok((new.as_str(), secret_store::fetch(&config.keyring_key(old))?)))
+            .collect::<Result<_>>()?;
+        for (new, token) in &keyring_tokens {
+            secret_store::store(&config.keyring_key(new), token)?;
+        }
+        for (old, _) in &renames {
+            let reused = renames.iter().any(|(_, new)| new == old);
+            if secret_store::is_keyring_token(&config.providers[old].token) && !reused {
+                config.forget_keyring_entry(old);
+            }
+        }
+
+        config.rename_providers(&renames);
+        config.save()?;
+
+        status!(
+            "{} Renamed {} service provider(s)",
+            "✏️".green(),
+            renames.len().to_string().green().bold()
+        );
+        Ok(())
+    }
+
+    /// Translates a `*` glob and its replacement into an anchored regex and
+    /// a replacement referring to the wildcards' capture groups.
+    fn glob_substitution(from: &str, to: &str) -> Result<(String, String)> {
+        let wildcards = from.matches('*').count();
+        if to.matches('*').count() > wildcards {
+            return Err(anyhow!(
+                "'{}' has more '*' than '{}'; each '*' in the new name needs one in the pattern",
+                to,
+                from
+            ));
+        }
+
+        let pattern = from
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join("(.*)");
+        let mut replacement = String::new();
+        for (index, part) in to.split('*').enumerate() {
+            if index > 0 {
+                replacement.push_str(&format!("${{{}}}", index));
+            }
+            replacement.push_str(&part.replace('$', "$$"));
+        }
+        Ok((format!("^{}$", pattern), replacement))
+    }
+
+    /// Refuses renames to an empty name, an alias, or a provider that is not
+    /// itself renamed away, and renames of several providers to one name.
+    fn check_bulk_renames(config: &Config, renames: &[(String, String)]) -> Result<()> {
+        for (old, new) in renames {
+            if new.trim().is_empty() {
+                return Err(anyhow!("'{}' would be renamed to an empty name", old));
+            }
+            if config.aliases.contains_key(new) {
+                return Err(anyhow!(
+                    "'{}' would be renamed to '{}', which is already an alias",
+                    old,
+                    new
+                ));
+            }
+
+            let sources: Vec<&str> = renames
+                .iter()
+                .filter(|(_, other)| other == new)
+                .map(|(source, _)| source.as_str())
+                .collect();
+            if sources.len() > 1 {
+                return Err(anyhow!(
+                    "{} would all be renamed to '{}'",
+                    Self::quoted_list(&sources),
+                    new
+                ));
+            }
+
+            let vacated = renames.iter().any(|(source, _)| source == new);
+            if config.providers.contains_key(new) && !vacated {
+                return Err(anyhow!(
+                    "'{}' would be renamed to '{}', which already exists",
+                    old,
+                    new
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Duplicates a provider under a new name, optionally overriding the
+    /// URL and token of the copy. The current provider is left unchanged.
+    pub fn copy_provider(
+        config: &mut Config,
+        src: &str,
+        dest: &str,
+        api_url: Option<String>,
+        token: Option<String>,
+        force: bool,
+    ) -> Result<()> {
+        let src = &config.resolve_name(src)?;
+        let Some(source) = config.providers.get(src) else {
+            return Err(CceError::ProviderNotFound(src.to_string()).into());
+        };
+
+        if config.aliases.contains_key(dest) {
+            return Err(anyhow!(
+                "'{}' is already an alias; remove it with 'cce alias --remove {}' first",
+                dest,
+                dest
+            ));
+        }
+
+        if config.providers.contains_key(dest) && !force {
+            return Err(CceError::ProviderExists(dest.to_string()).into());
+        }
+
+        if let Some(token) = &token {
+            Self::check_token(token)?;
+        }
+
+        let mut copy = source.clone();
+        copy.name = dest.to_string();
+        copy.last_used = None;
+
+        if let Some(api_url) = api_url {
+            copy.api_url = Self::normalize_api_url(&api_url, true)?;
+        }
+
+        // Keyring entries are per provider, so the copy needs its own
+        let token = match token {
+            Some(token) => Some(token),
+            None if secret_store::is_keyring_token(&source.token) => {
+                Some(secret_store::fetch(&config.keyring_key(src))?)
+            }
+            None => None,
+        };
+        if let Some(token) = token {
+            copy.token = Self::store_token(config, dest, token)?;
+        }
+
+        let replaced_keyring_entry = config
+            .providers
+            .get(dest)
+            .is_some_and(|existing| secret_store::is_keyring_token(&existing.token));
+        if replaced_keyring_entry && !secret_store::is_keyring_token(&copy.token) {
+            config.forget_keyring_entry(dest);
+        }
+
+        config.add_provider(copy);
+        config.save()?;
+
+        status!(
+            "{} Copied service provider '{}' to '{}'",
+            "📋".green(),
+            src.yellow(),
+            dest.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Prints the fields that differ between two providers as `-`/`+` lines,
+    /// and the shared ones dimmed. Tokens are only reported as differing or
+    /// not, never shown.
+    pub fn diff_providers(config: &Config, a: &str, b: &str, all_env: bool) -> Result<()> {
+        let mut sides = Vec::with_capacity(2);
+        for name in [a, b] {
+            let name = config.resolve_name(name)?;
+            let Some(provider) = config.providers.get(&name) else {
+                return Err(CceError::ProviderNotFound(name).into());
+            };
+            let mut provider = provider.clone();
+            provider.token = config.provider_token(&provider)?;
+            sides.push(provider);
+        }
+        let (left, right) = (&sides[0], &sides[1]);
+
+        let left_tags = left.tags.join(", ");
+        let right_tags = right.tags.join(", ");
+        let mut rows: Vec<(String, Option<&str>, Option<&str>)> = vec![
+            (
+                "api_url".to_string(),
+                Some(&left.api_url),
+                Some(&right.api_url),
+            ),
+            (
+                "model".to_string(),
+                left.model.as_deref(),
+                right.model.as_deref(),
+            ),
+            (
+                "http_proxy".to_string(),
+                left.http_proxy.as_deref(),
+                right.http_proxy.as_deref(),
+            ),
+            (
+                "https_proxy".to_string(),
+                left.https_proxy.as_deref(),
+                right.https_proxy.as_deref(),
+            ),
+            (
+                "tags".to_string(),
+                Some(left_tags.as_str()).filter(|tags| !tags.is_empty()),
+                Some(right_tags.as_str()).filter(|tags| !tags.is_empty()),
+            ),
+        ];
+        let mut extra_keys: Vec<&String> = left
+            .extra_env
+            .keys()
+            .chain(right.extra_env.keys())
+            .collect();
+        extra_keys.sort();
+        extra_keys.dedup();
+        for key in extra_keys {
+            rows.push((
+                format!("extra_env.{}", key),
+                left.extra_env.get(key).map(String::as_str),
+                right.extra_env.get(key).map(String::as_str),
+            ));
+        }
+
+        println!("{}", format!("--- {}", left.name).red());
+        println!("{}", format!("+++ {}", right.name).green());
+
+        let mut differences = Self::print_token_diff("token", &left.token, &right.token);
+        for (field, old, new) in &rows {
+            differences += Self::print_diff_row(field, *old, *new);
+        }
+
+        let mut header_names: Vec<&String> =
+            left.headers.keys().chain(right.headers.keys()).collect();
+        header_names.sort();
+        header_names.dedup();
+        for name in header_names {
+            let field = format!("headers.{}", name);
+            let old = left.headers.get(name).map(String::as_str);
+            let new = right.headers.get(name).map(String::as_str);
+            differences += match (old, new) {
+                (Some(old), Some(new)) if is_secret_header(name) => {
+                    Self::print_token_diff(&field, old, new)
+                }
+                _ if is_secret_header(name) => Self::print_diff_row(
+                    &field,
+                    old.map(|_| REDACTED_TOKEN),
+                    new.map(|_| REDACTED_TOKEN),
+                ),
+                _ => Self::print_diff_row(&field, old, new),
+            };
+        }
+
+        if all_env {
+            println!();
+            println!("{}", "Exported variables:".bold());
+            let scheme = config.env_scheme();
+            let left_vars = Self::provider_env_vars(left, scheme);
+            let right_vars = Self::provider_env_vars(right, scheme);
+            let mut keys: Vec<&str> = left_vars.iter().map(|(key, _)| *key).collect();
+            for (key, _) in &right_vars {
+                if !keys.contains(key) {
+                    keys.push(key);
+                }
+            }
+            let left_vars: HashMap<&str, &str> = left_vars
+                .iter()
+                .map(|(key, value)| (*key, value.as_ref()))
+                .collect();
+            let right_vars: HashMap<&str, &str> = right_vars
+                .iter()
+                .map(|(key, value)| (*key, value.as_ref()))
+                .collect();
+            for key in keys {
+                let old = left_vars.get(key).copied();
+                let new = right_vars.get(key).copied();
+                let is_token = left.token_vars(scheme).contains(&key)
+                    || right.token_vars(scheme).contains(&key)
+                    || key == CUSTOM_HEADERS_ENV;
+                differences += match (old, new) {
+                    (Some(old), Some(new)) if is_token => Self::print_token_diff(key, old, new),
+                    _ if is_token => Self::print_diff_row(
+                        key,
+                        old.map(|_| REDACTED_TOKEN),
+                        new.map(|_| REDACTED_TOKEN),
+                    ),
+                    _ => Self::print_diff_row(key, old, new),
+                };
+            }
+        }
+
+        println!();
+        if differences == 0 {
+            println!(
+                "{} '{}' and '{}' are identical",
+                "✅".green(),
+                left.name,
+                right.name
+            );
+        } else {
+            println!(
+                "{} {} difference(s) between '{}' and '{}'",
+                "🔍".blue(),
+                differences.to_string().bold(),
+                left.name,
+                right.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Reports whether two tokens differ without printing either; returns 1
+    /// if they do.
+    fn print_token_diff(field: &str, old: &str, new: &str) -> usize {
+        if old == new {
+            println!("{}", format!("  {}: same", field).dimmed());
+            0
+        } else {
+            println!("{}", format!("! {}: differs", field).yellow());
+            1
+        }
+    }
+
+    /// Prints one compared field, returning 1 if the sides differ. A field
+    /// unset on both sides is skipped.
+    fn print_diff_row(field: &str, old: Option<&str>, new: Option<&str>) -> usize {
+        if old == new {
+            if let Some(value) = old {
+                println!("{}", format!("  {}: {}", field, value).dimmed());
+            }
+            return 0;
+        }
+
+        if let Some(old) = old {
+            println!("{}", format!("- {}: {}", field, old).red());
+        }
+        if let Some(new) = new {
+            println!("{}", format!("+ {}: {}", field, new).green());
+        }
+        1
+    }
+
+    /// Adds a backup token to the provider's pool, starting the pool with its
+    /// current token when it has none yet. The active token is unchanged.
+    pub fn add_token(config: &mut Config, name: &str, token: String) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+        Self::check_token(&token)?;
+        let token = Self::trim_pasted(token, "token");
+
+        let mut pool = provider.tokens.clone();
+        if pool.is_empty() {
+            pool.push(config.seal_token(config.provider_token(provider)?)?);
+        }
+        for existing in &pool {
+            if config.unseal_token(existing)? == token {
+                return Err(anyhow!("'{}' already has this token", name));
+            }
+        }
+        pool.push(config.seal_token(token)?);
+
+        let count = pool.len();
+        config.providers.get_mut(name).unwrap().tokens = pool;
+        config.save()?;
+
+        status!(
+            "{} Added token {} to service provider '{}'",
+            "🔑".green(),
+            count,
+            name.green().bold()
+        );
+        status!(
+            "{} Switch to it when the active one is rate-limited with 'cce token next {}'",
+            "💡".yellow(),
+            name
+        );
+        Ok(())
+    }
+
+    pub fn list_tokens(config: &Config, name: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        println!(
+            "{} Tokens of service provider '{}':",
+            "🔑".blue(),
+            name.blue().bold()
+        );
+        if provider.tokens.is_empty() {
+            println!(
+                "  {} 1. {}",
+                "●".green(),
+                Self::masked_token(config, provider).green()
+            );
+            return Ok(());
+        }
+
+        for (index, token) in provider.tokens.iter().enumerate() {
+            let masked = Self::masked_pooled_token(config, token);
+            if index == provider.active_token {
+                println!("  {} {}. {}", "●".green(), index + 1, masked.green());
+            } else {
+                println!("  {} {}. {}", "○".white(), index + 1, masked.dimmed());
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the provider's next token (wrapping around) the active one.
+    pub fn next_token(config: &mut Config, name: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+        if provider.tokens.len() < 2 {
+            return Err(anyhow!(
+                "'{}' has a single token; add a backup with 'cce token add {}'",
+                name,
+                name
+            ));
+        }
+
+        let next = (provider.active_token + 1) % provider.tokens.len();
+        let count = provider.tokens.len();
+        let token = config.unseal_token(&provider.tokens[next])?;
+        let masked = Self::mask_secret(&token);
+        let stored = Self::store_token(config, name, token)?;
+
+        let provider = config.providers.get_mut(name).unwrap();
+        provider.token = stored;
+        provider.active_token = next;
+        config.save()?;
+
+        status!(
+            "{} Service provider '{}' now uses token {} of {} ({})",
+            "🔄".green(),
+            name.green().bold(),
+            next + 1,
+            count,
+            masked.dimmed()
+        );
+        if config.current_provider.as_ref() == Some(name) {
+            status!("{} Run 'cce use {}' to export it", "💡".yellow(), name);
+        }
+        Ok(())
+    }
+
+    /// Like `masked_token`, for an entry of a provider's token pool.
+    fn masked_pooled_token(config: &Config, token: &str) -> String {
+        if config.encrypted {
+            "(encrypted)".to_string()
+        } else {
+            Self::mask_secret(token)
+        }
+    }
+
+    /// Presents a fuzzy-searchable picker of providers, with the current one
+    /// pre-selected. Returns `None` if the user cancels.
+    pub fn select_provider(config: &Config) -> Result<Option<String>> {
+        if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+            return Err(anyhow!(
+                "No provider name given; pass one explicitly when not running in a terminal"
+            ));
+        }
+
+        if config.providers.is_empty() {
+            println!("{}", "No service providers configured".yellow());
+            return Ok(None);
+        }
+
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+
+        let current = config
+            .current_provider
+            .as_ref()
+            .and_then(|current| names.iter().position(|name| *name == current))
+            .unwrap_or(0);
+
+        let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select a service provider")
+            .items(&names)
+            .default(current)
+            .interact_opt()
+            .with_context(|| "Failed to read provider selection")?;
+
+        Ok(selection.map(|index| names[index].clone()))
+    }
+
+    /// Switches to `name` and exports its variables. Without `save` the
+    /// switch only affects this shell: the current provider, last-used time,
+    /// and history are left as they are. With `verify`, the provider's host
+    /// must first accept a connection within that timeout, unless `force` is
+    /// set.
+    pub fn use_provider(
+        config: &mut Config,
+        name: &str,
+        eval: bool,
+        save: bool,
+        shell: Option<Shell>,
+        verify: Option<Duration>,
+        force: bool,
+    ) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        if !config.providers.contains_key(name) {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        }
+
+        let shell_mode = eval || Self::shell_integration_active();
+        debug!(
+            "Using provider '{}' (shell mode: {}, save: {})",
+            name, shell_mode, save
+        );
+
+        if let Some(current) = &config.current_provider {
+            if current == name && !shell_mode && save {
+                status!(
+                    "{} Already using service provider '{}'",
+                    "ℹ️".blue(),
+                    name.blue().bold()
+                );
+                return Ok(());
+            }
+        }
+
+        let provider = config.resolved_provider(&config.providers[name])?;
+        let foreign = Self::foreign_url_warning(config, &provider);
+        let expired = (provider.expiry() == Some(Expiry::Expired)).then(|| {
+            format!(
+                "The token of '{}' expired on {}; replace it with 'cce rotate {}'",
+                name,
+                Self::expiry_text(&provider).unwrap_or_default(),
+                name
+            )
+        });
+        let warnings: Vec<String> = [foreign, expired].into_iter().flatten().collect();
+
+        if let Some(timeout) = verify {
+            Self::verify_reachable(name, &provider, timeout, force)?;
+        }
+
+        if save {
+            config.set_current_provider(name);
+            config.save()?;
+        }
+
+        Self::apply_environment_variables(&provider, config.env_scheme())?;
+
+        let switched = if save {
+            format!(
+                "{} Switched to service provider '{}'",
+                "🔄".green(),
+                name.green().bold()
+            )
+        } else {
+            format!(
+                "{} Using service provider '{}' temporarily (the current provider is not changed)",
+                "🔄".green(),
+                name.green().bold()
+            )
+        };
+
+        if shell_mode {
+            let shell = shell.unwrap_or_else(Shell::detect);
+            // `eval "$(cce use ... 2>/dev/null)"` would swallow them, so the
+            // warnings are evaluated along with the exports
+            for warning in &warnings {
+                match shell.echo_stderr(&format!("⚠️ {}", warning)) {
+                    Some(command) => println!("{}", command),
+                    None => eprintln!("{} {}", "⚠️".yellow(), warning),
+                }
+            }
+            Self::emit_export_commands(&provider, config.env_scheme(), shell);
+            // The shell wrapper shows this instead of guessing the name
+            status_stderr!("{}", switched);
+        } else {
+            for warning in &warnings {
+                println!("{} {}", "⚠️".yellow(), warning.yellow());
+            }
+            status!("{}", switched);
+            status!("  API URL: {}", provider.api_url.cyan());
+            Self::run_switch_hook(config, &provider);
+            Self::warn_without_shellenv();
+        }
+
+        Ok(())
+    }
+
+    /// How long `cce use` may take to check reachability, if it checks at
+    /// all: always with `--verify`, and with `verify_on_use` set except in
+    /// shell mode, which has to stay fast.
+    pub fn verify_timeout(
+        config: &Config,
+        verify: bool,
+        eval: bool,
+        timeout: Duration,
+    ) -> Option<Duration> {
+        let shell_mode = eval || Self::shell_integration_active();
+        (verify || (config.verify_on_use && !shell_mode)).then_some(timeout)
+    }
+
+    /// A DNS lookup and TCP connect to the provider's host, or to its proxy
+    /// when it has one, which is much cheaper than the request `cce test`
+    /// sends and still catches mistyped or dead hosts.
+    fn verify_reachable(
+        name: &str,
+        provider: &Provider,
+        timeout: Duration,
+        force: bool,
+    ) -> Result<()> {
+        let target = provider.proxy_for_api().unwrap_or(&provider.api_url);
+        let Err(e) = connectivity::reachable(target, timeout) else {
+            debug!("{} is reachable", target);
+            return Ok(());
+        };
+        if !force {
+            return Err(anyhow!(
+                "Service provider '{}' is unreachable: {:#}; kept the current provider (use --force to switch anyway)",
+                name,
+                e
+            ));
+        }
+        eprintln!(
+            "{} Service provider '{}' is unreachable ({:#}); switching anyway because of --force",
+            "⚠️".yellow(),
+            name,
+            e
+        );
+        Ok(())
+    }
+
+    /// A child process cannot change its parent shell's environment, so
+    /// without the `shellenv` wrapper `cce use` only updates the config.
+    /// Only interactive runs are warned; scripts calling `cce use` to change
+    /// the saved provider are left alone.
+    fn warn_without_shellenv() {
+        if Self::shellenv_loaded() || !std::io::stdout().is_terminal() {
+            return;
+        }
+        println!();
+        println!(
+            "{} {}",
+            "⚠️".yellow(),
+            "The cce shell function is not loaded, so this terminal's environment was not changed"
+                .yellow()
+                .bold()
+        );
+        println!(
+            "   Run {} and switch again, or 'cce install' to load it in every new terminal",
+            Self::shellenv_line(Shell::detect()).cyan()
+        );
+    }
+
+    /// Runs the configured `on_switch` command through the shell, with the
+    /// provider's name and API URL in `CCE_HOOK_PROVIDER` / `CCE_HOOK_URL`
+    /// (and as `$1` / `$2` on Unix). The switch has already happened, so a
+    /// failing hook only warns.
+    fn run_switch_hook(config: &Config, provider: &Provider) {
+        let Some(hook) = &config.on_switch else {
+            return;
+        };
+        if Config::is_dry_run() {
+            eprintln!("{} Dry run: the on_switch hook was not run", "🔍".blue());
+            return;
+        }
+
+        debug!("Running on_switch hook: {}", hook);
+        let mut command = if cfg!(windows) {
+            let mut command = std::process::Command::new("cmd");
+            command.arg("/C").arg(hook);
+            command
+        } else {
+            let mut command = std::process::Command::new("sh");
+            command
+                .arg("-c")
+                .arg(hook)
+                .arg("cce")
+                .arg(&provider.name)
+                .arg(&provider.api_url);
+            command
+        };
+        command
+            .env("CCE_HOOK_PROVIDER", &provider.name)
+            .env("CCE_HOOK_URL", &provider.api_url);
+
+        match command.status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!(
+                "{} The on_switch hook failed ({}); the switch itself succeeded",
+                "⚠️".yellow(),
+                status
+            ),
+            Err(e) => eprintln!("{} Could not run the on_switch hook: {}", "⚠️".yellow(), e),
+        }
+    }
+
+    /// The provider that was current before the last switch, for `cce last`
+    /// and `cce use -`.
+    pub fn previous_provider(config: &Config) -> Result<String> {
+        let Some(previous) = &config.previous_provider else {
+            return Err(anyhow!(
+                "No previous provider to switch back to yet; switch with 'cce use <name>' first"
+            ));
+        };
+        if !config.providers.contains_key(previous) {
+            return Err(anyhow!(
+                "The previous provider '{}' no longer exists",
+                previous
+            ));
+        }
+        Ok(previous.clone())
+    }
+
+    /// Chooses the provider for `cce use --random` / `--round-robin` among
+    /// all providers, or those tagged `tag`. The round-robin position is
+    /// advanced in `config` and saved along with the switch.
+    pub fn pick_provider(
+        config: &mut Config,
+        tag: Option<&str>,
+        rotation: Rotation,
+    ) -> Result<String> {
+        let tag = tag.map(str::to_lowercase);
+        let mut names: Vec<&String> = config
+            .providers
+            .iter()
+            .filter(|(_, provider)| tag.as_ref().is_none_or(|tag| provider.tags.contains(tag)))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        if names.is_empty() {
+            return Err(match &tag {
+                Some(tag) => anyhow!("No service providers match tag '{}'", tag),
+                None => anyhow!("No service providers configured"),
+            });
+        }
+
+        let picked = match rotation {
+            Rotation::Random => {
+                let weights: Vec<u64> = names
+                    .iter()
+                    .map(|name| u64::from(config.providers[*name].weight.unwrap_or(1)))
+                    .collect();
+                let total: u64 = weights.iter().sum();
+                if total == 0 {
+                    return Err(anyhow!(
+                        "Every candidate has weight 0, so none can be picked; set a positive one with 'cce edit <name> --weight <n>'"
+                    ));
+                }
+                // Providers with weight 0 are never landed on
+                let mut roll = Self::random_u64() % total;
+                let mut index = 0;
+                while roll >= weights[index] {
+                    roll -= weights[index];
+                    index += 1;
+                }
+                names[index].clone()
+            }
+            Rotation::RoundRobin => {
+                let key = tag.clone().unwrap_or_default();
+                let index = config.round_robin.get(&key).copied().unwrap_or(0) % names.len();
+                let picked = names[index].clone();
+                config.round_robin.insert(key, index + 1);
+                picked
+            }
+        };
+        Ok(picked)
+    }
+
+    /// A random number from the per-process keys of `RandomState`, which is
+    /// plenty for spreading load without pulling in an RNG crate.
+    fn random_u64() -> u64 {
+        use std::hash::{BuildHasher, Hasher};
+
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos())
+                .unwrap_or_default(),
+        );
+        hasher.finish()
+    }
+
+    /// Finds the configured provider other than the current one that the
+    /// live environment was exported from, e.g. by `cce use --no-save`.
+    /// Tokens are compared the fast way, so no secret is read.
+    fn temporary_override(config: &Config) -> Result<Option<&Provider>> {
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+        for name in names {
+            if config.current_provider.as_ref() == Some(name) {
+                continue;
+            }
+            let provider = &config.providers[name];
+            if Self::env_status(config, provider, true)? == EnvStatus::Match {
+                return Ok(Some(provider));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Describes a live value of the provider's URL variable that no
+    /// configured provider uses, i.e. one exported by another tool such as
+    /// direnv, which may set it again after cce overrides it.
+    fn foreign_url_warning(config: &Config, provider: &Provider) -> Option<String> {
+        let var = provider.url_var();
+        let live = std::env::var(var).ok().filter(|value| !value.is_empty())?;
+
+        let live_url = Self::comparable_url(&live);
+        let ours = config
+            .providers
+            .values()
+            .any(|configured| Self::comparable_url(&configured.api_url) == live_url);
+        if ours {
+            return None;
+        }
+
+        Some(format!(
+            "{} was set to {} by something other than cce (e.g. direnv); it is overridden now, but that tool may set it again",
+            var, live
+        ))
+    }
+
+    /// Exports the current provider after it was switched outside `use`,
+    /// i.e. from the TUI.
+    #[cfg(feature = "tui")]
+    pub fn export_current_provider(
+        config: &Config,
+        eval: bool,
+        shell: Option<Shell>,
+    ) -> Result<()> {
+        let Some(name) = config.current_provider.as_deref() else {
+            return Ok(());
+        };
+
+        let provider = config.resolved_provider(&config.providers[name])?;
+        Self::apply_environment_variables(&provider, config.env_scheme())?;
+
+        if eval || Self::shell_integration_active() {
+            Self::emit_export_commands(
+                &provider,
+                config.env_scheme(),
+                shell.unwrap_or_else(Shell::detect),
+            );
+        } else {
+            status!(
+                "{} Switched to service provider '{}'",
+                "🔄".green(),
+                name.green().bold()
+            );
+            status!(
+                "{} Run 'cce use {}' to export its variables in this shell",
+                "💡".yellow(),
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints a table of the variables `cce use` would export for the
+    /// provider, with `${VAR}` references resolved and secrets masked. Neither
+    /// the config nor the shell is changed.
+    pub fn print_provider_env(config: &Config, name: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        let provider = config.resolved_provider(provider)?;
+        let scheme = config.env_scheme();
+        let token_vars = provider.token_vars(scheme);
+        let vars = Self::provider_env_vars(&provider, scheme);
+
+        println!(
+            "{} 'cce use {}' would export (nothing was changed):",
+            "🔍".blue(),
+            name.blue().bold()
+        );
+        let width = vars.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+        for (key, value) in vars {
+            let value = if key == CUSTOM_HEADERS_ENV {
+                // One header per line, aligned under the first
+                Self::masked_headers(&provider)
+                    .join(&format!("\n  {:width$}  ", "", width = width))
+                    .cyan()
+            } else if token_vars.contains(&key) || is_secret_var(key) {
+                Self::mask_secret(&value).dimmed()
+            } else {
+                value.as_ref().cyan()
+            };
+            println!("  {:<width$}  {}", key, value, width = width);
+        }
+        Ok(())
+    }
+
+    /// The provider's headers as sorted `Name: Value` lines, with the values
+    /// of credential-like headers masked.
+    fn masked_headers(provider: &Provider) -> Vec<String> {
+        let mut lines: Vec<String> = provider
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                if is_secret_header(name) {
+                    format!("{}: {}", name, Self::mask_secret(value))
+                } else {
+                    format!("{}: {}", name, value)
+                }
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    /// Runs `command` with the provider's variables in its environment, in
+    /// place of those of the current provider, and returns its exit code.
+    /// Neither the config nor the calling shell is changed. On Unix, cce
+    /// replaces itself with the command, so signals reach it directly.
+    pub fn run_with_provider(config: &Config, name: &str, command: &[OsString]) -> Result<i32> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow!("No command given; pass it after '--'"))?;
+
+        let provider = config.resolved_provider(provider)?;
+        if provider.expiry() == Some(Expiry::Expired) {
+            eprintln!(
+                "{} The token of '{}' expired on {}",
+                "⚠️".yellow(),
+                name,
+                Self::expiry_text(&provider).unwrap_or_default()
+            );
+        }
+        if Config::is_dry_run() {
+            let words: Vec<String> = command
+                .iter()
+                .map(|word| word.to_string_lossy().into_owned())
+                .collect();
+            eprintln!(
+                "{} Dry run: would run {} with the variables of '{}'",
+                "🔍".blue(),
+                Self::command_line(&words).cyan(),
+                name
+            );
+            return Ok(0);
+        }
+
+        let scheme = config.env_scheme();
+        let mut child = std::process::Command::new(program);
+        child.args(args);
+        // A token in either variable would win over or mix with the provider's
+        let current = config.get_current_provider();
+        for key in Self::managed_env_var_names(current, EnvScheme::Both) {
+            child.env_remove(key);
+        }
+        for (key, value) in Self::provider_env_vars(&provider, scheme) {
+            child.env(key, value.as_ref());
+        }
+        debug!("Running {:?} with the variables of '{}'", command, name);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // Only returns on failure
+            let e = child.exec();
+            Err(Self::spawn_error(program, e))
+        }
+        #[cfg(not(unix))]
+        {
+            let status = child.status().map_err(|e| Self::spawn_error(program, e))?;
+            Ok(status.code().unwrap_or(1))
+        }
+    }
+
+    fn spawn_error(program: &OsStr, error: std::io::Error) -> anyhow::Error {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            anyhow!("Command '{}' not found", program.to_string_lossy())
+        } else {
+            anyhow!("Failed to run '{}': {}", program.to_string_lossy(), error)
+        }
+    }
+
+    /// Prints the variables `cce use` would export for a provider, without
+    /// switching to it or touching the config.
+    pub fn export_env(
+        config: &Config,
+        name: Option<&str>,
+        format: EnvFormat,
+        shell: Option<Shell>,
+    ) -> Result<()> {
+        let name = match name {
+            Some(name) => config.resolve_name(name)?,
+            None => config.current_provider.clone().ok_or_else(|| {
+                anyhow!("No current provider; pass a name or switch with 'cce use <name>'")
+            })?,
+        };
+        let Some(provider) = config.providers.get(&name) else {
+            return Err(CceError::ProviderNotFound(name).into());
+        };
+
+        let provider = config.resolved_provider(provider)?;
+        let vars = Self::provider_env_vars(&provider, config.env_scheme());
+
+        match format {
+            EnvFormat::Dotenv => {
+                for (key, value) in vars {
+                    println!("{}={}", key, Self::dotenv_quote(&value));
+                }
+            }
+            EnvFormat::Shell => Self::emit_export_commands(
+                &provider,
+                config.env_scheme(),
+                shell.unwrap_or_else(Shell::detect),
+            ),
+            EnvFormat::Json => {
+                let map: serde_json::Map<String, serde_json::Value> = vars
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.into()))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&map)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the provider's variables to a dotenv file readable only by the
+    /// owner. With `merge`, the file's other lines are kept and the
+    /// provider's keys are replaced in place or appended.
+    pub fn write_env_file(config: &Config, name: &str, out: &Path, merge: bool) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        let provider = config.resolved_provider(provider)?;
+        let entries: Vec<(&str, String)> = Self::provider_env_vars(&provider, config.env_scheme())
+            .into_iter()
+            .map(|(key, value)| (key, Self::dotenv_quote(&value)))
+            .collect();
+
+        let existing = match std::fs::read_to_string(out) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read env file: {:?}", out)),
+        };
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut written: Vec<&str> = Vec::new();
+        let mut conflicts: Vec<&str> = Vec::new();
+        for line in existing.lines() {
+            let entry = Self::dotenv_key(line)
+                .and_then(|key| entries.iter().find(|(ours, _)| *ours == key));
+            match entry {
+                Some((key, value)) => {
+                    let (_, current) = line.split_once('=').unwrap_or_default();
+                    if current.trim() != value && !conflicts.contains(key) {
+                        conflicts.push(key);
+                    }
+                    if merge && !written.contains(key) {
+                        lines.push(format!("{}={}", key, value));
+                        written.push(key);
+                    }
+                }
+                None if merge => lines.push(line.to_string()),
+                None => {}
+            }
+        }
+        for (key, value) in &entries {
+            if !written.contains(key) {
+                lines.push(format!("{}={}", key, value));
+            }
+        }
+
+        if !conflicts.is_empty() {
+            println!(
+                "{} {} already set {} to a different value; replacing it",
+                "⚠️".yellow(),
+                out.display(),
+                conflicts.join(", ").yellow()
+            );
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        config::write_private(out, &content)
+            .with_context(|| format!("Failed to write env file: {:?}", out))?;
+
+        status!(
+            "{} Wrote {} variable(s) of service provider '{}' to {}",
+            "✅".green(),
+            entries.len(),
+            name.green().bold(),
+            out.display().to_string().cyan()
+        );
+        Ok(())
+    }
+
+    /// The variable a dotenv line assigns, ignoring comments and `export`.
+    fn dotenv_key(line: &str) -> Option<&str> {
+        let line = line.trim_start();
+        if line.starts_with('#') {
+            return None;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, _) = line.split_once('=')?;
+        Some(key.trim())
+    }
+
+    /// Leaves plain values bare so `env $(cce export-env) ...` works, and
+    /// double-quotes anything a .env parser would split or expand.
+    fn dotenv_quote(value: &str) -> String {
+        let plain = !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./:@%+,=".contains(c));
+        if plain {
+            return value.to_string();
+        }
+
+        let mut quoted = String::with_capacity(value.len() + 2);
+        quoted.push('"');
+        for c in value.chars() {
+            match c {
+                '"' | '\\' | '$' | '`' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                '\n' => quoted.push_str("\\n"),
+                _ => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    /// Builds the transient provider defined by `CCE_PROVIDER_URL` and
+    /// `CCE_PROVIDER_TOKEN`, if both are set.
+    fn ephemeral_provider() -> Option<Provider> {
+        let read = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+
+        let mut provider = Provider::new(
+            EPHEMERAL_PROVIDER_NAME.to_string(),
+            read(EPHEMERAL_URL_ENV)?.trim_end_matches('/').to_string(),
+            read(EPHEMERAL_TOKEN_ENV)?,
+        );
+        provider.model = read(EPHEMERAL_MODEL_ENV);
+        Some(provider)
+    }
+
+    /// Exports the provider defined by `CCE_PROVIDER_*` variables, e.g. in CI.
+    /// Nothing is read from or written to the config file.
+    pub fn use_ephemeral_provider(config: &Config, eval: bool, shell: Option<Shell>) -> Result<()> {
+        let Some(provider) = Self::ephemeral_provider() else {
+            return Err(anyhow!(
+                "--from-env needs both {} and {} to be set",
+                EPHEMERAL_URL_ENV,
+                EPHEMERAL_TOKEN_ENV
+            ));
+        };
+        Self::normalize_api_url(&provider.api_url, true)?;
+        Self::check_token(&provider.token)?;
+
+        Self::apply_environment_variables(&provider, config.env_scheme())?;
+
+        if eval || Self::shell_integration_active() {
+            Self::emit_export_commands(
+                &provider,
+                config.env_scheme(),
+                shell.unwrap_or_else(Shell::detect),
+            );
+        } else {
+            status!(
+                "{} Using ephemeral service provider from {} (not saved to the config)",
+                "🔄".green(),
+                EPHEMERAL_URL_ENV.green().bold()
+            );
+            status!("  API URL: {}", provider.api_url.cyan());
+        }
+        Ok(())
+    }
+
+    /// Prints the active provider without decoration. Returns `false` when
+    /// no provider is active so the caller can exit non-zero.
+    pub fn print_current_provider(config: &Config, url: bool, json: bool) -> Result<bool> {
+        let Some(provider) = config
+            .current_provider
+            .as_ref()
+            .and_then(|name| config.providers.get(name))
+        else {
+            return Ok(false);
+        };
+
+        if json {
+            let value = serde_json::json!({
+                "name": provider.name,
+                "api_url": provider.api_url,
+            });
+            println!("{}", value);
+        } else if url {
+            println!("{}\t{}", provider.name, provider.api_url);
+        } else {
+            println!("{}", provider.name);
+        }
+
+        Ok(true)
+    }
+
+    /// Compares the live environment with what `cce use` exports for the
+    /// provider. With `fast`, tokens kept in the keyring or encrypted are only
+    /// checked for presence, so no keyring access or password prompt happens.
+    fn env_status(config: &Config, provider: &Provider, fast: bool) -> Result<EnvStatus> {
+        let env_tokens: Option<Vec<String>> = provider
+            .token_vars(config.env_scheme())
+            .into_iter()
+            .map(|key| std::env::var(key).ok())
+            .collect();
+        let (Some(env_tokens), Ok(env_url)) = (env_tokens, std::env::var(provider.url_var()))
+        else {
+            return Ok(EnvStatus::NotSet);
+        };
+
+        let token_is_secret = secret_store::is_keyring_token(&provider.token) || config.encrypted;
+        let token_matches = if fast && token_is_secret {
+            true
+        } else {
+            // An unset `${VAR}` leaves the template, which never matches
+            let token = config.provider_token(provider)?;
+            let token = interpolate_env(&token).unwrap_or(token);
+            env_tokens.iter().all(|env_token| *env_token == token)
+        };
+        let api_url =
+            interpolate_env(&provider.api_url).unwrap_or_else(|_| provider.api_url.clone());
+        let url_matches = env_url.trim_end_matches('/') == api_url.trim_end_matches('/');
+
+        let mut expected: Vec<(&str, &str)> = Self::proxy_env_vars(provider);
+        if let Some(model) = &provider.model {
+            expected.push((MODEL_ENV_VARS[0], model));
+        }
+        expected.extend(
+            provider
+                .extra_env
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        );
+        let headers = provider.custom_headers();
+        if let Some(headers) = &headers {
+            expected.push((CUSTOM_HEADERS_ENV, headers));
+        }
+        let rest_matches = expected
+            .iter()
+            .all(|(key, value)| std::env::var(key).is_ok_and(|live| live == *value));
+
+        Ok(if token_matches && url_matches && rest_matches {
+            EnvStatus::Match
+        } else {
+            EnvStatus::Mismatch
+        })
+    }
+
+    /// Prints a compact summary of the active provider and whether the live
+    /// environment matches it. Returns whether it matches.
+    ///
+    /// `fast` is the prompt-hook path: it prints nothing, avoids reading
+    /// secrets, and only reports a mismatch when an active provider has drifted.
+    pub fn print_status(
+        config: &Config,
+        format: OutputFormat,
+        quiet: bool,
+        fast: bool,
+    ) -> Result<bool> {
+        // An ephemeral provider is active when the live variables were
+        // exported from it; its token is plaintext, so only the scheme of
+        // the config is needed
+        let mut plain = Config::default();
+        plain.env_scheme = config.env_scheme;
+        let ephemeral = Self::ephemeral_provider().filter(|provider| {
+            Self::env_status(&plain, provider, false).is_ok_and(|status| status == EnvStatus::Match)
+        });
+        let is_ephemeral = ephemeral.is_some();
+        let (active, token_config) = match &ephemeral {
+            Some(provider) => (Some(provider), &plain),
+            None => (
+                config
+                    .current_provider
+                    .as_ref()
+                    .and_then(|name| config.providers.get(name)),
+                config,
+            ),
+        };
+
+        if fast {
+            return match active {
+                Some(provider) => {
+                    Ok(Self::env_status(token_config, provider, true)? == EnvStatus::Match)
+                }
+                None => Ok(true),
+            };
+        }
+
+        let env_status = active
+            .map(|provider| Self::env_status(token_config, provider, false))
+            .transpose()?;
+        let matches = env_status == Some(EnvStatus::Match);
+
+        if quiet {
+            return Ok(matches);
+        }
+
+        if format == OutputFormat::Table {
+            let mut table = Table::new(vec!["profile", "provider", "url", "env", "providers"]);
+            table.push(vec![
+                config.active_profile().to_string(),
+                active.map_or(String::new(), |provider| provider.name.clone()),
+                active.map_or(String::new(), |provider| provider.api_url.clone()),
+                env_status.map_or("", EnvStatus::as_str).to_string(),
+                config.providers.len().to_string(),
+            ]);
+            table.print();
+            return Ok(matches);
+        }
+
+        if format == OutputFormat::Json {
+            let value = serde_json::json!({
+                "profile": config.active_profile(),
+                "active_provider": active.map(|provider| &provider.name),
+                "ephemeral": is_ephemeral,
+                "api_url": active.map(|provider| &provider.api_url),
+                "expires_at": active.and_then(|provider| provider.expires_at),
+                "env_status": env_status,
+                "provider_count": config.providers.len(),
+            });
+            println!("{}", serde_json::to_string_pretty(&value)?);
+            return Ok(matches);
+        }
+
+        let env = match env_status {
+            Some(EnvStatus::Match) => "✅ matches config".green(),
+            Some(EnvStatus::Mismatch) => "⚠️ differs from config".yellow(),
+            Some(EnvStatus::NotSet) => "not set".red(),
+            None => "-".dimmed(),
+        };
+        match active {
+            Some(provider) if is_ephemeral => {
+                println!(
+                    "  Provider:  {} {}",
+                    provider.name.green().bold(),
+                    "(ephemeral, not in the config)".dimmed()
+                );
+                println!("  URL:       {}", provider.api_url.cyan());
+            }
+            Some(provider) => {
+                println!("  Provider:  {}", provider.name.green().bold());
+                println!("  URL:       {}", provider.api_url.cyan());
+                if let Some(expiry) = Self::format_expiry(provider) {
+                    println!("  Expires:   {}", expiry);
+                }
+            }
+            None => {
+                println!("  Provider:  {}", "None selected".yellow());
+                println!("  URL:       {}", "-".dimmed());
+            }
+        }
+        println!("  Env vars:  {}", env);
+        println!("  Providers: {}", config.providers.len());
+        Ok(matches)
+    }
+
+    pub fn check_environment(config: &Config, json: bool) -> Result<()> {
+        let report = Self::env_report(config)?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        println!(
+            "{}",
+            "🔍 Checking environment variable status".blue().bold()
+        );
+        println!();
+
+        let active = config
+            .current_provider
+            .as_ref()
+            .and_then(|name| config.providers.get(name));
+        let token_vars = Self::checked_token_vars(config, active);
+        let url_var = active.map_or(DEFAULT_URL_ENV, |provider| provider.url_var());
+
+        println!("{}", "Current environment variables:".cyan().bold());
+        for token_var in token_vars {
+            match std::env::var(token_var) {
+                Ok(key) => {
+                    println!("  {}: {}", token_var, Self::mask_secret(&key).green());
+                }
+                Err(_) => {
+                    println!("  {}: {}", token_var, "Not set".red());
+                }
+            }
+        }
+
+        match &report.env.url {
+            Some(url) => {
+                println!("  {}: {}", url_var, url.green());
+            }
+            None => {
+                println!("  {}: {}", url_var, "Not set".red());
+            }
+        }
+
+        println!();
+
+        // Check configuration status
+        if let Some(current_provider) = &config.current_provider {
+            if let Some(provider) = config.providers.get(current_provider) {
+                println!("{}", "CCE configuration status:".cyan().bold());
+                println!("  Current provider: {}", current_provider.green().bold());
+                if let Some(description) = &provider.description {
+                    println!("  Description: {}", description.dimmed());
+                }
+                println!("  Configured URL: {}", provider.api_url.cyan());
+
+                if let Some(model) = &provider.model {
+                    let model_var = MODEL_ENV_VARS[0];
+                    match std::env::var(model_var) {
+                        Ok(value) if &value == model => {
+                            println!("  Model: {} ({})", model.cyan(), "✅ matches".green());
+                        }
+                        Ok(value) => {
+                            println!(
+                                "  Model: {} ({} is '{}')",
+                                model.cyan(),
+                                model_var,
+                                value.yellow()
+                            );
+                        }
+                        Err(_) => {
+                            println!(
+                                "  Model: {} ({} {})",
+                                model.cyan(),
+                                model_var,
+                                "Not set".red()
+                            );
+                        }
+                    }
+                }
+
+                if !provider.extra_env.is_empty() {
+                    println!("  Extra variables:");
+                    let mut extra: Vec<_> = provider.extra_env.iter().collect();
+                    extra.sort();
+
+                    for (key, expected) in extra {
+                        match std::env::var(key) {
+                            Ok(value) if &value == expected => {
+                                println!("    {}: {}", key, "✅ matches".green());
+                            }
+                            Ok(_) => {
+                                println!("    {}: {}", key, "⚠️ differs".yellow());
+                            }
+                            Err(_) => {
+                                println!("    {}: {}", key, "Not set".red());
+                            }
+                        }
+                    }
+                }
+
+                if report.matches {
+                    println!(
+                        "  Status: {}",
+                        "✅ Environment variables match configuration".green()
+                    );
+                } else if let Some(live) = Self::temporary_override(config)? {
+                    println!(
+                        "  Status: {} '{}'",
+                        "ℹ️ Temporary override: the environment uses".blue(),
+                        live.name.cyan()
+                    );
+                    println!(
+                        "  Suggestion: Run 'cce use {}' to go back to the saved provider",
+                        current_provider.cyan()
+                    );
+                } else {
+                    println!(
+                        "  Status: {}",
+                        "⚠️ Environment variables do not match configuration".yellow()
+                    );
+                    println!(
+                        "  Suggestion: Run 'cce use {}' to reset",
+                        current_provider.cyan()
+                    );
+                }
+            } else {
+                println!(
+                    "{}",
+                    "❌ Configuration error: Current provider does not exist".red()
+                );
+            }
+        } else {
+            println!("{}", "CCE configuration status:".cyan().bold());
+            println!("  Current provider: {}", "None selected".yellow());
+            if !config.providers.is_empty() {
+                println!("  Suggestion: Use 'cce use <provider-name>' to select a provider");
+            } else {
+                println!("  Suggestion: Use 'cce add' to add a service provider");
+            }
+        }
+
+        match &config.default_provider {
+            Some(default) if config.providers.contains_key(default) => {
+                println!("  Default provider: {}", default.cyan());
+            }
+            Some(default) => {
+                println!(
+                    "  Default provider: {} {}",
+                    default.yellow(),
+                    "(no longer exists, run 'cce default --clear')".yellow()
+                );
+            }
+            None => {
+                println!("  Default provider: {}", "None".dimmed());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compares the live environment with the current provider, reading the
+    /// variables under the names that provider exports them as.
+    fn env_report(config: &Config) -> Result<EnvReport<'_>> {
+        let name = config.current_provider.as_deref();
+        let active = name.and_then(|name| config.providers.get(name));
+        let token_vars = Self::checked_token_vars(config, active);
+        let url_var = active.map_or(DEFAULT_URL_ENV, |provider| provider.url_var());
+
+        let status = active
+            .map(|provider| Self::env_status(config, provider, false))
+            .transpose()?;
+        let url = std::env::var(url_var).ok();
+
+        Ok(EnvReport {
+            env: LiveEnv {
+                token_set: token_vars.iter().all(|key| std::env::var_os(key).is_some()),
+                url_set: url.is_some(),
+                url,
+            },
+            config: ConfiguredEnv {
+                current_provider: name,
+                configured_url: active.map(|provider| provider.api_url.as_str()),
+            },
+            matches: status == Some(EnvStatus::Match),
+        })
+    }
+
+    /// Token variables `cce check` reads: the active provider's, or those of
+    /// the global scheme when no provider is active.
+    fn checked_token_vars<'a>(config: &Config, active: Option<&'a Provider>) -> Vec<&'a str> {
+        match active {
+            Some(provider) => provider.token_vars(config.env_scheme()),
+            None => config.env_scheme().token_vars().to_vec(),
+        }
+    }
+
+    /// Runs every setup check and prints the results. Returns `false` when a
+    /// hard failure was found so the caller can exit non-zero.
+    pub fn run_doctor() -> Result<bool> {
+        println!("{}", "🩺 Checking cce setup".blue().bold());
+        println!();
+
+        let mut doctor = Doctor::default();
+        let config_path = Config::get_config_path()?;
+        let path = config_path.display().to_string();
+
+        if !config_path.exists() {
+            doctor.warn(
+                &format!("Config file {} does not exist yet", path),
+                "Add a provider with 'cce add <name> <api_url>' to create it",
+            );
+        } else if let Err(e) = std::fs::read_to_string(&config_path) {
+            doctor.fail(
+                &format!("Config file {} is not readable: {}", path, e),
+                "Check the file's owner and permissions",
+            );
+        } else {
+            doctor.pass(&format!("Config file {} is readable", path));
+            match config::loose_permissions(&config_path) {
+                Some(mode) => doctor.warn(
+                    &format!(
+                        "Config file {} is accessible by other users (mode {:o})",
+                        path, mode
+                    ),
+                    &format!(
+                        "Run 'chmod 600 {}'; cce writes it that way on every save",
+                        path
+                    ),
+                ),
+                None => doctor.pass(&format!("Config file {} is private to its owner", path)),
+            }
+        }
+
+        match config_path.parent().filter(|dir| dir.exists()) {
+            Some(dir) => {
+                let probe = dir.join(format!(".doctor.{}.tmp", std::process::id()));
+                match std::fs::write(&probe, b"") {
+                    Ok(()) => {
+                        let _ = std::fs::remove_file(&probe);
+                        doctor.pass(&format!("Config directory {} is writable", dir.display()));
+                    }
+                    Err(e) => doctor.fail(
+                        &format!("Config directory {} is not writable: {}", dir.display(), e),
+                        "Fix the directory's permissions or point CCE_CONFIG elsewhere",
+                    ),
+                }
+            }
+            None => doctor.pass("Config directory will be created on first save"),
+        }
+
+        match Config::load() {
+            Ok(config) => Self::doctor_config(&mut doctor, &config),
+            Err(e) => doctor.fail(
+                &format!("Config file could not be loaded: {:#}", e),
+                "Fix the file by hand, or restore a backup such as config.toml.bak",
+            ),
+        }
+
+        if Self::shellenv_loaded() {
+            doctor.pass("Shell integration is loaded");
+        } else if Self::shell_integration_active() {
+            doctor.pass("CCE_SHELL_INTEGRATION=1 is set");
+        } else {
+            doctor.warn(
+                "Shell integration is not loaded in this shell",
+                "Run 'cce install' and restart your terminal, or add eval \"$(cce shellenv)\" to your shell profile",
+            );
+        }
+
+        println!();
+        if doctor.failures > 0 {
+            println!(
+                "{} {} problem(s) found",
+                "❌".red(),
+                doctor.failures.to_string().red().bold()
+            );
+        } else if doctor.warnings > 0 {
+            println!(
+                "{} No problems found, {} warning(s)",
+                "⚠️".yellow(),
+                doctor.warnings
+            );
+        } else {
+            println!("{} Everything looks good", "✅".green());
+        }
+
+        Ok(doctor.failures == 0)
+    }
+
+    fn doctor_config(doctor: &mut Doctor, config: &Config) {
+        let current = config.current_provider.as_ref();
+        match current.map(|name| (name, config.providers.get(name))) {
+            None => doctor.warn(
+                "No current provider is selected",
+                "Pick one with 'cce use <name>'",
+            ),
+            Some((name, None)) => doctor.fail(
+                &format!("Current provider '{}' does not exist", name),
+                "Pick another one with 'cce use <name>'",
+            ),
+            Some((name, Some(provider))) => {
+                doctor.pass(&format!("Current provider '{}' exists", name));
+
+                // `fast` compares everything but secrets, so nothing is prompted for
+                match Self::env_status(config, provider, true) {
+                    Ok(EnvStatus::Match) => doctor.pass("Environment variables match the config"),
+                    Ok(EnvStatus::Mismatch) => doctor.warn(
+                        "Environment variables differ from the config",
+                        &format!("Run 'cce use {}' to export them again", name),
+                    ),
+                    Ok(EnvStatus::NotSet) => doctor.warn(
+                        "Environment variables are not set in this shell",
+                        &format!("Run 'cce use {}' to export them", name),
+                    ),
+                    Err(e) => doctor.warn(
+                        &format!("Could not compare environment variables: {:#}", e),
+                        "Run 'cce check' for details",
+                    ),
+                }
+            }
+        }
+
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+        let mut invalid = 0;
+        for name in &names {
+            let provider = &config.providers[*name];
+            // Not a hard failure: `--no-validate` deliberately allows these
+            if let Err(e) = Self::normalize_api_url(&provider.api_url, true) {
+                invalid += 1;
+                doctor.warn(
+                    &format!("Provider '{}': {:#}", name, e),
+                    &format!(
+                        "Fix it with 'cce edit {} --url <url>' unless it was added with --no-validate on purpose",
+                        name
+                    ),
+                );
+            }
+        }
+        if invalid == 0 {
+            doctor.pass(&format!("All {} provider URL(s) are valid", names.len()));
+        }
+
+        let mut expiring = 0;
+        for name in &names {
+            let provider = &config.providers[*name];
+            let Some(expiry) = provider.expiry() else {
+                continue;
+            };
+            expiring += 1;
+            let verb = match expiry {
+                Expiry::Expired => "expired on",
+                Expiry::Soon => "expires on",
+            };
+            let label = format!(
+                "Provider '{}': token {} {}",
+                name,
+                verb,
+                Self::expiry_text(provider).unwrap_or_default()
+            );
+            let hint = format!(
+                "Replace it with 'cce rotate {}', then set the new date with 'cce edit {} --expires <date>'",
+                name, name
+            );
+            match expiry {
+                Expiry::Expired => doctor.fail(&label, &hint),
+                Expiry::Soon => doctor.warn(&label, &hint),
+            }
+        }
+        let dated = names
+            .iter()
+            .filter(|name| config.providers[**name].expires_at.is_some())
+            .count();
+        if dated > 0 && expiring == 0 {
+            doctor.pass(&format!(
+                "No provider token expires within {} days",
+                config::EXPIRY_WARNING_DAYS
+            ));
+        }
+
+        for (api_url, names) in Self::duplicate_urls(config) {
+            doctor.warn(
+                &format!(
+                    "{} share the API URL {}",
+                    Self::quoted_list(&names),
+                    api_url
+                ),
+                "Fine if they use different tokens on purpose; otherwise remove one with 'cce delete <name>'",
+            );
+        }
+    }
+
+    /// Prints a JSON Schema of the config file. It is derived from the
+    /// config types, so it always matches the fields this version reads.
+    pub fn print_config_schema() -> Result<()> {
+        let schema = schemars::schema_for!(Config);
+        let json = serde_json::to_string_pretty(&schema)
+            .with_context(|| "Failed to serialize the config schema")?;
+        println!("{}", json);
+        Ok(())
+    }
+
+    /// Lints the config file without loading it the usual way, so nothing
+    /// is migrated, repaired, or saved. Problems are reported with the line
+    /// they are on when the file is TOML. Returns whether none were found.
+    pub fn validate_config() -> Result<bool> {
+        let config_path = Config::get_config_path()?;
+        println!(
+            "{} Validating {}",
+            "🔍".blue(),
+            config_path.display().to_string().bold()
+        );
+        println!();
+
+        let mut doctor = Doctor::default();
+        let content = match std::fs::read_to_string(&config_path) {
+            Ok(content) => content,
+            Err(e) => {
+                doctor.fail(
+                    &format!("Config file cannot be read: {}", e),
+                    "Check the path given with --config or CCE_CONFIG",
+                );
+                return Ok(Self::validate_summary(&doctor));
+            }
+        };
+
+        let format = ConfigFormat::detect(&config_path)?;
+        let config: Config = match format.parse(&content) {
+            Ok(config) => config,
+            Err(e) => {
+                doctor.fail(&format!("{:#}", e), "Fix the syntax or field shown above");
+                return Ok(Self::validate_summary(&doctor));
+            }
+        };
+        doctor.pass(match format {
+            ConfigFormat::Toml => "Parsed as TOML",
+            ConfigFormat::Yaml => "Parsed as YAML",
+        });
+
+        // Spans are only available for TOML
+        let document = match format {
+            ConfigFormat::Toml => content.parse::<toml_edit::ImDocument<String>>().ok(),
+            ConfigFormat::Yaml => None,
+        };
+        let at = |path: &[&str]| -> String {
+            document
+                .as_ref()
+                .and_then(|document| Self::toml_line(document, path))
+                .map(|line| format!(" (line {})", line))
+                .unwrap_or_default()
+        };
+
+        match config.version.cmp(&config::CONFIG_VERSION) {
+            std::cmp::Ordering::Greater => doctor.fail(
+                &format!(
+                    "Schema version {} is newer than this cce supports ({}){}",
+                    config.version,
+                    config::CONFIG_VERSION,
+                    at(&["version"])
+                ),
+                "Upgrade cce",
+            ),
+            std::cmp::Ordering::Less => doctor.warn(
+                &format!(
+                    "Schema version {} is outdated{}",
+                    config.version,
+                    at(&["version"])
+                ),
+                "The next command that loads the config upgrades it and keeps a .bak copy",
+            ),
+            std::cmp::Ordering::Equal => doctor.pass(&format!("Schema version {}", config.version)),
+        }
+
+        if config.encrypted && (config.salt.is_none() || config.key_check.is_none()) {
+            doctor.fail(
+                "Tokens are marked encrypted, but salt or key_check is missing",
+                "Restore the config from a backup; the tokens cannot be decrypted without them",
+            );
+        }
+
+        match config.clone().resolve_inheritance() {
+            Ok(()) => doctor.pass("Provider inheritance resolves"),
+            Err(e) => doctor.fail(
+                &format!("{:#}", e),
+                "Fix 'extends' or add the missing api_url",
+            ),
+        }
+
+        let mut groups: Vec<(Vec<&str>, &HashMap<String, Provider>)> =
+            vec![(vec!["providers"], &config.providers)];
+        let mut profile_names: Vec<&String> = config.profiles.keys().collect();
+        profile_names.sort();
+        for name in profile_names {
+            groups.push((
+                vec!["profiles", name, "providers"],
+                &config.profiles[name].providers,
+            ));
+        }
+
+        let failures = doctor.failures;
+        let mut count = 0;
+        for (prefix, providers) in &groups {
+            let mut names: Vec<&String> = providers.keys().collect();
+            names.sort();
+            for name in names {
+                count += 1;
+                let provider = &providers[name];
+                let path = |field: &'static str| -> Vec<&str> {
+                    let mut path = prefix.clone();
+                    path.push(name);
+                    if !field.is_empty() {
+                        path.push(field);
+                    }
+                    path
+                };
+
+                if provider.name != *name {
+                    doctor.fail(
+                        &format!(
+                            "Provider '{}' has name = '{}'{}",
+                            name,
+                            provider.name,
+                            at(&path("name"))
+                        ),
+                        "The name must match the table key",
+                    );
+                }
+
+                // Inherited URLs are checked where they are defined
+                if provider.extends.is_none() || !provider.api_url.is_empty() {
+                    if let Err(e) = Url::parse(&provider.api_url) {
+                        if !is_env_template(&provider.api_url) {
+                            doctor.fail(
+                                &format!(
+                                    "Provider '{}': API URL '{}' does not parse: {}{}",
+                                    name,
+                                    provider.api_url,
+                                    e,
+                                    at(&path("api_url"))
+                                ),
+                                &format!(
+                                    "Fix it with 'cce edit {} --url <url>' (even --no-validate URLs must parse)",
+                                    name
+                                ),
+                            );
+                        }
+                    } else if let Err(e) = Self::normalize_api_url(&provider.api_url, true) {
+                        doctor.warn(
+                            &format!("Provider '{}': {:#}{}", name, e, at(&path("api_url"))),
+                            "Fine if it was added with --no-validate on purpose",
+                        );
+                    }
+                }
+
+                for (field, proxy) in [
+                    ("http_proxy", &provider.http_proxy),
+                    ("https_proxy", &provider.https_proxy),
+                ] {
+                    if let Some(Err(e)) = proxy.as_deref().map(Url::parse) {
+                        doctor.fail(
+                            &format!(
+                                "Provider '{}': {} does not parse: {}{}",
+                                name,
+                                field,
+                                e,
+                                at(&path(field))
+                            ),
+                            &format!("Fix it with 'cce set-proxy {} <url>'", name),
+                        );
+                    }
+                }
+
+                if !provider.tokens.is_empty() && provider.active_token >= provider.tokens.len() {
+                    doctor.fail(
+                        &format!(
+                            "Provider '{}': active_token {} is out of range for {} token(s){}",
+                            name,
+                            provider.active_token,
+                            provider.tokens.len(),
+                            at(&path("active_token"))
+                        ),
+                        "Set active_token to an index within tokens",
+                    );
+                }
+
+                if provider.weight == Some(0) {
+                    doctor.fail(
+                        &format!("Provider '{}': weight is 0{}", name, at(&path("weight"))),
+                        &format!(
+                            "Weights must be at least 1; fix it with 'cce edit {} --weight <n>'",
+                            name
+                        ),
+                    );
+                }
+
+                if provider.expiry() == Some(Expiry::Expired) {
+                    doctor.warn(
+                        &format!(
+                            "Provider '{}': token expired on {}{}",
+                            name,
+                            Self::expiry_text(provider).unwrap_or_default(),
+                            at(&path("expires_at"))
+                        ),
+                        &format!("Rotate it with 'cce rotate {}'", name),
+                    );
+                }
+            }
+        }
+        if doctor.failures == failures {
+            doctor.pass(&format!("{} provider(s) are well-formed", count));
+        }
+
+        let failures = doctor.failures;
+        let in_any_profile = |name: &str| {
+            groups
+                .iter()
+                .any(|(_, providers)| providers.contains_key(name))
+        };
+        for (field, value) in [
+            ("current_provider", &config.current_provider),
+            ("default_provider", &config.default_provider),
+        ] {
+            if let Some(name) = value
+                .as_deref()
+                .filter(|name| !config.providers.contains_key(*name))
+            {
+                doctor.fail(
+                    &format!("{} '{}' does not exist{}", field, name, at(&[field])),
+                    "Point it at a provider of the active profile or remove the line",
+                );
+            }
+        }
+        for (profile_name, profile) in &config.profiles {
+            if let Some(name) = profile
+                .current_provider
+                .as_deref()
+                .filter(|name| !profile.providers.contains_key(*name))
+            {
+                doctor.fail(
+                    &format!(
+                        "current_provider '{}' of profile '{}' does not exist{}",
+                        name,
+                        profile_name,
+                        at(&["profiles", profile_name, "current_provider"])
+                    ),
+                    "Point it at a provider of that profile or remove the line",
+                );
+            }
+        }
+
+        let mut aliases: Vec<&String> = config.aliases.keys().collect();
+        aliases.sort();
+        for alias in aliases {
+            match config.resolve_name(alias) {
+                Err(e) => doctor.fail(
+                    &format!("{:#}{}", e, at(&["aliases", alias])),
+                    &format!("Remove it with 'cce alias --remove {}'", alias),
+                ),
+                Ok(target) if !in_any_profile(&target) => doctor.fail(
+                    &format!(
+                        "Alias '{}' points to '{}', which does not exist{}",
+                        alias,
+                        target,
+                        at(&["aliases", alias])
+                    ),
+                    &format!("Remove it with 'cce alias --remove {}'", alias),
+                ),
+                Ok(_) => {}
+            }
+        }
+
+        let mut command_aliases: Vec<&String> = config.command_aliases.keys().collect();
+        command_aliases.sort();
+        for name in command_aliases {
+            if cli::is_builtin_command(name) {
+                doctor.warn(
+                    &format!(
+                        "Command alias '{}' is a built-in command and never runs{}",
+                        name,
+                        at(&["command_aliases", name])
+                    ),
+                    &format!("Rename it, e.g. with 'cce alias-cmd remove {}'", name),
+                );
+            } else if config.command_aliases[name].is_empty() {
+                doctor.fail(
+                    &format!(
+                        "Command alias '{}' is empty{}",
+                        name,
+                        at(&["command_aliases", name])
+                    ),
+                    "Give it a command or remove it",
+                );
+            } else if let Err(e) = cli::expand_command_alias(&config.command_aliases, name) {
+                doctor.fail(
+                    &format!("{:#}{}", e, at(&["command_aliases", name])),
+                    &format!("Remove it with 'cce alias-cmd remove {}'", name),
+                );
+            }
+        }
+        if doctor.failures == failures {
+            doctor.pass("References to providers and aliases resolve");
+        }
+
+        Ok(Self::validate_summary(&doctor))
+    }
+
+    fn validate_summary(doctor: &Doctor) -> bool {
+        println!();
+        if doctor.failures > 0 {
+            println!(
+                "{} {} problem(s) found; nothing was changed",
+                "❌".red(),
+                doctor.failures.to_string().red().bold()
+            );
+        } else if doctor.warnings > 0 {
+            println!(
+                "{} Config is valid, {} warning(s)",
+                "⚠️".yellow(),
+                doctor.warnings
+            );
+        } else {
+            println!("{} Config is valid", "✅".green());
+        }
+        doctor.failures == 0
+    }
+
+    /// Line of the key at `path` in a TOML document, or of the closest
+    /// parent that can be located.
+    fn toml_line(document: &toml_edit::ImDocument<String>, path: &[&str]) -> Option<usize> {
+        let mut table: &dyn toml_edit::TableLike = document.as_table();
+        let mut offset = None;
+        for key in path {
+            let Some((key, item)) = table.get_key_value(key) else {
+                break;
+            };
+            offset = key
+                .span()
+                .or_else(|| item.span())
+                .map(|span| span.start)
+                .or(offset);
+            match item.as_table_like() {
+                Some(child) => table = child,
+                None => break,
+            }
+        }
+        let offset = offset?;
+        Some(document.raw()[..offset].matches('\n').count() + 1)
+    }
+
+    pub fn list_templates() -> Result<()> {
+        println!("{}", "Available provider templates:".blue().bold());
+        println!();
+
+        for template in TEMPLATES {
+            println!(
+                "  {} - {}",
+                template.name.green().bold(),
+                template.description
+            );
+            println!("    API URL: {}", template.api_url.cyan());
+            if let Some(model) = template.model {
+                println!("    Model: {}", model.cyan());
+            }
+        }
+
+        println!();
+        status!(
+            "{} Use one with 'cce add <name> --template <template>'",
+            "💡".yellow()
+        );
+        Ok(())
+    }
+
+    pub fn show_history(config: &Config) -> Result<()> {
+        if config.history.is_empty() {
+            println!("{}", "No provider switches recorded yet".yellow());
+            return Ok(());
+        }
+
+        println!(
+            "{}",
+            "Provider switch history (newest first):".blue().bold()
+        );
+        println!();
+
+        for entry in config.history.iter().rev() {
+            println!(
+                "  {}  {} {}",
+                entry
+                    .used_at
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+                    .dimmed(),
+                entry.provider.green(),
+                format!("({})", Self::format_last_used(Some(entry.used_at))).dimmed()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn clear_history(config: &mut Config) -> Result<()> {
+        config.history.clear();
+        config.save()?;
+        status!("{} Cleared provider switch history", "🧹".green());
+        Ok(())
+    }
+
+    pub fn create_profile(config: &mut Config, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("Profile name cannot be empty"));
+        }
+
+        if config.has_profile(name) {
+            return Err(CceError::ProfileExists(name.to_string()).into());
+        }
+
+        config.profiles.insert(name.to_string(), Profile::default());
+        config.save()?;
+
+        status!("{} Created profile '{}'", "✅".green(), name.green().bold());
+        status!(
+            "{} Run 'cce profile use {}' to switch to it",
+            "💡".yellow(),
+            name
+        );
+        Ok(())
+    }
+
+    pub fn use_profile(config: &mut Config, name: &str) -> Result<()> {
+        if name == config.active_profile() {
+            status!("{} Profile '{}' is already active", "ℹ️".blue(), name);
+            return Ok(());
+        }
+
+        if !config.switch_profile(name) {
+            return Err(CceError::ProfileNotFound(name.to_string()).into());
+        }
+        config.save()?;
+
+        status!(
+            "{} Switched to profile '{}'",
+            "⚡".green(),
+            name.green().bold()
+        );
+        match &config.current_provider {
+            Some(current) => status!(
+                "{} Run 'cce use {}' to apply its provider in this terminal",
+                "💡".yellow(),
+                current
+            ),
+            None => status!(
+                "{} No service provider is active in this profile",
+                "ℹ️".blue()
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn list_profiles(config: &Config) -> Result<()> {
+        println!("{}", "Profiles:".blue().bold());
+        println!();
+
+        for name in config.profile_names() {
+            let count = config.provider_count(name);
+            if name == config.active_profile() {
+                println!(
+                    "  {} {} ({} providers)",
+                    "●".green(),
+                    name.green().bold(),
+                    count
+                );
+            } else {
+                println!("  {} {} ({} providers)", "○".white(), name.white(), count);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_profile(config: &mut Config, name: &str) -> Result<()> {
+        if name == config.active_profile() {
+            return Err(anyhow!(
+                "Cannot remove the active profile '{}'; switch to another profile first",
+                name
+            ));
+        }
+
+        let Some(profile) = config.profiles.remove(name) else {
+            return Err(CceError::ProfileNotFound(name.to_string()).into());
+        };
+        for provider in profile.providers.values() {
+            if secret_store::is_keyring_token(&provider.token) {
+                config.forget_profile_keyring_entry(name, &provider.name);
+            }
+        }
+        config.save()?;
+
+        status!("{} Removed profile '{}'", "🗑️".green(), name.yellow());
+        Ok(())
+    }
+
+    pub fn add_alias(config: &mut Config, alias: &str, target: &str) -> Result<()> {
+        if config.providers.contains_key(alias) {
+            return Err(anyhow!(
+                "'{}' is already a service provider name and cannot be used as an alias",
+                alias
+            ));
+        }
+
+        let previous = config.aliases.insert(alias.to_string(), target.to_string());
+        let resolved = match config.resolve_name(alias) {
+            Ok(resolved) if config.providers.contains_key(&resolved) => Ok(resolved),
+            Ok(_) => Err(anyhow!("Service provider '{}' does not exist", target)),
+            Err(e) => Err(e),
+        };
+
+        let resolved = match resolved {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                // Roll back so a bad alias never reaches the config file
+                match previous {
+                    Some(previous) => config.aliases.insert(alias.to_string(), previous),
+                    None => config.aliases.remove(alias),
+                };
+                return Err(e);
+            }
+        };
+
+        config.save()?;
+
+        status!(
+            "{} Alias '{}' now points to '{}'",
+            "🔗".green(),
+            alias.green().bold(),
+            resolved.cyan()
+        );
+        Ok(())
+    }
+
+    pub fn remove_alias(config: &mut Config, alias: &str) -> Result<()> {
+        if config.aliases.remove(alias).is_none() {
+            return Err(CceError::AliasNotFound(alias.to_string()).into());
+        }
+
+        config.save()?;
+        status!("{} Removed alias '{}'", "🗑️".green(), alias.green().bold());
+        Ok(())
+    }
+
+    pub fn list_command_aliases(config: &Config) {
+        if config.command_aliases.is_empty() {
+            println!(
+                "{}",
+                "No command aliases; add one with 'cce alias-cmd add <name> <command>...'".yellow()
+            );
+            return;
+        }
+
+        let mut names: Vec<&String> = config.command_aliases.keys().collect();
+        names.sort();
+        for name in names {
+            println!(
+                "  {} = cce {}",
+                name.green().bold(),
+                Self::command_line(&config.command_aliases[name]).cyan()
+            );
+        }
+    }
+
+    pub fn add_command_alias(
+        config: &mut Config,
+        name: &str,
+        mut command: Vec<String>,
+    ) -> Result<()> {
+        if name.is_empty() || name.starts_with('-') || name.contains(char::is_whitespace) {
+            return Err(anyhow!(
+                "Invalid command alias '{}': names must not start with '-' or contain spaces",
+                name
+            ));
+        }
+        if cli::is_builtin_command(name) {
+            return Err(anyhow!(
+                "'{}' is a built-in command; built-ins always win, so the alias would never run",
+                name
+            ));
+        }
+        // `cce alias-cmd add prod cce use production` reads naturally too
+        if command.first().is_some_and(|word| word == "cce") {
+            command.remove(0);
+        }
+        if command.is_empty() {
+            return Err(anyhow!("Command alias '{}' needs a command to run", name));
+        }
+
+        let previous = config.command_aliases.insert(name.to_string(), command);
+        let expanded = match cli::expand_command_alias(&config.command_aliases, name) {
+            Ok(expanded) => expanded.unwrap_or_default(),
+            Err(e) => {
+                // Roll back so a bad alias never reaches the config file
+                match previous {
+                    Some(previous) => config.command_aliases.insert(name.to_string(), previous),
+                    None => config.command_aliases.remove(name),
+                };
+                return Err(e);
+            }
+        };
+
+        config.save()?;
+
+        status!(
+            "{} 'cce {}' now runs 'cce {}'",
+            "🔗".green(),
+            name.green().bold(),
+            Self::command_line(&expanded).cyan()
+        );
+        if !cli::is_builtin_command(&expanded[0]) {
+            println!(
+                "{} '{}' is not a cce command yet; add it as a command alias before using 'cce {}'",
+                "⚠️".yellow(),
+                expanded[0],
+                name
+            );
+        }
+        Ok(())
+    }
+
+    pub fn remove_command_alias(config: &mut Config, name: &str) -> Result<()> {
+        if config.command_aliases.remove(name).is_none() {
+            return Err(CceError::CommandAliasNotFound(name.to_string()).into());
+        }
+
+        config.save()?;
+        status!(
+            "{} Removed command alias '{}'",
+            "🗑️".green(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Joins command words for display, quoting those a shell would split.
+    fn command_line(words: &[String]) -> String {
+        words
+            .iter()
+            .map(|word| {
+                if word.is_empty()
+                    || word.contains(|c: char| c.is_whitespace() || "'\"$`\\".contains(c))
+                {
+                    Shell::posix_quote(word)
+                } else {
+                    word.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Tags are case-insensitive single words, stored lowercase.
+    fn normalize_tag(tag: &str) -> Result<String> {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+            return Err(anyhow!(
+                "Invalid tag '{}': tags must be non-empty and contain no spaces or commas",
+                tag
+            ));
+        }
+        Ok(tag)
+    }
+
+    pub fn tag_provider(config: &mut Config, name: &str, tags: &[String]) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let tags = tags
+            .iter()
+            .map(|tag| Self::normalize_tag(tag))
+            .collect::<Result<Vec<_>>>()?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        provider.tags.extend(tags);
+        provider.tags.sort();
+        provider.tags.dedup();
+        let joined = provider.tags.join(", ");
+        config.save()?;
+
+        status!(
+            "{} Service provider '{}' is now tagged {}",
+            "🏷️".green(),
+            name.green().bold(),
+            joined.cyan()
+        );
+        Ok(())
+    }
+
+    pub fn untag_provider(config: &mut Config, name: &str, tags: &[String]) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        let tags: Vec<String> = tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+        let before = provider.tags.len();
+        provider.tags.retain(|tag| !tags.contains(tag));
+        if provider.tags.len() == before {
+            status!(
+                "{} Service provider '{}' has none of these tags",
+                "ℹ️".blue(),
+                name.blue().bold()
+            );
+            return Ok(());
+        }
+        config.save()?;
+
+        status!(
+            "{} Removed {} tag(s) from service provider '{}'",
+            "🧹".green(),
+            before - config.providers[name].tags.len(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Sets (or with `None`, removes) the model exported for a provider.
+    pub fn set_model(config: &mut Config, name: &str, model: Option<String>) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        if model
+            .as_deref()
+            .is_some_and(|model| model.trim().is_empty())
+        {
+            return Err(anyhow!(
+                "Model name cannot be empty; use --clear to remove it"
+            ));
+        }
+
+        provider.model = model.clone();
+        config.save()?;
+
+        match model {
+            Some(model) => status!(
+                "{} Service provider '{}' now uses model '{}'",
+                "✅".green(),
+                name.green().bold(),
+                model.cyan()
+            ),
+            None => status!(
+                "{} Removed the model from service provider '{}'",
+                "🧹".green(),
+                name.green().bold()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Sets or clears the proxies exported for a provider; `scheme` limits
+    /// the change to the HTTP or HTTPS proxy, otherwise both are changed.
+    pub fn set_proxy(
+        config: &mut Config,
+        name: &str,
+        proxy: Option<String>,
+        scheme: Option<ProxyScheme>,
+    ) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        if let Some(proxy) = &proxy {
+            let parsed =
+                Url::parse(proxy).with_context(|| format!("Invalid proxy URL '{}'", proxy))?;
+            if parsed.host_str().is_none() {
+                return Err(anyhow!("Proxy URL '{}' has no host", proxy));
+            }
+        }
+
+        if scheme != Some(ProxyScheme::Https) {
+            provider.http_proxy = proxy.clone();
+        }
+        if scheme != Some(ProxyScheme::Http) {
+            provider.https_proxy = proxy.clone();
+        }
+        config.save()?;
+
+        let which = match scheme {
+            Some(ProxyScheme::Http) => "HTTP proxy",
+            Some(ProxyScheme::Https) => "HTTPS proxy",
+            None => "proxy",
+        };
+        match proxy {
+            Some(proxy) => status!(
+                "{} Set {} for service provider '{}' to {}",
+                "✅".green(),
+                which,
+                name.green().bold(),
+                proxy.cyan()
+            ),
+            None => status!(
+                "{} Cleared {} for service provider '{}'",
+                "🧹".green(),
+                which,
+                name.green().bold()
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn set_extra_env(config: &mut Config, name: &str, assignment: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        let (key, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected KEY=VALUE, got '{}'", assignment))?;
+        Self::validate_env_var_name(key)?;
+
+        provider
+            .extra_env
+            .insert(key.to_string(), value.to_string());
+        config.save()?;
+
+        status!(
+            "{} Set {} for service provider '{}'",
+            "✅".green(),
+            key.cyan(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    pub fn unset_extra_env(config: &mut Config, name: &str, key: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        if provider.extra_env.remove(key).is_none() {
+            status!(
+                "{} {} is not set for service provider '{}'",
+                "ℹ️".blue(),
+                key.cyan(),
+                name.blue().bold()
+            );
+            return Ok(());
+        }
+        config.save()?;
+
+        status!(
+            "{} Removed {} from service provider '{}'",
+            "🗑️".green(),
+            key.cyan(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Sets a request header from `Name: Value`, replacing a header of the
+    /// same name in any case.
+    pub fn set_header(config: &mut Config, name: &str, header: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        let (header, value) = header
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected 'Name: Value', got '{}'", header))?;
+        let (header, value) = (header.trim(), value.trim());
+        Self::validate_header(header, value)?;
+
+        provider
+            .headers
+            .retain(|existing, _| !existing.eq_ignore_ascii_case(header));
+        provider
+            .headers
+            .insert(header.to_string(), value.to_string());
+        config.save()?;
+
+        status!(
+            "{} Set header {} for service provider '{}'",
+            "✅".green(),
+            header.cyan(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    pub fn unset_header(config: &mut Config, name: &str, header: &str) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get_mut(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        let Some(header) = provider
+            .headers
+            .keys()
+            .find(|existing| existing.eq_ignore_ascii_case(header))
+            .cloned()
+        else {
+            status!(
+                "{} Header {} is not set for service provider '{}'",
+                "ℹ️".blue(),
+                header.cyan(),
+                name.blue().bold()
+            );
+            return Ok(());
+        };
+        provider.headers.remove(&header);
+        config.save()?;
+
+        status!(
+            "{} Removed header {} from service provider '{}'",
+            "🗑️".green(),
+            header.cyan(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    /// Header names are HTTP tokens; values may not hold line breaks, which
+    /// separate the headers in `ANTHROPIC_CUSTOM_HEADERS`.
+    fn validate_header(header: &str, value: &str) -> Result<()> {
+        let valid_name = !header.is_empty()
+            && header
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+        if !valid_name {
+            return Err(anyhow!(
+                "Invalid header name '{}': use letters, digits, and - (e.g. X-Tenant-Id)",
+                header
+            ));
+        }
+        if value.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(anyhow!(
+                "Invalid value for header '{}': line breaks and control characters are not allowed",
+                header
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_env_var_name(key: &str) -> Result<()> {
+        let mut chars = key.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid environment variable name '{}'", key))
+        }
+    }
+
+    pub fn set_default_provider(config: &mut Config, name: &str) -> Result<()> {
+        if !config.providers.contains_key(name) {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        }
+
+        config.default_provider = Some(name.to_string());
+        config.save()?;
+
+        status!(
+            "{} '{}' will be applied automatically in new shells",
+            "⭐".green(),
+            name.green().bold()
+        );
+        Ok(())
+    }
+
+    pub fn clear_default_provider(config: &mut Config) -> Result<()> {
+        if config.default_provider.take().is_none() {
+            status!("{} No default service provider is set", "ℹ️".blue());
+            return Ok(());
+        }
+
+        config.save()?;
+        status!("{} Cleared the default service provider", "🧹".green());
+        Ok(())
+    }
+
+    pub fn show_default_provider(config: &Config) -> Result<()> {
+        match &config.default_provider {
+            Some(default) => println!("{}", default),
+            None => status!("{} No default service provider is set", "ℹ️".blue()),
+        }
+        Ok(())
+    }
+
+    /// Prints export commands for the default provider without touching the
+    /// saved configuration; used by the shell integration on startup.
+    pub fn emit_default_provider(config: &Config, shell: Option<Shell>) -> Result<()> {
+        let Some(default) = &config.default_provider else {
+            return Ok(());
+        };
+
+        let Some(provider) = config.providers.get(default) else {
+            eprintln!(
+                "{} Default service provider '{}' no longer exists; run 'cce default --clear'",
+                "⚠️".yellow(),
+                default.yellow()
+            );
+            return Ok(());
+        };
+
+        let provider = config.resolved_provider(provider)?;
+        Self::emit_export_commands(
+            &provider,
+            config.env_scheme(),
+            shell.unwrap_or_else(Shell::detect),
+        );
+        Ok(())
+    }
+
+    pub fn test_provider(config: &Config, name: &str, timeout: Duration) -> Result<()> {
+        let name = &config.resolve_name(name)?;
+        let Some(provider) = config.providers.get(name) else {
+            return Err(CceError::ProviderNotFound(name.to_string()).into());
+        };
+
+        println!(
+            "{} Testing service provider '{}'",
+            "🔌".blue(),
+            name.blue().bold()
+        );
+        println!("  API URL: {}", provider.api_url.cyan());
+
+        let resolved = config.resolved_provider(provider)?;
+        if let Some(proxy) = provider.proxy_for_api() {
+            println!("  Proxy: {}", proxy.cyan());
+        }
+        let result = connectivity::probe(
+            &resolved.api_url,
+            &resolved.token,
+            provider.proxy_for_api(),
+            &provider.headers,
+            timeout,
+        )?;
+
+        let mut cache = HealthCache::load();
+        cache.record(name, &provider.api_url, &result);
+        if let Err(e) = cache.save() {
+            eprintln!("{} {:#}", "⚠️".yellow(), e);
+        }
+
+        println!("  Status: {}", Self::describe_probe(&result.status));
+        println!("  Latency: {} ms", result.latency.as_millis());
+        Self::print_rate_limit_hint(provider, &result.status);
+        Ok(())
+    }
+
+    /// Points a rate-limited provider at its backup tokens, if it has any.
+    fn print_rate_limit_hint(provider: &Provider, status: &connectivity::ProbeStatus) {
+        let connectivity::ProbeStatus::Unexpected(code) = status else {
+            return;
+        };
+        if *code != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return;
+        }
+
+        if provider.tokens.len() > 1 {
+            println!(
+                "  {} Rate limited; switch to the next token with 'cce token next {}'",
+                "💡".yellow(),
+                provider.name
+            );
+        } else {
+            println!(
+                "  {} Rate limited; add a backup token with 'cce token add {}'",
+                "💡".yellow(),
+                provider.name
+            );
+        }
+    }
+
+    /// Opens the provider's web console in the default browser, falling back
+    /// to the origin of its API URL when no console is configured.
+    pub fn open_console(config: &Config, name: Option<&str>) -> Result<()> {
+        let name = match name {
+            Some(name) => config.resolve_name(name)?,
+            None => config.current_provider.clone().ok_or_else(|| {
+                anyhow!("No current provider; pass a name or switch with 'cce use <name>'")
+            })?,
+        };
+        let Some(provider) = config.providers.get(&name) else {
+            return Err(CceError::ProviderNotFound(name).into());
+        };
+
+        let url = match &provider.console_url {
+            Some(url) => url.clone(),
+            None => Url::parse(&provider.api_url)
+                .with_context(|| format!("Invalid API URL '{}'", provider.api_url))?
+                .origin()
+                .ascii_serialization(),
+        };
+
+        println!("{} Opening {}", "🌐".blue(), url.cyan());
+        open::that(&url).with_context(|| {
+            format!(
+                "Could not launch a browser (no graphical session?); visit {} manually",
+                url
+            )
+        })
+    }
+
+    /// Puts the resolved token on the clipboard, then waits `clear_after` and
+    /// clears it again. Without a clipboard the token is printed instead, but
+    /// only with `force`.
+    pub fn copy_token(
+        config: &Config,
+        name: Option<&str>,
+        clear_after: Option<Duration>,
+        force: bool,
+    ) -> Result<()> {
+        let name = match name {
+            Some(name) => config.resolve_name(name)?,
+            None => config.current_provider.clone().ok_or_else(|| {
+                anyhow!("No current provider; pass a name or switch with 'cce use <name>'")
+            })?,
+        };
+        let Some(provider) = config.providers.get(&name) else {
+            return Err(CceError::ProviderNotFound(name).into());
+        };
+        let token = config.resolved_provider(provider)?.token;
+
+        let copied = match clipboard::copy(&token) {
+            Ok(copied) => copied,
+            Err(e) if force => {
+                eprintln!(
+                    "{} {:#}; printing the token of '{}' instead",
+                    "⚠️".yellow(),
+                    e,
+                    name
+                );
+                println!("{}", token);
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(e.context(format!(
+                    "Cannot copy the token of '{}'; pass --force to print it instead",
+                    name
+                )))
+            }
+        };
+
+        let Some(delay) = clear_after else {
+            status!(
+                "{} Copied the token of '{}' to the clipboard",
+                "📋".green(),
+                name.green().bold()
+            );
+            return Ok(());
+        };
+        status!(
+            "{} Copied the token of '{}' to the clipboard; clearing it in {}s",
+            "📋".green(),
+            name.green().bold(),
+            delay.as_secs()
+        );
+        if copied.clear_after(delay)? {
+            status!("{} Clipboard cleared", "🧹".blue());
+        }
+        Ok(())
+    }
+
+    /// Prints whatever identifying details the provider reports for its
+    /// token, for the named or else the current provider.
+    pub fn whoami(config: &Config, name: Option<&str>, timeout: Duration) -> Result<()> {
+        let name = match name {
+            Some(name) => config.resolve_name(name)?,
+            None => config.current_provider.clone().ok_or_else(|| {
+                anyhow!("No current provider; pass a name or switch with 'cce use <name>'")
+            })?,
+        };
+        let Some(provider) = config.providers.get(&name) else {
+            return Err(CceError::ProviderNotFound(name).into());
+        };
+
+        println!(
+            "{} Account for service provider '{}'",
+            "👤".blue(),
+            name.blue().bold()
+        );
+        println!("  API URL: {}", provider.api_url.cyan());
+
+        let resolved = config.resolved_provider(provider)?;
+        let info = connectivity::account_info(
+            &resolved.api_url,
+            &resolved.token,
+            provider.proxy_for_api(),
+            &provider.headers,
+            timeout,
+        )?;
+
+        println!("  Status: {}", Self::describe_probe(&info.status));
+        if !matches!(info.status, connectivity::ProbeStatus::Ok(_)) {
+            Self::print_rate_limit_hint(provider, &info.status);
+            return Ok(());
+        }
+
+        if info.fields.is_empty() {
+            println!(
+                "  {} unsupported: this provider does not report account details",
+                "ℹ️".blue()
+            );
+        }
+        for (field, value) in &info.fields {
+            println!("  {}: {}", field, value.cyan());
+        }
+        Ok(())
+    }
+
+    /// Tests every provider, up to `jobs` at a time, reusing cached results
+    /// younger than the health TTL unless `refresh` is set. The table is
+    /// printed once all checks are done, so rows never interleave.
+    pub fn test_all_providers(
+        config: &Config,
+        timeout: Duration,
+        refresh: bool,
+        jobs: usize,
+        format: OutputFormat,
+    ) -> Result<()> {
+        if format != OutputFormat::Pretty {
+            return Self::test_all_providers_plain(config, timeout, refresh, jobs, format);
+        }
+        if config.providers.is_empty() {
+            println!("{}", "No service providers configured".yellow());
+            return Ok(());
+        }
+
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+
+        println!(
+            "{} Testing {} service provider(s), up to {} at a time",
+            "🔌".blue(),
+            names.len().to_string().blue().bold(),
+            jobs
+        );
+        println!();
+
+        let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+        let mut healthy = 0;
+        let mut cached = 0;
+        let mut cache = HealthCache::load();
+        let results = Self::check_health(config, &mut cache, &names, timeout, refresh, jobs);
+
+        for (name, result) in names.iter().zip(results) {
+            let (entry, from_cache) = result?;
+
+            if entry.health == Health::Ok {
+                healthy += 1;
+            }
+
+            let age = if from_cache {
+                cached += 1;
+                format!(" (cached {}s ago)", entry.age().as_secs())
+                    .dimmed()
+                    .to_string()
+            } else {
+                String::new()
+            };
+            println!(
+                "  {:<width$}  {:>6} ms  {}{}",
+                name,
+                entry.latency_ms,
+                Self::describe_probe(&entry.status()),
+                age,
+                width = width
+            );
+        }
+
+        cache.prune(config);
+        if let Err(e) = cache.save() {
+            eprintln!("{} {:#}", "⚠️".yellow(), e);
+        }
+
+        println!();
+        println!(
+            "{} {}/{} service provider(s) OK",
+            if healthy == names.len() {
+                "✅".green()
+            } else {
+                "⚠️".yellow()
+            },
+            healthy,
+            names.len()
+        );
+        if cached > 0 {
+            status!(
+                "{} {} result(s) came from the cache; run 'cce test --all --refresh' to re-check them",
+                "ℹ️".blue(),
+                cached
+            );
+        }
+        Ok(())
+    }
+
+    /// `cce test --all` as a table or JSON, with nothing but the results.
+    fn test_all_providers_plain(
+        config: &Config,
+        timeout: Duration,
+        refresh: bool,
+        jobs: usize,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+
+        let mut cache = HealthCache::load();
+        let results = Self::check_health(config, &mut cache, &names, timeout, refresh, jobs);
+        let results = results.into_iter().collect::<Result<Vec<_>>>()?;
+
+        cache.prune(config);
+        if let Err(e) = cache.save() {
+            eprintln!("{} {:#}", "⚠️".yellow(), e);
+        }
+
+        if format == OutputFormat::Json {
+            let summaries: Vec<TestSummary> = names
+                .iter()
+                .zip(&results)
+                .map(|(name, (entry, cached))| TestSummary {
+                    name,
+                    entry,
+                    cached: *cached,
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&summaries)?);
+            return Ok(());
+        }
+
+        // `age` is only set for results reused from the cache
+        let mut table = Table::new(vec!["name", "url", "health", "latency", "age", "detail"]);
+        for (name, (entry, cached)) in names.iter().zip(&results) {
+            let age = if *cached {
+                format!("{}s", entry.age().as_secs())
+            } else {
+                String::new()
+            };
+            let detail = match (&entry.reason, entry.code) {
+                (Some(reason), _) => reason.clone(),
+                (None, Some(code)) => format!("HTTP {}", code),
+                (None, None) => String::new(),
+            };
+            table.push(vec![
+                name.to_string(),
+                config.providers[*name].api_url.clone(),
+                entry.health.as_str().to_string(),
+                format!("{}ms", entry.latency_ms),
+                age,
+                detail,
+            ]);
+        }
+        table.print();
+        Ok(())
+    }
+
+    fn describe_probe(status: &connectivity::ProbeStatus) -> ColoredString {
+        match status {
+            connectivity::ProbeStatus::Ok(code) => {
+                format!("✅ OK (HTTP {})", code.as_u16()).green()
+            }
+            connectivity::ProbeStatus::AuthFailed(code) => {
+                format!("🔑 Auth failed (HTTP {})", code.as_u16()).red()
+            }
+            connectivity::ProbeStatus::Unexpected(code) => {
+                format!("⚠️ Reachable, unexpected response (HTTP {})", code.as_u16()).yellow()
+            }
+            connectivity::ProbeStatus::Unreachable(reason) => {
+                format!("❌ Unreachable ({})", reason).red()
+            }
+        }
+    }
+
+    pub fn backup_config(keep: usize) -> Result<()> {
+        if Config::is_dry_run() {
+            eprintln!(
+                "{} Dry run: nothing was saved. Without --dry-run, cce would back up {:?}",
+                "🔍".blue(),
+                Config::get_config_path()?
+            );
+            return Ok(());
+        }
+
+        let (backup, pruned) = Config::create_backup(keep)?;
+        status!(
+            "{} Backed up the config to {}",
+            "💾".green(),
+            backup.display().to_string().cyan()
+        );
+        if !pruned.is_empty() {
+            println!(
+                "  Removed {} old backup(s), keeping the newest {}",
+                pruned.len(),
+                keep
+            );
+        }
+        Ok(())
+    }
+
+    pub fn list_backups() -> Result<()> {
+        let backups = Config::backup_paths()?;
+        if backups.is_empty() {
+            println!(
+                "{}",
+                "No backups yet; create one with 'cce backup'".yellow()
+            );
+            return Ok(());
+        }
+
+        println!("{}", "Config backups (newest first):".blue().bold());
+        for backup in backups.iter().rev() {
+            let name = backup
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            println!("  {}", name.cyan());
+        }
+        println!();
+        status!(
+            "{} Restore one with 'cce restore --file <name>' or 'cce restore --latest'",
+            "💡".yellow()
+        );
+        Ok(())
+    }
+
+    /// Restores a backup after confirmation.
+    pub fn restore_config(file: Option<&Path>, latest: bool, yes: bool) -> Result<()> {
+        let backup = match file {
+            // A bare name from `cce backup --list` refers to the config directory
+            Some(file) if !file.exists() && file.components().count() == 1 => {
+                let config_path = Config::get_config_path()?;
+                config_path.with_file_name(file)
+            }
+            Some(file) => file.to_path_buf(),
+            None if latest => Config::backup_paths()?
+                .pop()
+                .ok_or_else(|| anyhow!("No backups to restore; create one with 'cce backup'"))?,
+            None => return Err(anyhow!("Pass --file <backup> or --latest")),
+        };
+        if !backup.exists() {
+            return Err(anyhow!("Backup {:?} does not exist", backup));
+        }
+
+        status!(
+            "{} Restoring the config from {}",
+            "♻️".blue(),
+            backup.display().to_string().cyan()
+        );
+
+        if !yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to restore without confirmation; pass --yes"
+                ));
+            }
+
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Replace the current config with this backup?")
+                .default(false)
+                .interact()
+                .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                status!("{} Nothing was restored", "ℹ️".blue());
+                return Ok(());
+            }
+        }
+
+        if Config::is_dry_run() {
+            eprintln!(
+                "{} Dry run: nothing was saved. Without --dry-run, cce would restore {:?}",
+                "🔍".blue(),
+                backup
+            );
+            return Ok(());
+        }
+
+        if let Some(previous) = Config::restore_backup(&backup)? {
+            println!(
+                "  Saved the previous config to {}",
+                previous.display().to_string().cyan()
+            );
+        }
+
+        status!("{} Restored the config", "✅".green());
+        Ok(())
+    }
+
+    pub fn export_config(
+        config: &Config,
+        format: ExportFormat,
+        out: Option<&Path>,
+        with_tokens: bool,
+    ) -> Result<()> {
+        let mut providers = BTreeMap::new();
+        for (name, provider) in &config.providers {
+            let mut exported = provider.clone();
+            if with_tokens {
+                exported.token = config.provider_token(provider)?;
+                exported.tokens = provider
+                    .tokens
+                    .iter()
+                    .map(|token| config.unseal_token(token))
+                    .collect::<Result<_>>()?;
+            } else {
+                exported.token = REDACTED_TOKEN.to_string();
+                exported.tokens.clear();
+                exported.active_token = 0;
+            }
+            providers.insert(name.clone(), exported);
+        }
+
+        let exported = ExportedConfig {
+            providers,
+            current_provider: config.current_provider.clone(),
+        };
+        let content = exported.serialize(format)?;
+
+        let Some(out) = out else {
+            print!("{}", content);
+            if !content.ends_with('\n') {
+                println!();
+            }
+            return Ok(());
+        };
+
+        config::write_private(out, &content)
+            .with_context(|| format!("Failed to write export file: {:?}", out))?;
+
+        status!(
+            "{} Exported {} service provider(s) to {}",
+            "📦".green(),
+            exported.providers.len().to_string().green().bold(),
+            out.display().to_string().cyan()
+        );
+        if with_tokens {
+            println!(
+                "{} The export contains plaintext tokens, keep it private",
+                "⚠️".yellow()
+            );
+        } else {
+            status!(
+                "{} Tokens were redacted; use {} for a full backup",
+                "💡".blue(),
+                "--with-tokens".yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints (or writes to `out`) a POSIX shell script of `cce` commands that
+    /// recreate every provider with its settings, aliases, and the default
+    /// and current provider. Tokens are read from `CCE_TOKEN_<NAME>`
+    /// variables unless `with_tokens` is set; `${VAR}` references are kept.
+    pub fn export_script(config: &Config, out: Option<&Path>, with_tokens: bool) -> Result<()> {
+        let quote = Shell::posix_quote;
+        let mut names: Vec<&String> = config.providers.keys().collect();
+        names.sort();
+
+        let mut placeholders: Vec<(String, &str)> = Vec::new();
+        let mut body = Vec::new();
+        for name in &names {
+            let provider = &config.providers[*name];
+            let readable = !config.encrypted && !secret_store::is_keyring_token(&provider.token);
+
+            let token = if with_tokens {
+                quote(&config.provider_token(provider)?)
+            } else if readable && is_env_template(&provider.token) {
+                quote(&provider.token)
+            } else {
+                let var = Self::token_placeholder(name, &placeholders);
+                let token = format!("\"${{{}:?}}\"", var);
+                placeholders.push((var, name.as_str()));
+                token
+            };
+
+            let mut add = format!(
+                "printf '%s\\n' {} | cce add {} {} - --yes",
+                token,
+                quote(name),
+                quote(&provider.api_url)
+            );
+            let options = [
+                ("model", provider.model.clone()),
+                ("token-env", provider.token_env.clone()),
+                ("url-env", provider.url_env.clone()),
+                ("desc", provider.description.clone()),
+                ("console-url", provider.console_url.clone()),
+                ("weight", provider.weight.map(|weight| weight.to_string())),
+            ];
+            for (option, value) in options {
+                if let Some(value) = value {
+                    add.push_str(&format!(" --{}={}", option, quote(&value)));
+                }
+            }
+            if Self::normalize_api_url(&provider.api_url, true).is_err() {
+                add.push_str(" --no-validate");
+            }
+
+            body.push(String::new());
+            body.push(add);
+
+            match (&provider.http_proxy, &provider.https_proxy) {
+                (Some(http), Some(https)) if http == https => {
+                    body.push(format!("cce set-proxy {} {}", quote(name), quote(http)));
+                }
+                (http, https) => {
+                    for (scheme, proxy) in [("http", http), ("https", https)] {
+                        if let Some(proxy) = proxy {
+                            body.push(format!(
+                                "cce set-proxy {} {} --only {}",
+                                quote(name),
+                                quote(proxy),
+                                scheme
+                            ));
+                        }
+                    }
+                }
+            }
+
+            let mut extra: Vec<(&String, &String)> = provider.extra_env.iter().collect();
+            extra.sort();
+            for (key, value) in extra {
+                body.push(format!(
+                    "cce set-env {} {}",
+                    quote(name),
+                    quote(&format!("{}={}", key, value))
+                ));
+            }
+
+            let mut headers: Vec<(&String, &String)> = provider.headers.iter().collect();
+            headers.sort();
+            for (header, value) in headers {
+                body.push(format!(
+                    "cce set-header {} {}",
+                    quote(name),
+                    quote(&format!("{}: {}", header, value))
+                ));
+            }
+
+            if !provider.tags.is_empty() {
+                let tags: Vec<String> = provider.tags.iter().map(|tag| quote(tag)).collect();
+                body.push(format!("cce tag {} {}", quote(name), tags.join(" ")));
+            }
+
+            let backups: Vec<&String> = provider
+                .tokens
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != provider.active_token)
+                .map(|(_, token)| token)
+                .collect();
+            if with_tokens {
+                for token in backups {
+                    body.push(format!(
+                        "printf '%s\\n' {} | cce token add {} -",
+                        quote(&config.unseal_token(token)?),
+                        quote(name)
+                    ));
+                }
+            } else if !backups.is_empty() {
+                body.push(format!(
+                    "# {} backup token(s) not exported; add them with 'cce token add {}'",
+                    backups.len(),
+                    name
+                ));
+            }
+
+            if let Some(expires_at) = provider.expires_at {
+                body.push(format!(
+                    "cce edit {} --expires {}",
+                    quote(name),
+                    quote(&expires_at.to_rfc3339())
+                ));
+            }
+
+            if provider.locked {
+                body.push(format!("cce lock {}", quote(name)));
+            }
+        }
+
+        let mut aliases: Vec<(&String, &String)> = config.aliases.iter().collect();
+        aliases.sort();
+        if !aliases.is_empty() {
+            body.push(String::new());
+            for (alias, target) in aliases {
+                body.push(format!("cce alias {} {}", quote(alias), quote(target)));
+            }
+        }
+        if config.default_provider.is_some() || config.current_provider.is_some() {
+            body.push(String::new());
+        }
+        if let Some(default) = &config.default_provider {
+            body.push(format!("cce default {}", quote(default)));
+        }
+        if let Some(current) = &config.current_provider {
+            body.push(format!("cce use {}", quote(current)));
+        }
+
+        let mut lines = vec![
+            "#!/bin/sh".to_string(),
+            format!(
+                "# Recreates {} cce service provider(s); generated by 'cce export --script'",
+                names.len()
+            ),
+        ];
+        if !placeholders.is_empty() {
+            lines.push("# Set these variables to the providers' tokens before running it:".into());
+            for (var, name) in &placeholders {
+                lines.push(format!("#   {} ({})", var, name));
+            }
+        }
+        lines.push("set -e".to_string());
+        lines.extend(body);
+        let content = lines.join("\n") + "\n";
+
+        let Some(out) = out else {
+            print!("{}", content);
+            return Ok(());
+        };
+
+        config::write_private(out, &content)
+            .with_context(|| format!("Failed to write export file: {:?}", out))?;
+
+        status!(
+            "{} Exported {} service provider(s) to {}; run it with 'sh {}'",
+            "📦".green(),
+            names.len().to_string().green().bold(),
+            out.display().to_string().cyan(),
+            out.display()
+        );
+        if with_tokens {
+            println!(
+                "{} The script contains plaintext tokens, keep it private",
+                "⚠️".yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// `CCE_TOKEN_<NAME>`, with the name uppercased and everything but
+    /// letters and digits turned into `_`, numbered if already taken.
+    fn token_placeholder(name: &str, taken: &[(String, &str)]) -> String {
+        let base: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let base = format!("CCE_TOKEN_{}", base);
+
+        let mut var = base.clone();
+        let mut suffix = 2;
+        while taken.iter().any(|(other, _)| *other == var) {
+            var = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        var
+    }
+
+    pub fn import_config(
+        config: &mut Config,
+        file: &Path,
+        strategy: ImportStrategy,
+        adopt_current: bool,
+        force: bool,
+    ) -> Result<()> {
+        let content = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read import file: {:?}", file))?;
+        let imported = ExportedConfig::parse(&content, ExportFormat::detect(file, &content))?;
+
+        // Replace drops every provider, merge overwrites those in the file
+        let mut touched: Vec<&String> = match strategy {
+            ImportStrategy::Replace => config.providers.keys().collect(),
+            ImportStrategy::Merge => imported.providers.keys().collect(),
+            ImportStrategy::SkipExisting => Vec::new(),
+        };
+        touched.sort();
+        for name in touched {
+            Self::ensure_unlocked(config, name, force)?;
+        }
+
+        // Replaced providers stay available so redacted imports can reuse their tokens
+        let previous = if strategy == ImportStrategy::Replace {
+            std::mem::take(&mut config.providers)
+        } else {
+            HashMap::new()
+        };
+
+        let (mut added, mut overwritten, mut skipped) = (0, 0, 0);
+
+        for (name, mut provider) in imported.providers {
+            let local = config.providers.get(&name).or_else(|| previous.get(&name));
+            let exists = local.is_some();
+
+            if exists && strategy == ImportStrategy::SkipExisting {
+                skipped += 1;
+                continue;
+            }
+
+            provider.name = name.clone();
+            if provider.token == REDACTED_TOKEN {
+                // Redacted exports carry no secret: keep the local token or skip
+                match local {
+                    Some(existing) => {
+                        provider.token = existing.token.clone();
+                        provider.tokens = existing.tokens.clone();
+                        provider.active_token = existing.active_token;
+                    }
+                    None => {
+                        println!(
+                            "{} Skipping '{}': its token was redacted in the export",
+                            "⚠️".yellow(),
+                            name.yellow()
+                        );
+                        skipped += 1;
+                        continue;
+                    }
+                }
+            } else {
+                provider.token = Self::store_token(config, &name, provider.token)?;
+                provider.tokens = provider
+                    .tokens
+                    .into_iter()
+                    .map(|token| config.seal_token(token))
+                    .collect::<Result<_>>()?;
+                if provider.active_token >= provider.tokens.len() {
+                    provider.active_token = 0;
+                }
+            }
+
+            if exists {
+                overwritten += 1;
+            } else {
+                added += 1;
+            }
+            config.providers.insert(name, provider);
+        }
+
+        for (name, provider) in &previous {
+            if secret_store::is_keyring_token(&provider.token)
+                && !config.providers.contains_key(name)
+            {
+                config.forget_keyring_entry(name);
+            }
+        }
+
+        if adopt_current {
+            if let Some(current) = imported.current_provider {
+                if config.providers.contains_key(&current) {
+                    config.current_provider = Some(current);
+                }
+            }
+        }
+        if let Some(current) = &config.current_provider {
+            if !config.providers.contains_key(current) {
+                config.clear_current_provider();
+            }
+        }
+        // Exported providers carry their inherited values, so nothing is lost
+        config.detach_orphans();
+
+        config.save()?;
+
+        status!(
+            "{} Imported service providers from {}",
+            "📥".green(),
+            file.display().to_string().cyan()
+        );
+        status!("  Added: {}", added.to_string().green());
+        status!("  Overwritten: {}", overwritten.to_string().yellow());
+        status!("  Skipped: {}", skipped.to_string().dimmed());
+        if let Some(current) = &config.current_provider {
+            status!("  Current provider: {}", current.green().bold());
+        }
+        Ok(())
+    }
+
+    /// Creates the `imported` provider from an API URL and token already set
+    /// up for Claude Code, taking each from the first place it is found: the
+    /// environment, `~/.claude/settings.json`, then shell startup files.
+    pub fn import_existing(config: &mut Config, yes: bool, force: bool) -> Result<()> {
+        let mut url: Option<(String, String)> = None;
+        let mut token: Option<(String, String)> = None;
+        for (source, vars) in Self::existing_setups() {
+            if url.is_none() {
+                url = vars
+                    .get(DEFAULT_URL_ENV)
+                    .map(|value| (value.clone(), source.clone()));
+            }
+            if token.is_none() {
+                token = vars
+                    .get(DEFAULT_TOKEN_ENV)
+                    .map(|value| (value.clone(), source.clone()));
+            }
+        }
+
+        if url.is_none() && token.is_none() {
+            println!(
+                "{} No existing {} or {} found in the environment, ~/.claude/settings.json, or shell startup files",
+                "ℹ️".blue(),
+                DEFAULT_URL_ENV,
+                DEFAULT_TOKEN_ENV
+            );
+            println!(
+                "{} Add a provider with 'cce add <name> <url>'",
+                "💡".yellow()
+            );
+            return Ok(());
+        }
+
+        println!("{} Found an existing setup:", "🔎".blue());
+        match &url {
+            Some((value, source)) => {
+                println!("  {}: {} (from {})", DEFAULT_URL_ENV, value.cyan(), source)
+            }
+            None => println!("  {}: {}", DEFAULT_URL_ENV, "Not found".red()),
+        }
+        match &token {
+            Some((value, source)) => println!(
+                "  {}: {} (from {})",
+                DEFAULT_TOKEN_ENV,
+                Self::mask_secret(value).green(),
+                source
+            ),
+            None => println!("  {}: {}", DEFAULT_TOKEN_ENV, "Not found".red()),
+        }
+
+        let (Some((url, _)), Some((token, _))) = (url, token) else {
+            println!(
+                "{} Both are needed to create a provider; add it with 'cce add {} <url>' instead",
+                "💡".yellow(),
+                IMPORTED_PROVIDER_NAME
+            );
+            return Ok(());
+        };
+
+        let name = IMPORTED_PROVIDER_NAME;
+        if config.providers.contains_key(name) && !force {
+            return Err(CceError::ProviderExists(name.to_string()).into());
+        }
+
+        if !yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!(
+                    "Refusing to create '{}' without confirmation; pass --yes",
+                    name
+                ));
+            }
+
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Create service provider '{}' from it?", name))
+                .default(true)
+                .interact()
+                .with_context(|| "Failed to read confirmation")?;
+            if !confirmed {
+                status!("{} Nothing was imported", "ℹ️".blue());
+                return Ok(());
+            }
+        }
+
+        let provider = Provider::new(name.to_string(), url, token);
+        Self::add_provider(config, provider, true, true, force)?;
+        status!("{} Switch to it with 'cce use {}'", "💡".yellow(), name);
+        Ok(())
+    }
+
+    /// The default URL and token variables found in each place Claude Code
+    /// may already be configured, labelled by where they came from.
+    fn existing_setups() -> Vec<(String, HashMap<String, String>)> {
+        let wanted = [DEFAULT_URL_ENV, DEFAULT_TOKEN_ENV];
+        let mut setups = Vec::new();
+
+        let env: HashMap<String, String> = wanted
+            .iter()
+            .filter_map(|key| {
+                let value = std::env::var(key).ok()?;
+                Some((key.to_string(), value.trim().to_string()))
+            })
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        setups.push(("environment".to_string(), env));
+
+        let Some(home) = dirs::home_dir() else {
+            return setups;
+        };
+
+        let settings = std::fs::read_to_string(home.join(".claude").join("settings.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok());
+        if let Some(serde_json::Value::Object(env)) =
+            settings.and_then(|json| json.get("env").cloned())
+        {
+            let vars = env
+                .into_iter()
+                .filter(|(key, _)| wanted.contains(&key.as_str()))
+                .filter_map(|(key, value)| Some((key, value.as_str()?.trim().to_string())))
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            setups.push(("~/.claude/settings.json".to_string(), vars));
+        }
+
+        for file in SHELL_RC_FILES {
+            let Ok(content) = std::fs::read_to_string(home.join(file)) else {
+                continue;
+            };
+            let mut vars = HashMap::new();
+            for line in content.lines() {
+                if let Some((key, value)) = Self::parse_rc_export(line) {
+                    if wanted.contains(&key) {
+                        vars.insert(key.to_string(), value);
+                    }
+                }
+            }
+            setups.push((format!("~/{}", file), vars));
+        }
+
+        setups
+    }
+
+    /// Reads `export KEY=value` (POSIX shells) or `set -gx KEY value` (fish).
+    /// Values that expand other variables or commands are skipped, since
+    /// their result is unknown here.
+    fn parse_rc_export(line: &str) -> Option<(&str, String)> {
+        let line = line.trim();
+        let (key, value) = if let Some(rest) = line.strip_prefix("export ") {
+            rest.trim().split_once('=')?
+        } else if let Some(rest) = line
+            .strip_prefix("set -gx ")
+            .or_else(|| line.strip_prefix("set -x "))
+        {
+            rest.trim().split_once(char::is_whitespace)?
+        } else {
+            return None;
+        };
+
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('\'' | '"')) => value.strip_prefix(quote)?.split(quote).next()?,
+            _ => value.split_whitespace().next()?,
+        };
+        if value.is_empty() || value.contains('$') || value.contains('`') {
+            return None;
+        }
+        Some((key.trim(), value.to_string()))
+    }
+
+    pub fn encrypt_config(config: &mut Config) -> Result<()> {
+        if config.encrypted {
+            status!("{} Tokens are already encrypted", "ℹ️".blue());
+            return Ok(());
+        }
+
+        let password = crypto::prompt_password("🔐 New master password: ")?;
+        if password.is_empty() {
+            return Err(anyhow!("Master password must not be empty"));
+        }
+        let confirmation = crypto::prompt_password("🔐 Confirm master password: ")?;
+        if password != confirmation {
+            return Err(anyhow!("Master passwords do not match"));
+        }
+
+        config.enable_encryption(&password)?;
+        config.save()?;
+
+        status!(
+            "{} Encrypted tokens for {} service provider(s)",
+            "🔒".green(),
+            config.providers.len().to_string().green().bold()
+        );
+        Ok(())
+    }
+
+    pub fn decrypt_config(config: &mut Config) -> Result<()> {
+        if !config.encrypted {
+            status!("{} Tokens are not encrypted", "ℹ️".blue());
+            return Ok(());
+        }
+
+        config.disable_encryption()?;
+        config.save()?;
+
+        status!(
+            "{} Decrypted tokens for {} service provider(s)",
+            "🔓".green(),
+            config.providers.len().to_string().green().bold()
+        );
+        println!(
+            "{} Tokens are now stored as plaintext in the config file",
+            "⚠️".yellow()
+        );
+        Ok(())
+    }
+
+    /// Moves the tokens of every profile into the keyring, since
+    /// `keyring = true` covers them all.
+    pub fn migrate_to_keyring(config: &mut Config) -> Result<()> {
+        let profiles: Vec<String> = config
+            .profile_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let mut migrated = 0;
+        let mut failed = 0;
+
+        for profile in &profiles {
+            let providers = config.profile_providers(profile).unwrap();
+            let mut names: Vec<String> = providers
+                .iter()
+                .filter(|(_, provider)| !secret_store::is_keyring_token(&provider.token))
+                .map(|(name, _)| name.clone())
+                .collect();
+            names.sort();
+
+            for name in &names {
+                let token =
+                    config.provider_token(&config.profile_providers(profile).unwrap()[name])?;
+
+                match secret_store::store(&secret_store::key(profile, name), &token) {
+                    Ok(()) => {
+                        let providers = config.profile_providers_mut(profile).unwrap();
+                        providers.get_mut(name).unwrap().token = KEYRING_SENTINEL.to_string();
+                        migrated += 1;
+                    }
+                    Err(e) => {
+                        println!("{} {:#}; keeping it in the config file", "⚠️".yellow(), e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+
+        if failed == 0 {
+            config.keyring = true;
+        }
+        config.save()?;
+
+        status!(
+            "{} Moved {} token(s) into the OS keyring",
+            "🔑".green(),
+            migrated.to_string().green().bold()
+        );
+        if failed > 0 {
+            println!(
+                "{} {} token(s) could not be migrated and remain in the config file",
+                "⚠️".yellow(),
+                failed.to_string().yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Strips trailing slashes and, unless disabled, checks that the URL is
+    /// an absolute http(s) URL with a host. URLs referring to `${VAR}` are
+    /// only known at use time and are not checked.
+    pub(crate) fn normalize_api_url(api_url: &str, validate: bool) -> Result<String> {
+        let normalized = api_url.trim_end_matches('/').to_string();
+
+        if validate && !is_env_template(&normalized) {
+            let parsed = Url::parse(&normalized)
+                .map_err(|e| anyhow!("Invalid API URL '{}': {}", api_url, e))?;
+
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(anyhow!(
+                    "Invalid API URL '{}': scheme must be http or https (use --no-validate to skip this check)",
+                    api_url
+                ));
+            }
+            if parsed.host_str().is_none_or(str::is_empty) {
+                return Err(anyhow!("Invalid API URL '{}': missing host", api_url));
+            }
+        }
+
+        Ok(normalized)
+    }
+
+    /// Stores a token in the keyring when enabled, falling back to the
+    /// config file (encrypted if configured) when the keyring is unavailable.
+    pub(crate) fn store_token(config: &Config, name: &str, token: String) -> Result<String> {
+        if config.keyring {
+            match secret_store::store(&config.keyring_key(name), &token) {
+                Ok(()) => return Ok(KEYRING_SENTINEL.to_string()),
+                Err(e) => println!(
+                    "{} {:#}; storing token in the config file instead",
+                    "⚠️".yellow(),
+                    e
+                ),
+            }
+        }
+
+        config.seal_token(token)
+    }
+
+    /// Prints the registration script for dynamic completions; the shell
+    /// calls back into cce (with `COMPLETE` set) to complete provider names.
+    pub fn output_completions(shell: Shell) -> Result<()> {
+        let name = match shell {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Powershell => "powershell",
+            Shell::Cmd => return Err(anyhow!("cmd.exe does not support shell completions")),
+            Shell::Nu => return Err(anyhow!("Shell completions are not available for nushell")),
+        };
+
+        let shells = Shells::builtins();
+        let completer = shells
+            .completer(name)
+            .ok_or_else(|| anyhow!("No completion support for {}", name))?;
+        let cce_path = std::env::current_exe()?.to_string_lossy().into_owned();
+
+        completer
+            .write_registration("COMPLETE", "cce", "cce", &cce_path, &mut std::io::stdout())
+            .with_context(|| "Failed to write completion script")
+    }
+
+    pub fn set_timeout(config: &mut Config, secs: Option<u64>, reset: bool) -> Result<()> {
+        if secs.is_none() && !reset {
+            let source = if config.timeout_secs.is_some() {
+                "configured"
+            } else {
+                "default"
+            };
+            println!(
+                "Request timeout: {}s ({})",
+                config.timeout(None).as_secs().to_string().cyan(),
+                source
+            );
+            return Ok(());
+        }
+
+        config.timeout_secs = secs;
+        config.save()?;
+
+        status!(
+            "{} Request timeout is now {}s",
+            "✅".green(),
+            config.timeout(None).as_secs().to_string().green().bold()
+        );
+        Ok(())
+    }
+
+    pub fn set_env_scheme(
+        config: &mut Config,
+        scheme: Option<EnvScheme>,
+        reset: bool,
+    ) -> Result<()> {
+        let describe = |scheme: EnvScheme| scheme.token_vars().join(" and ");
+
+        if scheme.is_none() && !reset {
+            let source = if config.env_scheme.is_some() {
+                "configured"
+            } else {
+                "default"
+            };
+            println!(
+                "Tokens are exported as {} ({})",
+                describe(config.env_scheme()).cyan(),
+                source
+            );
+            return Ok(());
+        }
+
+        config.env_scheme = scheme;
+        config.save()?;
+
+        status!(
+            "{} Tokens are now exported as {}",
+            "✅".green(),
+            describe(config.env_scheme()).green().bold()
+        );
+        status!(
+            "{} Providers with their own token variable keep it; run 'cce use' again to re-export",
+            "💡".yellow()
+        );
+        Ok(())
+    }
+
+    /// Shows, sets, or (with `reset`) removes the command run after each
+    /// switch.
+    pub fn set_on_switch(config: &mut Config, hook: Option<String>, reset: bool) -> Result<()> {
+        if hook.is_none() && !reset {
+            match &config.on_switch {
+                Some(hook) => println!("{}", hook),
+                None => status!("{} No on_switch hook is set", "ℹ️".blue()),
+            }
+            return Ok(());
+        }
+
+        config.on_switch = hook.filter(|hook| !hook.trim().is_empty());
+        config.save()?;
+
+        match &config.on_switch {
+            Some(hook) => status!(
+                "{} After each switch, cce now runs: {}",
+                "✅".green(),
+                hook.cyan()
+            ),
+            None => status!("{} Removed the on_switch hook", "🧹".green()),
+        }
+        Ok(())
+    }
+
+    pub fn set_clipboard_clear(config: &mut Config, secs: Option<u64>, reset: bool) -> Result<()> {
+        let describe = |config: &Config| match config.clipboard_clear(None) {
+            Some(delay) => format!("{}s", delay.as_secs()),
+            None => "never".to_string(),
+        };
+
+        if secs.is_none() && !reset {
+            let source = if config.clipboard_clear_secs.is_some() {
+                "configured"
+            } else {
+                "default"
+            };
+            println!(
+                "Clipboard clear delay of 'cce cp-token': {} ({})",
+                describe(config).cyan(),
+                source
+            );
+            return Ok(());
+        }
+
+        config.clipboard_clear_secs = secs;
+        config.save()?;
+
+        status!(
+            "{} Clipboard clear delay is now {}",
+            "✅".green(),
+            describe(config).green().bold()
+        );
+        Ok(())
+    }
+
+    pub fn set_verify_on_use(config: &mut Config, enabled: Option<bool>) -> Result<()> {
+        let Some(enabled) = enabled else {
+            println!(
+                "Reachability check on 'cce use': {}",
+                if config.verify_on_use { "on" } else { "off" }.cyan()
+            );
+            return Ok(());
+        };
+
+        config.verify_on_use = enabled;
+        config.save()?;
+
+        if enabled {
+            status!(
+                "{} 'cce use' now checks that the provider is reachable before switching (skip it with --force)",
+                "✅".green()
+            );
+        } else {
+            status!(
+                "{} 'cce use' no longer checks reachability unless --verify is given",
+                "✅".green()
+            );
+        }
         Ok(())
     }
 
-    pub fn check_environment(config: &Config) -> Result<()> {
-        println!(
-            "{}",
-            "🔍 Checking environment variable status".blue().bold()
-        );
-        println!();
-
-        // Check current environment variables
-        let current_api_key = std::env::var("ANTHROPIC_AUTH_TOKEN");
-        let current_api_url = std::env::var("ANTHROPIC_BASE_URL");
+    pub fn print_config_path() -> Result<()> {
+        println!("{}", Config::get_config_path()?.display());
+        Ok(())
+    }
 
-        println!("{}", "Current environment variables:".cyan().bold());
-        match &current_api_key {
-            Ok(key) => {
-                let masked_key = if key.len() > 8 {
-                    format!("{}****", &key[..8])
-                } else {
-                    "****".to_string()
-                };
-                println!("  ANTHROPIC_AUTH_TOKEN: {}", masked_key.green());
-            }
-            Err(_) => {
-                println!("  ANTHROPIC_AUTH_TOKEN: {}", "Not set".red());
-            }
-        }
+    /// Prints the parsed config, in the config file's format, with every
+    /// token masked.
+    pub fn show_config(config: &Config) -> Result<()> {
+        let mut value =
+            toml::Value::try_from(config).with_context(|| "Failed to serialize config")?;
 
-        match &current_api_url {
-            Ok(url) => {
-                println!("  ANTHROPIC_BASE_URL: {}", url.green());
-            }
-            Err(_) => {
-                println!("  ANTHROPIC_BASE_URL: {}", "Not set".red());
+        Self::mask_provider_tokens(config, &mut value, &config.providers);
+        if let Some(profiles) = value.get_mut("profiles").and_then(|p| p.as_table_mut()) {
+            for (name, entry) in profiles.iter_mut() {
+                if let Some(profile) = config.profiles.get(name) {
+                    Self::mask_provider_tokens(config, entry, &profile.providers);
+                }
             }
         }
 
-        println!();
+        print!("{}", ConfigFormat::current()?.serialize(&value)?);
+        Ok(())
+    }
 
-        // Check configuration status
-        if let Some(current_provider) = &config.current_provider {
-            if let Some(provider) = config.providers.get(current_provider) {
-                println!("{}", "CCE configuration status:".cyan().bold());
-                println!("  Current provider: {}", current_provider.green().bold());
-                println!("  Configured URL: {}", provider.api_url.cyan());
+    /// Replaces the tokens under `value.providers` with their masked form.
+    fn mask_provider_tokens(
+        config: &Config,
+        value: &mut toml::Value,
+        providers: &HashMap<String, Provider>,
+    ) {
+        let Some(entries) = value.get_mut("providers").and_then(|p| p.as_table_mut()) else {
+            return;
+        };
 
-                // Verify if environment variables match configuration
-                let env_matches = match (&current_api_key, &current_api_url) {
-                    (Ok(env_key), Ok(env_url)) => {
-                        env_key == &provider.token && env_url == &provider.api_url
+        for (name, entry) in entries.iter_mut() {
+            if let (Some(provider), Some(entry)) = (providers.get(name), entry.as_table_mut()) {
+                entry.insert(
+                    "token".to_string(),
+                    toml::Value::String(Self::masked_token(config, provider)),
+                );
+                if !provider.tokens.is_empty() {
+                    let masked = provider
+                        .tokens
+                        .iter()
+                        .map(|token| toml::Value::String(Self::masked_pooled_token(config, token)))
+                        .collect();
+                    entry.insert("tokens".to_string(), toml::Value::Array(masked));
+                }
+                if let Some(headers) = entry.get_mut("headers").and_then(|h| h.as_table_mut()) {
+                    for (header, value) in headers.iter_mut() {
+                        if let (true, Some(text)) = (is_secret_header(header), value.as_str()) {
+                            *value = toml::Value::String(Self::mask_secret(text));
+                        }
                     }
-                    _ => false,
-                };
-
-                if env_matches {
-                    println!(
-                        "  Status: {}",
-                        "✅ Environment variables match configuration".green()
-                    );
-                } else {
-                    println!(
-                        "  Status: {}",
-                        "⚠️ Environment variables do not match configuration".yellow()
-                    );
-                    println!(
-                        "  Suggestion: Run 'cce use {}' to reset",
-                        current_provider.cyan()
-                    );
                 }
-            } else {
-                println!(
-                    "{}",
-                    "❌ Configuration error: Current provider does not exist".red()
-                );
-            }
-        } else {
-            println!("{}", "CCE configuration status:".cyan().bold());
-            println!("  Current provider: {}", "None selected".yellow());
-            if !config.providers.is_empty() {
-                println!("  Suggestion: Use 'cce use <provider-name>' to select a provider");
-            } else {
-                println!("  Suggestion: Use 'cce add' to add a service provider");
             }
         }
-
-        Ok(())
     }
 
-    pub fn clear_provider(config: &mut Config) -> Result<()> {
-        // Check if there's a current provider to clear
+    pub fn clear_provider(config: &mut Config, eval: bool, shell: Option<Shell>) -> Result<()> {
+        let shell_mode = eval || Self::shell_integration_active();
+
+        // Nothing to clear in the config, but a shell wrapper still gets unset
+        // commands so stale variables leave the session too
         if config.current_provider.is_none() {
-            println!("{} No service provider is currently active", "ℹ️".blue());
+            if shell_mode {
+                Self::emit_unset_commands(
+                    None,
+                    config.env_scheme(),
+                    shell.unwrap_or_else(Shell::detect),
+                );
+            } else {
+                status!("{} No service provider is currently active", "ℹ️".blue());
+            }
             return Ok(());
         }
 
         let previous_provider = config.current_provider.clone();
+        let previous = previous_provider
+            .as_ref()
+            .and_then(|name| config.providers.get(name))
+            .cloned();
 
         // Clear current provider in config
         config.clear_current_provider();
         config.save()?;
 
-        let shell_mode = Self::shell_integration_active();
-
         if !shell_mode {
             if let Some(provider_name) = previous_provider {
-                println!("{} Cleared service provider configuration", "🧹".green());
-                println!(
+                status!("{} Cleared service provider configuration", "🧹".green());
+                status!(
                     "{} Removed '{}' as the active provider",
                     "✓".green(),
                     provider_name.yellow()
@@ -251,26 +6101,31 @@ impl ProviderManager {
             }
         }
 
-        Self::clear_environment_variables(shell_mode)?;
+        Self::clear_environment_variables(previous.as_ref(), config.env_scheme(), shell_mode)?;
 
         if shell_mode {
-            Self::emit_unset_commands();
+            Self::emit_unset_commands(
+                previous.as_ref(),
+                config.env_scheme(),
+                shell.unwrap_or_else(Shell::detect),
+            );
         }
 
         Ok(())
     }
 
-    fn clear_environment_variables(shell_mode: bool) -> Result<()> {
+    fn clear_environment_variables(
+        previous: Option<&Provider>,
+        scheme: EnvScheme,
+        shell_mode: bool,
+    ) -> Result<()> {
         // Remove from current process environment
-        std::env::remove_var("ANTHROPIC_AUTH_TOKEN");
-        std::env::remove_var("ANTHROPIC_BASE_URL");
-        std::env::remove_var("ANTHROPIC_MODEL");
-        std::env::remove_var("ANTHROPIC_DEFAULT_OPUS_MODEL");
-        std::env::remove_var("ANTHROPIC_DEFAULT_SONNET_MODEL");
-        std::env::remove_var("ANTHROPIC_DEFAULT_HAIKU_MODEL");
+        for key in Self::managed_env_var_names(previous, scheme) {
+            std::env::remove_var(key);
+        }
 
         if !shell_mode {
-            println!(
+            status!(
                 "{}",
                 "Environment variables cleared from current session".green()
             );
@@ -279,134 +6134,255 @@ impl ProviderManager {
         Ok(())
     }
 
-    fn emit_export_commands(provider: &Provider) {
-        println!("export ANTHROPIC_AUTH_TOKEN=\"{}\"", provider.token);
-        println!("export ANTHROPIC_BASE_URL=\"{}\"", provider.api_url);
+    /// Environment variables exported for a provider, in export order.
+    fn provider_env_vars(provider: &Provider, scheme: EnvScheme) -> Vec<(&str, Cow<'_, str>)> {
+        let mut vars: Vec<(&str, &str)> = provider
+            .token_vars(scheme)
+            .into_iter()
+            .map(|key| (key, provider.token.as_str()))
+            .collect();
+        vars.push((provider.url_var(), provider.api_url.as_str()));
 
         if let Some(ref model) = provider.model {
-            println!("export ANTHROPIC_MODEL=\"{}\"", model);
-            println!("export ANTHROPIC_DEFAULT_OPUS_MODEL=\"{}\"", model);
-            println!("export ANTHROPIC_DEFAULT_SONNET_MODEL=\"{}\"", model);
-            println!("export ANTHROPIC_DEFAULT_HAIKU_MODEL=\"{}\"", model);
+            for key in MODEL_ENV_VARS {
+                vars.push((key, model.as_str()));
+            }
+        }
+
+        vars.extend(Self::proxy_env_vars(provider));
+
+        let mut extra: Vec<(&str, &str)> = provider
+            .extra_env
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        extra.sort();
+        vars.extend(extra);
+
+        let mut vars: Vec<(&str, Cow<'_, str>)> = vars
+            .into_iter()
+            .map(|(key, value)| (key, Cow::Borrowed(value)))
+            .collect();
+        if let Some(headers) = provider.custom_headers() {
+            vars.push((CUSTOM_HEADERS_ENV, Cow::Owned(headers)));
+        }
+        vars
+    }
+
+    /// Proxy variables for the provider; empty when no proxy is configured,
+    /// so proxies already set in the shell are left alone.
+    fn proxy_env_vars(provider: &Provider) -> Vec<(&'static str, &str)> {
+        let mut vars = Vec::new();
+        if let Some(proxy) = &provider.http_proxy {
+            vars.extend(HTTP_PROXY_ENV_VARS.map(|key| (key, proxy.as_str())));
+        }
+        if let Some(proxy) = &provider.https_proxy {
+            vars.extend(HTTPS_PROXY_ENV_VARS.map(|key| (key, proxy.as_str())));
+        }
+        vars
+    }
+
+    /// Every variable cce may have exported: the defaults, the scheme's
+    /// token variables, plus any custom names used by the given provider.
+    fn managed_env_var_names(provider: Option<&Provider>, scheme: EnvScheme) -> Vec<&str> {
+        let mut names = vec![DEFAULT_TOKEN_ENV, DEFAULT_URL_ENV];
+        for &key in scheme.token_vars() {
+            if !names.contains(&key) {
+                names.push(key);
+            }
+        }
+        names.extend(MODEL_ENV_VARS);
+
+        if let Some(provider) = provider {
+            let mut extra: Vec<&str> = provider.extra_env.keys().map(String::as_str).collect();
+            extra.sort();
+            if !provider.headers.is_empty() {
+                extra.push(CUSTOM_HEADERS_ENV);
+            }
+
+            let proxies = Self::proxy_env_vars(provider)
+                .into_iter()
+                .map(|(key, _)| key);
+
+            for name in provider
+                .token_vars(scheme)
+                .into_iter()
+                .chain([provider.url_var()])
+                .chain(proxies)
+                .chain(extra)
+            {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names
+    }
+
+    fn emit_export_commands(provider: &Provider, scheme: EnvScheme, shell: Shell) {
+        for line in Self::export_lines(provider, scheme, shell) {
+            println!("{}", line);
         }
     }
 
-    fn emit_unset_commands() {
+    /// The commands exporting the provider's variables in the given shell,
+    /// shared by `use --eval`, `print-env`, and the shell integration.
+    pub(crate) fn export_lines(
+        provider: &Provider,
+        scheme: EnvScheme,
+        shell: Shell,
+    ) -> Vec<String> {
+        Self::provider_env_vars(provider, scheme)
+            .into_iter()
+            .map(|(key, value)| {
+                debug!("Exporting {} for {:?}", key, shell);
+                shell.export(key, &value)
+            })
+            .collect()
+    }
+
+    fn emit_unset_commands(previous: Option<&Provider>, scheme: EnvScheme, shell: Shell) {
         // Output unset commands for shell
-        println!("unset ANTHROPIC_AUTH_TOKEN");
-        println!("unset ANTHROPIC_BASE_URL");
-        println!("unset ANTHROPIC_MODEL");
-        println!("unset ANTHROPIC_DEFAULT_OPUS_MODEL");
-        println!("unset ANTHROPIC_DEFAULT_SONNET_MODEL");
-        println!("unset ANTHROPIC_DEFAULT_HAIKU_MODEL");
+        for key in Self::managed_env_var_names(previous, scheme) {
+            debug!("Unsetting {} for {:?}", key, shell);
+            println!("{}", shell.unset(key));
+        }
     }
 
-    fn apply_environment_variables(provider: &Provider) -> Result<()> {
+    fn apply_environment_variables(provider: &Provider, scheme: EnvScheme) -> Result<()> {
         // Immediately set environment variables for current process
-        std::env::set_var("ANTHROPIC_AUTH_TOKEN", &provider.token);
-        std::env::set_var("ANTHROPIC_BASE_URL", &provider.api_url);
-
-        // Set model environment variables if model is specified
-        if let Some(ref model) = provider.model {
-            std::env::set_var("ANTHROPIC_MODEL", model);
-            std::env::set_var("ANTHROPIC_DEFAULT_OPUS_MODEL", model);
-            std::env::set_var("ANTHROPIC_DEFAULT_SONNET_MODEL", model);
-            std::env::set_var("ANTHROPIC_DEFAULT_HAIKU_MODEL", model);
+        for (key, value) in Self::provider_env_vars(provider, scheme) {
+            trace!("Setting {} in the cce process", key);
+            std::env::set_var(key, value.as_ref());
         }
 
         Ok(())
     }
 
+    /// Whether this process was started through the `shellenv` wrapper (or
+    /// an exported bash function of it).
+    fn shellenv_loaded() -> bool {
+        std::env::var_os(SHELLENV_MARKER_ENV).is_some()
+            || std::env::var_os("BASH_FUNC_cce%%").is_some()
+    }
+
+    /// The profile line that loads the `shellenv` wrapper in `shell`.
+    fn shellenv_line(shell: Shell) -> &'static str {
+        match shell {
+            Shell::Fish => "cce shellenv --shell fish | source",
+            Shell::Powershell => "cce shellenv --shell powershell | Out-String | Invoke-Expression",
+            Shell::Nu => "cce shellenv --shell nu",
+            _ => r#"eval "$(cce shellenv)""#,
+        }
+    }
+
     fn shell_integration_active() -> bool {
         std::env::var("CCE_SHELL_INTEGRATION")
             .map(|v| v == "1")
             .unwrap_or(false)
     }
 
-    pub fn install_shell_integration(force: bool) -> Result<()> {
-        use std::fs::{File, OpenOptions};
-        use std::io::{BufRead, BufReader, Write};
-
-        // Detect shell type
-        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
-        let shell_name = shell.split('/').last().unwrap_or("bash");
-
-        let (config_file, comment_prefix) = match shell_name {
-            "zsh" => ("~/.zshrc", "#"),
-            "bash" => ("~/.bashrc", "#"),
-            "fish" => ("~/.config/fish/config.fish", "#"),
-            _ => ("~/.bashrc", "#"),
-        };
-
-        // Expand tilde
-        let config_path = if config_file.starts_with("~/") {
-            let home = dirs::home_dir()
-                .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
-            home.join(&config_file[2..])
-        } else {
-            std::path::PathBuf::from(config_file)
+    /// Adds the line that loads the `shellenv` wrapper to `shell`'s rc file,
+    /// between marker comments, after backing the file up. An existing
+    /// installation is left alone unless `force` replaces it.
+    pub fn install_shell_integration(shell: Shell, force: bool) -> Result<()> {
+        let (rc_file, integration_line) = match shell {
+            Shell::Bash => ("~/.bashrc", r#"eval "$(cce shellenv --shell bash)""#),
+            Shell::Zsh => ("~/.zshrc", r#"eval "$(cce shellenv --shell zsh)""#),
+            Shell::Fish => (
+                "~/.config/fish/config.fish",
+                "cce shellenv --shell fish | source",
+            ),
+            // config.nu can only `source` a file that exists when it is parsed
+            Shell::Nu => {
+                return Err(anyhow!(
+                    "Automatic installation is not supported for nushell; see 'cce shellenv --shell nu'"
+                ))
+            }
+            Shell::Powershell | Shell::Cmd => {
+                return Err(anyhow!(
+                    "Automatic installation supports bash, zsh, and fish; for PowerShell, add {} to $PROFILE",
+                    Self::shellenv_line(Shell::Powershell)
+                ))
+            }
         };
 
-        // Check if already installed
-        let integration_line = r#"eval "$(cce shellenv)""#;
-        let mut already_installed = false;
-
-        if config_path.exists() {
-            let file = File::open(&config_path)?;
-            let reader = BufReader::new(file);
+        let home = dirs::home_dir().ok_or_else(|| {
+            anyhow!(
+                "Could not determine your home directory, so the location of {} is unknown; add {} to it by hand",
+                rc_file,
+                integration_line
+            )
+        })?;
+        let rc_path = home.join(rc_file.trim_start_matches("~/"));
 
-            for line in reader.lines() {
-                let line = line?;
-                let trimmed = line.trim();
-                // Skip commented lines
-                if !trimmed.starts_with('#') && trimmed == integration_line {
-                    already_installed = true;
-                    break;
-                }
+        let existing = match std::fs::read_to_string(&rc_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", rc_path.display()))
             }
-        }
+        };
 
-        if already_installed && !force {
-            println!(
+        let installed = existing.lines().any(|line| {
+            let line = line.trim();
+            line == RC_BLOCK_START || line == integration_line || line == Self::shellenv_line(shell)
+        });
+        if installed && !force {
+            status!(
                 "{} Shell integration is already installed in {}",
                 "ℹ️".blue(),
-                config_file.cyan()
-            );
-            println!(
-                "{} Use {} to reinstall",
-                "💡".blue(),
-                "cce install --force".yellow()
+                rc_file.cyan()
             );
+            status!("{} Use {} to reinstall", "💡".blue(), "--force".yellow());
             return Ok(());
         }
 
-        // Create config directory if it doesn't exist (for fish)
-        if let Some(parent) = config_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let block = format!(
+            "{}\n{}\n{}\n",
+            RC_BLOCK_START, integration_line, RC_BLOCK_END
+        );
+        let mut content = Self::without_integration(&existing, shell);
+        if !content.is_empty() {
+            if !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push('\n');
         }
+        content.push_str(&block);
 
-        // Add shell integration
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&config_path)?;
-
-        let integration_block = format!(
-            r#"
-{} CCE Shell Integration
-{}"#,
-            comment_prefix, integration_line
-        );
+        if Config::is_dry_run() {
+            eprintln!(
+                "{} Dry run: nothing was written. Without --dry-run, cce would add to {}:",
+                "🔍".blue(),
+                rc_path.display()
+            );
+            eprint!("{}", block);
+            return Ok(());
+        }
 
-        writeln!(file, "{}", integration_block)?;
+        if !existing.is_empty() {
+            let mut backup = rc_path.clone().into_os_string();
+            backup.push(".cce.bak");
+            std::fs::copy(&rc_path, &backup).with_context(|| {
+                format!("Failed to back up {} to {:?}", rc_path.display(), backup)
+            })?;
+            debug!("Backed up {:?} to {:?}", rc_path, backup);
+        }
+        if let Some(parent) = rc_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&rc_path, content)
+            .with_context(|| format!("Failed to write {}", rc_path.display()))?;
 
-        println!("{} Shell integration installed successfully!", "✅".green());
-        println!("📄 Added to: {}", config_path.display().to_string().cyan());
-        println!();
-        println!("{} To activate in current terminal:", "🔄".blue().bold());
-        println!("   {}", format!("source {}", config_file).yellow());
-        println!();
-        println!(
+        status!("{} Shell integration installed successfully!", "✅".green());
+        status!("📄 Added to: {}", rc_path.display().to_string().cyan());
+        status!();
+        status!("{} To activate in current terminal:", "🔄".blue().bold());
+        status!("   {}", format!("source {}", rc_file).yellow());
+        status!();
+        status!(
             "{} Or restart your terminal for changes to take effect.",
             "🆕".blue().bold()
         );
@@ -414,30 +6390,178 @@ impl ProviderManager {
         Ok(())
     }
 
-    pub fn output_shellenv() -> Result<()> {
+    /// `content` without a previously installed integration: the marked
+    /// block, or the unmarked lines older releases of `cce install` wrote.
+    fn without_integration(content: &str, shell: Shell) -> String {
+        let mut kept = Vec::new();
+        let mut in_block = false;
+        for line in content.lines() {
+            let trimmed = line.trim();
+            if trimmed == RC_BLOCK_START {
+                in_block = true;
+            } else if trimmed == RC_BLOCK_END {
+                in_block = false;
+            } else if !in_block
+                && trimmed != LEGACY_RC_COMMENT
+                && trimmed != Self::shellenv_line(shell)
+            {
+                kept.push(line);
+            }
+        }
+        // Drop the blank line that separated the integration from the rest
+        while kept.last().is_some_and(|line| line.trim().is_empty()) {
+            kept.pop();
+        }
+        if kept.is_empty() {
+            String::new()
+        } else {
+            kept.join("\n") + "\n"
+        }
+    }
+
+    pub fn output_shellenv(config: &Config, shell: Option<Shell>, hook: bool) -> Result<()> {
         // Get current executable path
         let current_exe =
             std::env::current_exe().unwrap_or_else(|_| std::path::PathBuf::from("cce"));
-        let cce_path = current_exe.display();
+        let cce_path = current_exe.display().to_string();
+
+        // Emitting bash syntax for e.g. nushell would silently break the
+        // user's rc file, so only explain how to pick a shell
+        if shell.is_none() {
+            if let Some(name) = Shell::unsupported_login_shell() {
+                println!(
+                    "# cce: the detected shell '{}' is not supported by 'cce shellenv'",
+                    name
+                );
+                println!("# Pass --shell with one of: {}", Shell::supported_names());
+                return Ok(());
+            }
+        }
+
+        let shell = shell.unwrap_or_else(Shell::detect);
 
         // Output complete shell function definition
+        match shell {
+            Shell::Bash | Shell::Zsh => Self::output_posix_shellenv(&cce_path),
+            Shell::Fish => Self::output_fish_shellenv(&cce_path),
+            Shell::Powershell => Self::output_powershell_shellenv(&cce_path),
+            Shell::Cmd => Self::output_cmd_shellenv(&cce_path),
+            Shell::Nu => Self::output_nu_shellenv(&cce_path),
+        }
+
+        // Apply the default provider when the shell starts without one
+        if config.default_provider.is_some() {
+            Self::output_default_provider_hook(&cce_path, shell);
+        }
+
+        if hook {
+            Self::output_drift_hook(&cce_path, shell);
+        }
+
+        Ok(())
+    }
+
+    /// Prompt hook that re-exports the active provider whenever its variables
+    /// no longer match the config, e.g. after a manual `unset`.
+    fn output_drift_hook(cce_path: &str, shell: Shell) {
+        match shell {
+            Shell::Bash | Shell::Zsh => println!(
+                r#"
+_cce_drift_hook() {{
+    if ! "{0}" status --fast 2>/dev/null; then
+        local current
+        current=$("{0}" current 2>/dev/null) &&
+            eval "$("{0}" use "$current" --eval --shell bash 2>/dev/null)"
+    fi
+}}
+if [[ -n "$ZSH_VERSION" ]]; then
+    autoload -Uz add-zsh-hook && add-zsh-hook precmd _cce_drift_hook
+elif [[ "$PROMPT_COMMAND" != *_cce_drift_hook* ]]; then
+    PROMPT_COMMAND="_cce_drift_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}"
+fi"#,
+                cce_path
+            ),
+            Shell::Fish => println!(
+                r#"
+function _cce_drift_hook --on-event fish_prompt
+    if not "{0}" status --fast 2>/dev/null
+        set -l current ("{0}" current 2>/dev/null)
+        and "{0}" use $current --eval --shell fish 2>/dev/null | source
+    end
+end"#,
+                cce_path
+            ),
+            Shell::Powershell => println!(
+                r#"
+$global:_cceOriginalPrompt = $function:prompt
+function global:prompt {{
+    & "{0}" status --fast 2>$null
+    if ($LASTEXITCODE -ne 0) {{
+        $current = & "{0}" current 2>$null
+        if ($LASTEXITCODE -eq 0 -and $current) {{
+            & "{0}" use $current --eval --shell powershell 2>$null | Out-String | Invoke-Expression
+        }}
+    }}
+    & $global:_cceOriginalPrompt
+}}"#,
+                cce_path
+            ),
+            Shell::Nu => println!(
+                r#"
+$env.config = ($env.config | upsert hooks.pre_prompt (($env.config.hooks.pre_prompt? | default []) | append {{||
+    if (^"{0}" status --fast | complete | get exit_code) != 0 {{
+        let current = (^"{0}" current | complete)
+        if $current.exit_code == 0 {{
+            _cce_apply (^"{0}" use ($current.stdout | str trim) --eval --shell nu | complete | get stdout)
+        }}
+    }}
+}}))"#,
+                cce_path
+            ),
+            Shell::Cmd => {}
+        }
+    }
+
+    fn output_posix_shellenv(cce_path: &str) {
         println!(
-            r#"cce() {{
+            r#"export CCE_SHELLENV_LOADED=1
+cce() {{
     local cce_binary="{}"
     
-    if [[ "$1" == "use" && -n "$2" ]]; then
+    # Help and `use --print` only show information, so they run as is
+    # instead of being evaluated
+    local arg
+    for arg in "${{@:2}}"; do
+        case "$arg" in
+            -h|--help|--print)
+                "$cce_binary" "$@"
+                return
+                ;;
+        esac
+    done
+
+    if [[ ("$1" == "use" && -n "$2") || "$1" == "last" ]]; then
+        # Errors go straight to stderr; a failed switch exports nothing
+        # `status` is read-only in zsh
+        local env_output cce_status
+        env_output=$("$cce_binary" "$@" --eval --shell bash)
+        cce_status=$?
+        if [[ $cce_status -ne 0 ]]; then
+            return $cce_status
+        fi
+        # The binary reports the switch on stderr
+        eval "$env_output"
+    elif [[ "$1" == "tui" ]]; then
+        # The UI is drawn on stderr; stdout carries the exports
         local env_output
-        env_output=$(CCE_SHELL_INTEGRATION=1 "$cce_binary" use "$2" 2>/dev/null)
+        env_output=$("$cce_binary" tui --eval --shell bash)
         if [[ $? -eq 0 && -n "$env_output" ]]; then
             eval "$env_output"
-            echo "⚡ Switched to service provider '$2'"
             echo "✅ Environment variables are now active in current terminal"
-        else
-            "$cce_binary" "$@"
         fi
-    elif [[ "$1" == "clear" ]]; then
+    elif [[ "$1" == "clear" || "$1" == "unset" ]]; then
         local env_output
-        env_output=$(CCE_SHELL_INTEGRATION=1 "$cce_binary" clear 2>/dev/null)
+        env_output=$("$cce_binary" clear --eval --shell bash 2>/dev/null)
         if [[ $? -eq 0 && -n "$env_output" ]]; then
             eval "$env_output"
             echo "🧹 Cleared service provider configuration"
@@ -451,7 +6575,398 @@ impl ProviderManager {
 }}"#,
             cce_path
         );
+    }
 
-        Ok(())
+    fn output_fish_shellenv(cce_path: &str) {
+        println!(
+            r#"set -gx CCE_SHELLENV_LOADED 1
+function cce
+    set -l cce_binary "{}"
+
+    # Help and `use --print` only show information, so they run as is
+    # instead of being evaluated
+    for arg in $argv[2..-1]
+        if contains -- $arg -h --help --print
+            $cce_binary $argv
+            return
+        end
+    end
+
+    if test "$argv[1]" = "use"; and test -n "$argv[2]"; or test "$argv[1]" = "last"
+        # Errors go straight to stderr; a failed switch exports nothing
+        set -l env_output ($cce_binary $argv --eval --shell fish)
+        set -l cce_status $status
+        if test $cce_status -ne 0
+            return $cce_status
+        end
+        # The binary reports the switch on stderr
+        printf '%s\n' $env_output | source
+    else if test "$argv[1]" = "tui"
+        set -l env_output ($cce_binary tui --eval --shell fish)
+        if test $status -eq 0; and test -n "$env_output"
+            printf '%s\n' $env_output | source
+            echo "✅ Environment variables are now active in current terminal"
+        end
+    else if contains -- "$argv[1]" clear unset
+        set -l env_output ($cce_binary clear --eval --shell fish 2>/dev/null)
+        if test $status -eq 0; and test -n "$env_output"
+            printf '%s\n' $env_output | source
+            echo "🧹 Cleared service provider configuration"
+            echo "✅ Environment variables are now unset in current terminal"
+        else
+            $cce_binary $argv
+        end
+    else
+        $cce_binary $argv
+    end
+end"#,
+            cce_path
+        );
+    }
+
+    fn output_powershell_shellenv(cce_path: &str) {
+        println!(
+            r#"$env:CCE_SHELLENV_LOADED = "1"
+function cce {{
+    $cceBinary = "{}"
+
+    # Help and `use --print` only show information, so they run as is
+    # instead of being evaluated
+    if ($args | Select-Object -Skip 1 | Where-Object {{ $_ -in "-h", "--help", "--print" }}) {{
+        & $cceBinary @args
+        return
+    }}
+
+    if (($args.Count -ge 2 -and $args[0] -eq "use") -or ($args.Count -ge 1 -and $args[0] -eq "last")) {{
+        # Errors go straight to stderr; a failed switch exports nothing
+        $envOutput = & $cceBinary @args --eval --shell powershell
+        if ($LASTEXITCODE -eq 0) {{
+            # The binary reports the switch on stderr
+            $envOutput | Out-String | Invoke-Expression
+        }}
+    }} elseif ($args.Count -ge 1 -and $args[0] -eq "tui") {{
+        $envOutput = & $cceBinary tui --eval --shell powershell
+        if ($LASTEXITCODE -eq 0 -and $envOutput) {{
+            $envOutput | Out-String | Invoke-Expression
+            Write-Host "✅ Environment variables are now active in current terminal"
+        }}
+    }} elseif ($args.Count -ge 1 -and $args[0] -in "clear", "unset") {{
+        $envOutput = & $cceBinary clear --eval --shell powershell 2>$null
+        if ($LASTEXITCODE -eq 0 -and $envOutput) {{
+            $envOutput | Out-String | Invoke-Expression
+            Write-Host "🧹 Cleared service provider configuration"
+            Write-Host "✅ Environment variables are now unset in current terminal"
+        }} else {{
+            & $cceBinary @args
+        }}
+    }} else {{
+        & $cceBinary @args
+    }}
+}}"#,
+            cce_path
+        );
+    }
+
+    fn output_default_provider_hook(cce_path: &str, shell: Shell) {
+        match shell {
+            Shell::Bash | Shell::Zsh => println!(
+                r#"
+if [[ -z "$ANTHROPIC_BASE_URL" ]]; then
+    eval "$("{}" default --eval --shell bash 2>/dev/null)"
+fi"#,
+                cce_path
+            ),
+            Shell::Fish => println!(
+                r#"
+if not set -q ANTHROPIC_BASE_URL
+    "{}" default --eval --shell fish 2>/dev/null | source
+end"#,
+                cce_path
+            ),
+            Shell::Powershell => println!(
+                r#"
+if (-not $env:ANTHROPIC_BASE_URL) {{
+    & "{}" default --eval --shell powershell 2>$null | Out-String | Invoke-Expression
+}}"#,
+                cce_path
+            ),
+            Shell::Nu => println!(
+                r#"
+if ($env.ANTHROPIC_BASE_URL? | is-empty) {{
+    _cce_apply (^"{}" default --eval --shell nu | complete | get stdout)
+}}"#,
+                cce_path
+            ),
+            Shell::Cmd => {}
+        }
+    }
+
+    fn output_nu_shellenv(cce_path: &str) {
+        println!(
+            r#"$env.CCE_SHELLENV_LOADED = "1"
+
+# Applies the JSON lines printed by `cce ... --eval --shell nu`
+def --env _cce_apply [output: string] {{
+    let ops = ($output | lines | where {{|line| $line | str starts-with "{{" }} | each {{|line| $line | from json }})
+    load-env ($ops | where op == "set" | reduce --fold {{}} {{|op, acc| $acc | upsert $op.name $op.value }})
+    for op in ($ops | where op == "hide") {{
+        hide-env --ignore-errors $op.name
+    }}
+}}
+
+def --env --wrapped cce [...args] {{
+    let cce_binary = "{}"
+
+    # Help and `use --print` only show information, so they run as is
+    # instead of being evaluated
+    if ($args | skip 1 | any {{|arg| $arg in ["-h", "--help", "--print"] }}) {{
+        ^$cce_binary ...$args
+        return
+    }}
+
+    if (($args | get 0?) == "use" and ($args | length) >= 2) or ($args | get 0?) == "last" {{
+        # Errors are passed through; a failed switch exports nothing
+        let result = (^$cce_binary ...$args --eval --shell nu | complete)
+        print --stderr --no-newline $result.stderr
+        if $result.exit_code != 0 {{
+            return
+        }}
+        # The binary reports the switch on stderr, passed through above
+        _cce_apply $result.stdout
+    }} else if ($args | get 0?) == "tui" {{
+        # The UI is drawn on stderr; stdout carries the exports
+        let output = (^$cce_binary tui --eval --shell nu)
+        if $env.LAST_EXIT_CODE == 0 {{
+            _cce_apply $output
+            print "✅ Environment variables are now active in current terminal"
+        }}
+    }} else if ($args | get 0?) in ["clear", "unset"] {{
+        let result = (^$cce_binary clear --eval --shell nu | complete)
+        print --stderr --no-newline $result.stderr
+        if $result.exit_code != 0 {{
+            return
+        }}
+        _cce_apply $result.stdout
+        print "🧹 Cleared service provider configuration"
+        print "✅ Environment variables are now unset in current terminal"
+    }} else {{
+        ^$cce_binary ...$args
+    }}
+}}"#,
+            cce_path
+        );
+    }
+
+    fn output_cmd_shellenv(cce_path: &str) {
+        // cmd.exe has no shell functions, so only usage guidance can be emitted
+        println!(
+            r#"REM cmd.exe does not support shell functions; apply a provider with:
+REM   for /f "delims=" %i in ('"{}" use <name> --eval --shell cmd') do %i
+REM and clear it again with:
+REM   for /f "delims=" %i in ('"{}" unset --eval --shell cmd') do %i"#,
+            cce_path, cce_path
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_secret_marks_empty_tokens() {
+        assert_eq!(ProviderManager::mask_secret(""), "(empty)");
+    }
+
+    #[test]
+    fn mask_secret_hides_short_tokens_entirely() {
+        for secret in ["a", "abc", "sk-12", "sk-0123456789ab"] {
+            assert_eq!(ProviderManager::mask_secret(secret), "****", "{}", secret);
+        }
+    }
+
+    #[test]
+    fn mask_secret_shows_only_the_ends_of_long_tokens() {
+        assert_eq!(
+            ProviderManager::mask_secret("sk-ant-0123456789abcdef"),
+            "sk-****ef"
+        );
+        // Counts characters, not bytes
+        assert_eq!(
+            ProviderManager::mask_secret("ключ-ключ-ключ-ключ"),
+            "клю****юч"
+        );
+    }
+
+    #[test]
+    fn check_token_rejects_empty_tokens() {
+        for token in ["", " ", "\t\n"] {
+            assert!(ProviderManager::check_token(token).is_err(), "{:?}", token);
+        }
+    }
+
+    #[test]
+    fn check_token_accepts_single_character_tokens() {
+        ProviderManager::check_token("x").unwrap();
+        assert_eq!(ProviderManager::mask_secret("x"), "****");
+    }
+
+    #[test]
+    fn glob_star_alone_matches_everything() {
+        for name in ["", "a", "proj-a-1"] {
+            assert!(ProviderManager::glob_match("*", name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn glob_leading_and_trailing_stars() {
+        assert!(ProviderManager::glob_match("proj-a-*", "proj-a-1"));
+        assert!(ProviderManager::glob_match("proj-a-*", "proj-a-"));
+        assert!(!ProviderManager::glob_match("proj-a-*", "proj-b-1"));
+        assert!(ProviderManager::glob_match("*-staging", "api-staging"));
+        assert!(!ProviderManager::glob_match("*-staging", "api-staging-2"));
+        assert!(ProviderManager::glob_match("*a*", "banana"));
+        assert!(ProviderManager::glob_match("a*b*c", "aXbYbZc"));
+    }
+
+    #[test]
+    fn glob_without_match() {
+        assert!(!ProviderManager::glob_match("prod", "production"));
+        assert!(!ProviderManager::glob_match("prod", ""));
+        assert!(!ProviderManager::glob_match("a*z", "abc"));
+    }
+
+    #[test]
+    fn glob_metacharacters_are_literal() {
+        assert!(ProviderManager::glob_match("a.b", "a.b"));
+        assert!(!ProviderManager::glob_match("a.b", "axb"));
+        assert!(ProviderManager::glob_match("a?", "a?"));
+        assert!(!ProviderManager::glob_match("a?", "ab"));
+        assert!(ProviderManager::glob_match("[x]-*", "[x]-1"));
+        assert!(!ProviderManager::glob_match("[x]-*", "x-1"));
+    }
+
+    fn config_with(providers: &[(&str, &str)]) -> Config {
+        let mut config = Config::default();
+        for (name, api_url) in providers {
+            config.add_provider(Provider::new(
+                name.to_string(),
+                api_url.to_string(),
+                format!("tok-{}", name),
+            ));
+        }
+        config
+    }
+
+    fn sorted_names(config: &Config, sort: ListSort, filter: ListFilter) -> Vec<String> {
+        ProviderManager::sorted_providers(config, sort, filter)
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn list_order_is_stable() {
+        let providers = [
+            ("zeta", "https://b.example.com"),
+            ("alpha", "https://b.example.com"),
+            ("mid", "https://a.example.com"),
+            ("beta", "https://c.example.com"),
+        ];
+        let expected = ["alpha", "beta", "mid", "zeta"];
+        // HashMap order differs between instances; the sorted order must not
+        for _ in 0..10 {
+            let config = config_with(&providers);
+            assert_eq!(
+                sorted_names(&config, ListSort::Name, ListFilter::default()),
+                expected
+            );
+        }
+        let mut reversed = providers;
+        reversed.reverse();
+        assert_eq!(
+            sorted_names(
+                &config_with(&reversed),
+                ListSort::Name,
+                ListFilter::default()
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn list_ties_are_broken_by_name() {
+        let mut config = config_with(&[
+            ("zeta", "https://b.example.com"),
+            ("alpha", "https://b.example.com"),
+            ("mid", "https://a.example.com"),
+        ]);
+        assert_eq!(
+            sorted_names(&config, ListSort::Url, ListFilter::default()),
+            ["mid", "alpha", "zeta"]
+        );
+
+        config.providers.get_mut("zeta").unwrap().last_used = Some(Utc::now());
+        // Never-used providers come last, in name order
+        assert_eq!(
+            sorted_names(&config, ListSort::Recent, ListFilter::default()),
+            ["zeta", "alpha", "mid"]
+        );
+    }
+
+    #[test]
+    fn list_filter_matches_name_or_url() {
+        let config = config_with(&[
+            ("work", "https://gateway.corp.example.com"),
+            ("corp-backup", "https://backup.example.com"),
+            ("home", "https://api.example.com"),
+        ]);
+        let filter = ListFilter {
+            text: Some("CORP"),
+            ..ListFilter::default()
+        };
+        assert_eq!(
+            sorted_names(&config, ListSort::Name, filter),
+            ["corp-backup", "work"]
+        );
+    }
+
+    #[test]
+    fn random_pick_skips_zero_weights() {
+        let mut config = config_with(&[
+            ("never", "https://never.example.com"),
+            ("always", "https://always.example.com"),
+        ]);
+        config.providers.get_mut("never").unwrap().weight = Some(0);
+        for _ in 0..50 {
+            let picked =
+                ProviderManager::pick_provider(&mut config, None, Rotation::Random).unwrap();
+            assert_eq!(picked, "always");
+        }
+    }
+
+    #[test]
+    fn random_pick_fails_when_every_weight_is_zero() {
+        let mut config = config_with(&[("a", "https://a.example.com")]);
+        config.providers.get_mut("a").unwrap().weight = Some(0);
+        let error =
+            ProviderManager::pick_provider(&mut config, None, Rotation::Random).unwrap_err();
+        assert!(error.to_string().contains("weight 0"), "{}", error);
+    }
+
+    #[test]
+    fn pasted_values_are_trimmed() {
+        assert_eq!(
+            ProviderManager::trim_pasted(" sk-ant-token\n".to_string(), "token"),
+            "sk-ant-token"
+        );
+        assert_eq!(
+            ProviderManager::trim_pasted("\thttps://api.example.com \r\n".to_string(), "API URL"),
+            "https://api.example.com"
+        );
+        assert_eq!(
+            ProviderManager::trim_pasted("sk-ant token".to_string(), "token"),
+            "sk-ant token"
+        );
     }
 }
@@ -0,0 +1,129 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use keyring::Entry;
+
+/// Value written to the config file in place of a token that lives in the
+/// OS keyring.
+pub const KEYRING_SENTINEL: &str = "<keyring>";
+
+const SERVICE: &str = "cce";
+
+pub fn is_keyring_token(token: &str) -> bool {
+    token == KEYRING_SENTINEL
+}
+
+/// Keyring key of provider `name` in `profile`. Provider names repeat
+/// across profiles, so they cannot be keys on their own; entries written by
+/// config version 1 were, and are moved by the migration to version 2.
+pub fn key(profile: &str, name: &str) -> String {
+    format!("{}:{}", profile, name)
+}
+
+fn entry(key: &str) -> Result<Entry> {
+    Entry::new(SERVICE, key)
+        .with_context(|| format!("Failed to open keyring entry '{}:{}'", SERVICE, key))
+}
+
+pub fn store(key: &str, token: &str) -> Result<()> {
+    if Config::is_dry_run() {
+        return Ok(());
+    }
+    entry(key)?
+        .set_password(token)
+        .with_context(|| format!("Failed to store token for '{}' in the keyring", key))
+}
+
+pub fn fetch(key: &str) -> Result<String> {
+    entry(key)?
+        .get_password()
+        .with_context(|| format!("Failed to read token for '{}' from the keyring", key))
+}
+
+pub fn delete(key: &str) -> Result<()> {
+    if Config::is_dry_run() {
+        return Ok(());
+    }
+    match entry(key)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to delete token for '{}' from the keyring", key))
+        }
+    }
+}
+
+/// An in-memory keyring shared by all entries, so tests can see what
+/// commands store and delete without touching the real one.
+#[cfg(test)]
+pub(crate) mod mock {
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, MutexGuard, Once};
+
+    static SECRETS: Mutex<Option<HashMap<String, Vec<u8>>>> = Mutex::new(None);
+
+    struct Builder;
+
+    struct Secret {
+        user: String,
+    }
+
+    impl CredentialBuilderApi for Builder {
+        fn build(
+            &self,
+            _target: Option<&str>,
+            _service: &str,
+            user: &str,
+        ) -> keyring::Result<Box<Credential>> {
+            Ok(Box::new(Secret {
+                user: user.to_string(),
+            }))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl CredentialApi for Secret {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            with_secrets(|secrets| secrets.insert(self.user.clone(), secret.to_vec()));
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            with_secrets(|secrets| secrets.get(&self.user).cloned()).ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            with_secrets(|secrets| secrets.remove(&self.user))
+                .map(drop)
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn with_secrets<T>(f: impl FnOnce(&mut HashMap<String, Vec<u8>>) -> T) -> T {
+        let mut secrets = SECRETS.lock().unwrap();
+        f(secrets.get_or_insert_with(HashMap::new))
+    }
+
+    /// Routes every keyring entry created from now on to the mock. Tests
+    /// using it hold the returned guard, as they also toggle and depend on
+    /// the global dry-run flag.
+    pub fn install() -> MutexGuard<'static, ()> {
+        static INSTALL: Once = Once::new();
+        static IN_USE: Mutex<()> = Mutex::new(());
+        INSTALL.call_once(|| keyring::set_default_credential_builder(Box::new(Builder)));
+        IN_USE.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The token stored under `key`, if any.
+    pub fn token(key: &str) -> Option<String> {
+        with_secrets(|secrets| secrets.get(key).cloned())
+            .map(|secret| String::from_utf8(secret).unwrap())
+    }
+}
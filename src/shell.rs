@@ -0,0 +1,189 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+    Cmd,
+    Nu,
+}
+
+impl Shell {
+    /// Detects the user's shell from `SHELL`, then `PSModulePath` (set by
+    /// PowerShell), falling back to cmd on Windows and bash elsewhere.
+    pub fn detect() -> Self {
+        if let Ok(shell) = std::env::var("SHELL") {
+            let shell_name = shell.rsplit(['/', '\\']).next().unwrap_or("");
+
+            return match shell_name.trim_end_matches(".exe") {
+                "zsh" => Shell::Zsh,
+                "fish" => Shell::Fish,
+                "pwsh" | "powershell" => Shell::Powershell,
+                "nu" => Shell::Nu,
+                _ => Shell::Bash,
+            };
+        }
+
+        if std::env::var_os("PSModulePath").is_some() {
+            Shell::Powershell
+        } else if cfg!(windows) {
+            Shell::Cmd
+        } else {
+            Shell::Bash
+        }
+    }
+
+    /// Name of the shell in `SHELL` when `detect` only falls back to bash
+    /// for it, e.g. `nu` or `tcsh`, whose syntax cce cannot emit.
+    pub fn unsupported_login_shell() -> Option<String> {
+        let shell = std::env::var("SHELL").ok()?;
+        let shell_name = shell.rsplit(['/', '\\']).next().unwrap_or("");
+        let shell_name = shell_name.trim_end_matches(".exe");
+
+        match shell_name {
+            "" | "sh" | "bash" | "zsh" | "fish" | "pwsh" | "powershell" | "nu" => None,
+            other => Some(other.to_string()),
+        }
+    }
+
+    /// Values accepted by `--shell`, comma-separated.
+    pub fn supported_names() -> String {
+        Self::value_variants()
+            .iter()
+            .filter_map(|shell| shell.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Nushell cannot eval text, so for it each command is a JSON line that
+    /// the `shellenv` wrapper applies with `load-env` / `hide-env`.
+    pub fn export(&self, key: &str, value: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("export {}={}", key, Self::posix_quote(value)),
+            Shell::Fish => format!("set -gx {} {}", key, Self::fish_quote(value)),
+            Shell::Powershell => format!("$env:{} = {}", key, Self::powershell_quote(value)),
+            // Quoting the whole assignment keeps & | < > literal; cmd has no
+            // way to escape % at the prompt, so it is doubled as in batch files.
+            Shell::Cmd => format!("set \"{}={}\"", key, value.replace('%', "%%")),
+            Shell::Nu => {
+                serde_json::json!({ "op": "set", "name": key, "value": value }).to_string()
+            }
+        }
+    }
+
+    pub fn unset(&self, key: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("unset {}", key),
+            Shell::Fish => format!("set -e {}", key),
+            Shell::Powershell => {
+                format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", key)
+            }
+            Shell::Cmd => format!("set \"{}=\"", key),
+            Shell::Nu => serde_json::json!({ "op": "hide", "name": key }).to_string(),
+        }
+    }
+
+    /// Command printing `message` on stderr when the output is evaluated.
+    /// `None` for cmd, whose `for /f` loop only captures stdout, and for
+    /// nushell, whose wrapper prints stderr itself, so writing to stderr
+    /// directly already reaches the user.
+    pub fn echo_stderr(&self, message: &str) -> Option<String> {
+        match self {
+            Shell::Bash | Shell::Zsh => {
+                Some(format!("printf '%s\\n' {} >&2", Self::posix_quote(message)))
+            }
+            Shell::Fish => Some(format!("echo {} >&2", Self::fish_quote(message))),
+            Shell::Powershell => Some(format!(
+                "[Console]::Error.WriteLine({})",
+                Self::powershell_quote(message)
+            )),
+            Shell::Cmd | Shell::Nu => None,
+        }
+    }
+
+    /// Single quotes disable every expansion in POSIX shells; an embedded
+    /// quote is closed, escaped, and reopened.
+    pub(crate) fn posix_quote(value: &str) -> String {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+
+    /// Fish single quotes only treat `\\` and `\'` specially.
+    fn fish_quote(value: &str) -> String {
+        format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+    }
+
+    /// PowerShell single-quoted strings are verbatim except for doubled quotes;
+    /// typographic single quotes also terminate them and are doubled as well.
+    fn powershell_quote(value: &str) -> String {
+        let mut quoted = String::with_capacity(value.len() + 2);
+        quoted.push('\'');
+        for c in value.chars() {
+            if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                quoted.push(c);
+            }
+            quoted.push(c);
+        }
+        quoted.push('\'');
+        quoted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRICKY: &str = r#"it's "a" $HOME"#;
+
+    #[test]
+    fn export_quotes_for_each_shell() {
+        let cases = [
+            (Shell::Bash, r#"export KEY='it'\''s "a" $HOME'"#),
+            (Shell::Zsh, r#"export KEY='it'\''s "a" $HOME'"#),
+            (Shell::Fish, r#"set -gx KEY 'it\'s "a" $HOME'"#),
+            (Shell::Powershell, r#"$env:KEY = 'it''s "a" $HOME'"#),
+            (Shell::Cmd, r#"set "KEY=it's "a" $HOME""#),
+            (
+                Shell::Nu,
+                r#"{"name":"KEY","op":"set","value":"it's \"a\" $HOME"}"#,
+            ),
+        ];
+        for (shell, expected) in cases {
+            assert_eq!(shell.export("KEY", TRICKY), expected, "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn export_escapes_shell_specific_characters() {
+        assert_eq!(Shell::Fish.export("KEY", r"a\b"), r"set -gx KEY 'a\\b'");
+        assert_eq!(
+            Shell::Powershell.export("KEY", "it\u{2019}s"),
+            "$env:KEY = 'it\u{2019}\u{2019}s'"
+        );
+        assert_eq!(
+            Shell::Cmd.export("KEY", "100% & more"),
+            r#"set "KEY=100%% & more""#
+        );
+        assert_eq!(Shell::Bash.export("KEY", ""), "export KEY=''");
+    }
+
+    #[test]
+    fn unset_for_each_shell() {
+        let cases = [
+            (Shell::Bash, "unset KEY"),
+            (Shell::Zsh, "unset KEY"),
+            (Shell::Fish, "set -e KEY"),
+            (
+                Shell::Powershell,
+                "Remove-Item Env:KEY -ErrorAction SilentlyContinue",
+            ),
+            (Shell::Cmd, r#"set "KEY=""#),
+            (Shell::Nu, r#"{"name":"KEY","op":"hide"}"#),
+        ];
+        for (shell, expected) in cases {
+            assert_eq!(shell.unset("KEY"), expected, "{:?}", shell);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+/// Plain-text table with left-aligned columns separated by two spaces, as
+/// printed by `--format table`. Cells carry no colors, so the output can be
+/// read by `awk`, `cut`, and the like; empty cells are shown as `-`.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Self {
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.headers.len());
+        self.rows.push(row);
+    }
+
+    pub fn render(&self) -> String {
+        let cell = |value: &str| -> String {
+            if value.is_empty() {
+                "-".to_string()
+            } else {
+                value.to_string()
+            }
+        };
+        let lines: Vec<Vec<String>> = std::iter::once(
+            self.headers
+                .iter()
+                .map(|header| header.to_uppercase())
+                .collect(),
+        )
+        .chain(
+            self.rows
+                .iter()
+                .map(|row| row.iter().map(|value| cell(value)).collect()),
+        )
+        .collect();
+
+        let widths: Vec<usize> = (0..self.headers.len())
+            .map(|column| {
+                lines
+                    .iter()
+                    .map(|line| line[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut output = String::new();
+        for line in &lines {
+            let last = line.len() - 1;
+            for (column, value) in line.iter().enumerate() {
+                if column == last {
+                    // The last column is not padded, so lines have no trailing spaces
+                    output.push_str(value);
+                } else {
+                    let padding = widths[column] - value.chars().count();
+                    output.push_str(value);
+                    output.push_str(&" ".repeat(padding + 2));
+                }
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.render());
+    }
+}
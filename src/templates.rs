@@ -0,0 +1,50 @@
+use crate::error::CceError;
+use anyhow::Result;
+
+/// Preset for a well-known Claude-compatible endpoint, used by
+/// `cce add --template`.
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub api_url: &'static str,
+    pub model: Option<&'static str>,
+    pub extra_env: &'static [(&'static str, &'static str)],
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "anthropic",
+        description: "Anthropic API",
+        api_url: "https://api.anthropic.com",
+        model: None,
+        extra_env: &[],
+    },
+    Template {
+        name: "openrouter",
+        description: "OpenRouter",
+        api_url: "https://openrouter.ai/api",
+        model: None,
+        extra_env: &[],
+    },
+    Template {
+        name: "deepseek",
+        description: "DeepSeek Anthropic-compatible API",
+        api_url: "https://api.deepseek.com/anthropic",
+        model: Some("deepseek-chat"),
+        extra_env: &[],
+    },
+    Template {
+        name: "bigmodel",
+        description: "Zhipu BigModel (GLM) Anthropic-compatible API",
+        api_url: "https://open.bigmodel.cn/api/anthropic",
+        model: Some("glm-4.5"),
+        extra_env: &[],
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static Template> {
+    TEMPLATES
+        .iter()
+        .find(|template| template.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| CceError::TemplateNotFound(name.to_string()).into())
+}
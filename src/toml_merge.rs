@@ -0,0 +1,155 @@
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+/// Rewrites the `existing` TOML document so it holds the same data as
+/// `updated`, keeping the comments, key order, and formatting of everything
+/// that did not change. New tables are placed after their last existing
+/// sibling. Returns `None` when either document does not parse.
+pub fn merge(existing: &str, updated: &str) -> Option<String> {
+    let mut document: DocumentMut = existing.parse().ok()?;
+    let updated: DocumentMut = updated.parse().ok()?;
+
+    spread_positions(document.as_table_mut());
+    merge_table(document.as_table_mut(), updated.as_table());
+    Some(document.to_string())
+}
+
+/// Tables are printed in order of their position. Spacing the existing
+/// positions out leaves room to slot new tables in next to their siblings.
+const POSITION_GAP: usize = 1 << 16;
+
+fn spread_positions(table: &mut Table) {
+    if let Some(position) = table.position() {
+        table.set_position(position * POSITION_GAP);
+    }
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(table) => spread_positions(table),
+            Item::ArrayOfTables(tables) => tables.iter_mut().for_each(spread_positions),
+            Item::None | Item::Value(_) => {}
+        }
+    }
+}
+
+fn merge_table(old: &mut Table, new: &Table) {
+    let stale: Vec<String> = old
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in stale {
+        old.remove(&key);
+    }
+
+    for (key, item) in new.iter() {
+        match old.get_mut(key) {
+            Some(existing) => merge_item(existing, item),
+            None => {
+                let mut item = item.clone();
+                reposition(&mut item, &mut (last_position(old) + 1));
+                old.insert(key, item);
+            }
+        }
+    }
+}
+
+fn merge_item(old: &mut Item, new: &Item) {
+    match (old, new) {
+        (Item::Table(old), Item::Table(new)) => merge_table(old, new),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new)) => {
+            while old.len() > new.len() {
+                old.remove(old.len() - 1);
+            }
+            for (index, table) in new.iter().enumerate() {
+                match old.get_mut(index) {
+                    Some(existing) => merge_table(existing, table),
+                    None => {
+                        let next = old.iter().map(last_position).max().unwrap_or(0) + 1;
+                        let mut table = table.clone();
+                        reposition_table(&mut table, &mut { next });
+                        old.push(table);
+                    }
+                }
+            }
+        }
+        (Item::Value(old), Item::Value(new)) => merge_value(old, new.clone()),
+        // Maps written inline by hand stay inline
+        (Item::Value(old @ Value::InlineTable(_)), Item::Table(new)) => {
+            merge_value(old, Value::InlineTable(new.clone().into_inline_table()))
+        }
+        (old, new) => {
+            let next = match &*old {
+                Item::Table(table) => table.position().unwrap_or(0),
+                Item::ArrayOfTables(tables) => tables.iter().map(last_position).max().unwrap_or(0),
+                Item::None | Item::Value(_) => 0,
+            };
+            let mut item = new.clone();
+            reposition(&mut item, &mut { next });
+            *old = item;
+        }
+    }
+}
+
+fn merge_value(old: &mut Value, new: Value) {
+    if !same_value(old, &new) {
+        // The decor holds comments at the end of the line
+        let decor = old.decor().clone();
+        *old = new;
+        *old.decor_mut() = decor;
+    }
+}
+
+/// Compares values while ignoring how they are formatted.
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same_value(a, b))
+        }
+        (Value::InlineTable(a), Value::InlineTable(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same_value(a, b)))
+        }
+        _ => false,
+    }
+}
+
+/// Gives tables copied from the updated document consecutive positions
+/// starting at `next_position`.
+fn reposition(item: &mut Item, next_position: &mut usize) {
+    match item {
+        Item::Table(table) => reposition_table(table, next_position),
+        Item::ArrayOfTables(tables) => {
+            for table in tables.iter_mut() {
+                reposition_table(table, next_position);
+            }
+        }
+        Item::None | Item::Value(_) => {}
+    }
+}
+
+fn reposition_table(table: &mut Table, next_position: &mut usize) {
+    table.set_position(*next_position);
+    *next_position += 1;
+    for (_, item) in table.iter_mut() {
+        reposition(item, next_position);
+    }
+}
+
+/// The highest position of `table` and the tables nested in it.
+fn last_position(table: &Table) -> usize {
+    table
+        .iter()
+        .map(|(_, item)| match item {
+            Item::Table(table) => last_position(table),
+            Item::ArrayOfTables(tables) => tables.iter().map(last_position).max().unwrap_or(0),
+            Item::None | Item::Value(_) => 0,
+        })
+        .max()
+        .unwrap_or(0)
+        .max(table.position().unwrap_or(0))
+}
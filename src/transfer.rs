@@ -0,0 +1,73 @@
+use crate::config::Provider;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Placeholder written instead of the real token in redacted exports.
+pub const REDACTED_TOKEN: &str = "***";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Yaml,
+}
+
+impl ExportFormat {
+    /// Picks the format from the file extension, falling back to sniffing
+    /// the content (JSON documents start with `{`).
+    pub fn detect(path: &Path, content: &str) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ExportFormat::Json,
+            Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml") => {
+                ExportFormat::Yaml
+            }
+            _ if content.trim_start().starts_with('{') => ExportFormat::Json,
+            _ => ExportFormat::Yaml,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportStrategy {
+    /// Add new providers and overwrite providers with the same name
+    Merge,
+    /// Remove all existing providers before importing
+    Replace,
+    /// Keep the local version of providers that already exist
+    SkipExisting,
+}
+
+/// Portable representation of the provider list used by `cce export` and
+/// `cce import`; providers are kept sorted so exports diff cleanly.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedConfig {
+    pub providers: BTreeMap<String, Provider>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_provider: Option<String>,
+}
+
+impl ExportedConfig {
+    pub fn parse(content: &str, format: ExportFormat) -> Result<Self> {
+        match format {
+            ExportFormat::Json => {
+                serde_json::from_str(content).with_context(|| "Invalid JSON export file")
+            }
+            ExportFormat::Yaml => {
+                serde_yaml::from_str(content).with_context(|| "Invalid YAML export file")
+            }
+        }
+    }
+
+    pub fn serialize(&self, format: ExportFormat) -> Result<String> {
+        match format {
+            ExportFormat::Json => {
+                serde_json::to_string_pretty(self).with_context(|| "Failed to serialize JSON")
+            }
+            ExportFormat::Yaml => {
+                serde_yaml::to_string(self).with_context(|| "Failed to serialize YAML")
+            }
+        }
+    }
+}
@@ -0,0 +1,528 @@
+use crate::config::{Config, Provider};
+use crate::connectivity::{self, ProbeStatus};
+use crate::error::CceError;
+use crate::provider::ProviderManager;
+use anyhow::{anyhow, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+const FORM_LABELS: [&str; 3] = ["Name", "API URL", "Token"];
+
+enum Mode {
+    Browse,
+    Form(Form),
+    ConfirmDelete(String),
+}
+
+/// Add/edit form; `editing` is the provider being edited, `None` when adding.
+struct Form {
+    editing: Option<String>,
+    fields: [String; 3],
+    focus: usize,
+}
+
+enum Action {
+    None,
+    Quit,
+    Test(String),
+}
+
+struct App<'a> {
+    config: &'a mut Config,
+    names: Vec<String>,
+    list: ListState,
+    mode: Mode,
+    /// Last outcome shown in the footer; `true` marks an error
+    status: Option<(String, bool)>,
+    switched: bool,
+    timeout: Duration,
+}
+
+/// Restores the terminal even when the event loop bails out with an error.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stderr(), LeaveAlternateScreen);
+    }
+}
+
+/// Runs the dashboard until the user quits. The UI is drawn on stderr so
+/// that `--eval` output on stdout stays clean for the shell wrapper.
+/// Returns whether the current provider was switched.
+pub fn run(config: &mut Config, timeout: Duration) -> Result<bool> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(anyhow!("cce tui needs an interactive terminal"));
+    }
+
+    // Ask for the master password before the terminal switches to raw mode
+    config.unlock()?;
+
+    // Error messages are rendered as plain text inside the UI
+    let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+    colored::control::set_override(false);
+
+    let result = run_app(config, timeout);
+
+    colored::control::set_override(colorize);
+    result
+}
+
+fn run_app(config: &mut Config, timeout: Duration) -> Result<bool> {
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+
+    let mut app = App::new(config, timeout);
+    loop {
+        terminal.draw(|frame| app.render(frame))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match app.handle_key(key) {
+            Action::None => {}
+            Action::Quit => break,
+            Action::Test(name) => {
+                app.status = Some((format!("Testing '{}'...", name), false));
+                terminal.draw(|frame| app.render(frame))?;
+                app.test(&name);
+            }
+        }
+    }
+
+    Ok(app.switched)
+}
+
+impl<'a> App<'a> {
+    fn new(config: &'a mut Config, timeout: Duration) -> Self {
+        let mut app = Self {
+            config,
+            names: Vec::new(),
+            list: ListState::default(),
+            mode: Mode::Browse,
+            status: None,
+            switched: false,
+            timeout,
+        };
+        app.refresh(None);
+
+        let current = app.config.current_provider.clone();
+        if let Some(index) = current.and_then(|current| app.position(&current)) {
+            app.list.select(Some(index));
+        }
+        app
+    }
+
+    /// Reloads the sorted provider names, keeping `select` (or the current
+    /// row) selected when it still exists.
+    fn refresh(&mut self, select: Option<&str>) {
+        let keep = select
+            .map(str::to_string)
+            .or_else(|| self.selected().map(str::to_string));
+
+        self.names = self.config.providers.keys().cloned().collect();
+        self.names.sort();
+
+        // A removed provider leaves the selection on the row it occupied
+        let index = if self.names.is_empty() {
+            None
+        } else {
+            let index = keep
+                .and_then(|name| self.position(&name))
+                .or(self.list.selected())
+                .unwrap_or(0);
+            Some(index.min(self.names.len() - 1))
+        };
+        self.list.select(index);
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|candidate| candidate == name)
+    }
+
+    fn selected(&self) -> Option<&str> {
+        self.list
+            .selected()
+            .and_then(|index| self.names.get(index))
+            .map(String::as_str)
+    }
+
+    fn report(&mut self, result: Result<String>) {
+        self.status = Some(match result {
+            Ok(message) => (message, false),
+            Err(e) => (format!("{:#}", e), true),
+        });
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => self.handle_browse_key(key),
+            Mode::Form(form) => {
+                self.handle_form_key(form, key);
+                Action::None
+            }
+            Mode::ConfirmDelete(name) => {
+                if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                    let result = self.delete(&name);
+                    self.report(result);
+                } else {
+                    self.status = None;
+                }
+                Action::None
+            }
+        }
+    }
+
+    fn handle_browse_key(&mut self, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Enter => {
+                if let Some(name) = self.selected().map(str::to_string) {
+                    let result = self.switch(&name);
+                    self.report(result);
+                }
+            }
+            KeyCode::Char('a') => {
+                self.mode = Mode::Form(Form {
+                    editing: None,
+                    fields: Default::default(),
+                    focus: 0,
+                });
+            }
+            KeyCode::Char('e') => {
+                if let Some(name) = self.selected().map(str::to_string) {
+                    let api_url = self.config.providers[&name].api_url.clone();
+                    self.mode = Mode::Form(Form {
+                        editing: Some(name.clone()),
+                        fields: [name, api_url, String::new()],
+                        focus: 1,
+                    });
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(name) = self.selected().map(str::to_string) {
+                    self.mode = Mode::ConfirmDelete(name);
+                }
+            }
+            KeyCode::Char('t') => {
+                if let Some(name) = self.selected() {
+                    return Action::Test(name.to_string());
+                }
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn handle_form_key(&mut self, mut form: Form, key: KeyEvent) {
+        // The name of an edited provider is fixed; use `cce rename` for that
+        let first = if form.editing.is_some() { 1 } else { 0 };
+
+        match key.code {
+            KeyCode::Esc => return,
+            KeyCode::Enter => {
+                let result = self.submit(&form);
+                let saved = result.is_ok();
+                self.report(result);
+                if saved {
+                    return;
+                }
+            }
+            KeyCode::Tab | KeyCode::Down => {
+                form.focus = if form.focus + 1 < FORM_LABELS.len() {
+                    form.focus + 1
+                } else {
+                    first
+                };
+            }
+            KeyCode::BackTab | KeyCode::Up => {
+                form.focus = if form.focus > first {
+                    form.focus - 1
+                } else {
+                    FORM_LABELS.len() - 1
+                };
+            }
+            KeyCode::Backspace => {
+                form.fields[form.focus].pop();
+            }
+            KeyCode::Char(c) => form.fields[form.focus].push(c),
+            _ => {}
+        }
+
+        self.mode = Mode::Form(form);
+    }
+
+    fn switch(&mut self, name: &str) -> Result<String> {
+        self.config.set_current_provider(name);
+        self.config.save()?;
+        self.switched = true;
+        Ok(format!("Switched to '{}'", name))
+    }
+
+    fn submit(&mut self, form: &Form) -> Result<String> {
+        let [name, api_url, token] = &form.fields;
+        let api_url = ProviderManager::normalize_api_url(api_url.trim(), true)?;
+
+        let name = match &form.editing {
+            Some(name) => {
+                ProviderManager::ensure_unlocked(self.config, name, false)?;
+                let token = match token.trim() {
+                    "" => None,
+                    token => Some(ProviderManager::store_token(
+                        self.config,
+                        name,
+                        token.to_string(),
+                    )?),
+                };
+                let provider = self.config.providers.get_mut(name).unwrap();
+                provider.api_url = api_url;
+                if let Some(token) = token {
+                    provider.token = token;
+                }
+                name.clone()
+            }
+            None => {
+                let name = name.trim().to_string();
+                if name.is_empty() {
+                    return Err(anyhow!("Provider name must not be empty"));
+                }
+                if self.config.providers.contains_key(&name) {
+                    return Err(CceError::ProviderExists(name).into());
+                }
+                if self.config.aliases.contains_key(&name) {
+                    return Err(anyhow!("'{}' is already an alias", name));
+                }
+                ProviderManager::check_token(token.trim())?;
+
+                let token = ProviderManager::store_token(self.config, &name, token.trim().into())?;
+                self.config
+                    .add_provider(Provider::new(name.clone(), api_url, token));
+                name
+            }
+        };
+
+        self.config.save()?;
+        self.refresh(Some(&name));
+        Ok(match form.editing {
+            Some(_) => format!("Updated '{}'", name),
+            None => format!("Added '{}'", name),
+        })
+    }
+
+    fn delete(&mut self, name: &str) -> Result<String> {
+        ProviderManager::ensure_unlocked(self.config, name, false)?;
+        ProviderManager::forget_provider(self.config, name);
+        self.config.save()?;
+        self.refresh(None);
+        Ok(format!("Removed '{}'", name))
+    }
+
+    fn test(&mut self, name: &str) {
+        let provider = &self.config.providers[name];
+        let result = self
+            .config
+            .resolved_provider(provider)
+            .and_then(|resolved| {
+                connectivity::probe(
+                    &resolved.api_url,
+                    &resolved.token,
+                    provider.proxy_for_api(),
+                    &provider.headers,
+                    self.timeout,
+                )
+            });
+
+        self.status = Some(match result {
+            Ok(result) => {
+                let (outcome, failed) = match result.status {
+                    ProbeStatus::Ok(code) => (format!("OK (HTTP {})", code.as_u16()), false),
+                    ProbeStatus::AuthFailed(code) => {
+                        (format!("auth failed (HTTP {})", code.as_u16()), true)
+                    }
+                    ProbeStatus::Unexpected(code) => (
+                        format!("reachable, unexpected response (HTTP {})", code.as_u16()),
+                        true,
+                    ),
+                    ProbeStatus::Unreachable(reason) => (format!("unreachable ({})", reason), true),
+                };
+                (
+                    format!(
+                        "'{}': {} in {} ms",
+                        name,
+                        outcome,
+                        result.latency.as_millis()
+                    ),
+                    failed,
+                )
+            }
+            Err(e) => (format!("{:#}", e), true),
+        });
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [header, body, details, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(format!(
+                " cce · profile: {} · {} provider(s)",
+                self.config.active_profile(),
+                self.names.len()
+            ))
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+            header,
+        );
+
+        let current = self.config.current_provider.as_deref();
+        let items: Vec<ListItem> = self
+            .names
+            .iter()
+            .map(|name| {
+                let provider = &self.config.providers[name];
+                let (marker, style) = if Some(name.as_str()) == current {
+                    (
+                        "● ",
+                        Style::new().fg(Color::Green).add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    ("○ ", Style::new())
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{}{}", marker, name), style),
+                    Span::styled(
+                        format!("  {}", provider.api_url),
+                        Style::new().fg(Color::Cyan),
+                    ),
+                ]))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::new().borders(Borders::ALL).title(" Providers "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, body, &mut self.list);
+
+        frame.render_widget(self.details(), details);
+        frame.render_widget(self.footer(), footer);
+
+        if let Mode::Form(form) = &self.mode {
+            Self::render_form(frame, form);
+        }
+    }
+
+    fn details(&self) -> Paragraph<'_> {
+        let lines = match self.selected().map(|name| &self.config.providers[name]) {
+            Some(provider) => vec![
+                Line::from(format!("API URL: {}", provider.api_url)),
+                Line::from(format!(
+                    "Token: {}",
+                    ProviderManager::masked_token(self.config, provider)
+                )),
+                Line::from(format!(
+                    "Model: {}",
+                    provider.model.as_deref().unwrap_or("(default)")
+                )),
+                Line::from(format!(
+                    "Last used: {}",
+                    ProviderManager::format_last_used(provider.last_used)
+                )),
+            ],
+            None => vec![Line::from(
+                "No service providers configured; press 'a' to add one",
+            )],
+        };
+        Paragraph::new(lines).block(Block::new().borders(Borders::ALL).title(" Details "))
+    }
+
+    fn footer(&self) -> Paragraph<'_> {
+        if let Mode::ConfirmDelete(name) = &self.mode {
+            return Paragraph::new(format!(" Delete '{}'? (y/n)", name))
+                .style(Style::new().fg(Color::Yellow));
+        }
+        if let Mode::Form(_) = &self.mode {
+            return Paragraph::new(" Tab: next field · Enter: save · Esc: cancel");
+        }
+
+        match &self.status {
+            Some((message, true)) => {
+                Paragraph::new(format!(" {}", message)).style(Style::new().fg(Color::Red))
+            }
+            Some((message, false)) => {
+                Paragraph::new(format!(" {}", message)).style(Style::new().fg(Color::Green))
+            }
+            None => {
+                Paragraph::new(" Enter: switch · a: add · e: edit · d: delete · t: test · q: quit")
+            }
+        }
+    }
+
+    fn render_form(frame: &mut Frame, form: &Form) {
+        let area = centered(frame.area(), 60, FORM_LABELS.len() as u16 + 2);
+        let title = match &form.editing {
+            Some(name) => format!(" Edit '{}' (empty token keeps the current one) ", name),
+            None => " Add provider ".to_string(),
+        };
+
+        let lines: Vec<Line> = FORM_LABELS
+            .iter()
+            .zip(&form.fields)
+            .enumerate()
+            .map(|(index, (label, value))| {
+                // Never echo the token
+                let shown = if index == 2 {
+                    "*".repeat(value.chars().count())
+                } else {
+                    value.clone()
+                };
+                let style = if index == form.focus {
+                    Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                } else {
+                    Style::new()
+                };
+                Line::from(vec![
+                    Span::styled(format!("{:>8}: ", label), style),
+                    Span::raw(shown),
+                ])
+            })
+            .collect();
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::new().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+}
+
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
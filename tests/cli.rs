@@ -0,0 +1,528 @@
+//! End-to-end tests running the `cce` binary against a config file in a
+//! temporary directory, with a clean environment.
+
+use fs2::FileExt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const TWO_PROVIDERS: &str = r#"version = 2
+
+[providers.a]
+name = "a"
+api_url = "https://a.example.com"
+token = "tok-aaaaaa"
+
+[providers.b]
+name = "b"
+api_url = "https://b.example.com"
+token = "tok-bbbbbb"
+"#;
+
+struct Sandbox {
+    dir: tempfile::TempDir,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    fn with_config(content: &str) -> Self {
+        let sandbox = Self::new();
+        sandbox.write_config(content);
+        sandbox
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.dir.path().join("config.toml")
+    }
+
+    fn write_config(&self, content: &str) {
+        fs::write(self.config_path(), content).unwrap();
+    }
+
+    fn read_config(&self) -> String {
+        fs::read_to_string(self.config_path()).unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = self.isolated(env!("CARGO_BIN_EXE_cce"));
+        command.args(args);
+        command
+    }
+
+    /// `program` with an environment pointing only at the sandbox.
+    fn isolated(&self, program: &str) -> Command {
+        let mut command = Command::new(program);
+        command
+            .env_clear()
+            .env("HOME", self.dir.path())
+            .env("CCE_CONFIG", self.config_path())
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    fn spawn(&self, args: &[&str]) -> Child {
+        self.command(args).spawn().unwrap()
+    }
+
+    /// Runs `script` in `shell` with the `shellenv` wrapper loaded, or
+    /// returns `None` when that shell is not installed.
+    fn run_wrapped(&self, shell: &str, script: &str) -> Option<Output> {
+        let script = format!(
+            "eval \"$('{}' shellenv --shell {})\"\n{}",
+            env!("CARGO_BIN_EXE_cce"),
+            shell,
+            script
+        );
+        let output = self
+            .isolated(shell)
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .args(["-c", &script])
+            .output();
+        match output {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            output => Some(output.unwrap()),
+        }
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn concurrent_switches_are_all_recorded() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+
+    let children: Vec<Child> = (0..8)
+        .map(|i| sandbox.spawn(&["use", if i % 2 == 0 { "a" } else { "b" }]))
+        .collect();
+    let mut switches = 0;
+    for child in children {
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        // Switching to the provider that is already current saves nothing
+        if stdout(&output).contains("Switched to") {
+            switches += 1;
+        }
+    }
+
+    // Without the lock, saves racing on the same file drop each other's entries
+    let config: toml::Value = toml::from_str(&sandbox.read_config()).unwrap();
+    let history = config["history"].as_array().unwrap();
+    assert_eq!(history.len(), switches);
+    assert_eq!(
+        history.last().unwrap()["provider"].as_str(),
+        config["current_provider"].as_str()
+    );
+}
+
+#[test]
+fn command_waits_for_the_config_lock() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+    let lock = fs::File::create(sandbox.dir.path().join("config.toml.lock")).unwrap();
+    lock.lock_exclusive().unwrap();
+
+    let mut child = sandbox.spawn(&["use", "a"]);
+    thread::sleep(Duration::from_millis(500));
+    assert!(child.try_wait().unwrap().is_none(), "cce did not wait");
+    assert!(!sandbox.read_config().contains("current_provider"));
+
+    lock.unlock().unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox.read_config().contains("current_provider = \"a\""));
+}
+
+#[test]
+fn delete_all_removes_matching_providers() {
+    let sandbox = Sandbox::with_config(
+        r#"version = 2
+current_provider = "proj-a-1"
+
+[providers.proj-a-1]
+name = "proj-a-1"
+api_url = "https://a1.example.com"
+token = "tok-a1"
+
+[providers.proj-a-2]
+name = "proj-a-2"
+api_url = "https://a2.example.com"
+token = "tok-a2"
+
+[providers.proj-b-1]
+name = "proj-b-1"
+api_url = "https://b1.example.com"
+token = "tok-b1"
+"#,
+    );
+
+    let output = sandbox.run(&["delete", "--all", "proj-a-*", "--yes"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Removed 2 service provider(s)"));
+
+    let config: toml::Value = toml::from_str(&sandbox.read_config()).unwrap();
+    let names: Vec<&String> = config["providers"].as_table().unwrap().keys().collect();
+    assert_eq!(names, ["proj-b-1"]);
+    assert!(config.get("current_provider").is_none());
+}
+
+#[test]
+fn v0_config_is_migrated_with_a_backup() {
+    let fixture = include_str!("fixtures/config-v0.toml");
+    let sandbox = Sandbox::with_config(fixture);
+
+    let output = sandbox.run(&["list"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let backup = fs::read_to_string(sandbox.dir.path().join("config.toml.bak")).unwrap();
+    assert_eq!(backup, fixture);
+
+    let migrated = sandbox.read_config();
+    assert!(migrated.starts_with("# Written by cce 0.1"), "{}", migrated);
+    let config: toml::Value = toml::from_str(&migrated).unwrap();
+    assert_eq!(config["version"].as_integer(), Some(2));
+    assert_eq!(config["current_provider"].as_str(), Some("anthropic"));
+
+    let providers = config["providers"].as_table().unwrap();
+    assert_eq!(providers.len(), 2);
+    assert_eq!(
+        providers["anthropic"]["api_url"].as_str(),
+        Some("https://api.anthropic.com")
+    );
+    assert_eq!(
+        providers["anthropic"]["token"].as_str(),
+        Some("sk-ant-v0-token")
+    );
+    assert_eq!(
+        providers["proxy"]["api_url"].as_str(),
+        Some("https://proxy.example.com")
+    );
+    assert_eq!(
+        providers["proxy"]["token"].as_str(),
+        Some("sk-proxy-v0-token")
+    );
+    assert_eq!(
+        providers["proxy"]["model"].as_str(),
+        Some("claude-sonnet-4")
+    );
+}
+
+#[test]
+fn dry_run_delete_leaves_the_config_unchanged() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+
+    let output = sandbox.run(&["--dry-run", "delete", "a"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("remove provider 'a'"));
+    assert_eq!(sandbox.read_config(), TWO_PROVIDERS);
+}
+
+#[test]
+fn dry_run_import_replace_leaves_the_config_unchanged() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+    let export = sandbox.dir.path().join("export.json");
+    fs::write(
+        &export,
+        r#"{"providers": {"c": {"name": "c", "api_url": "https://c.example.com", "token": "tok-cccccc"}}}"#,
+    )
+    .unwrap();
+
+    let output = sandbox.run(&[
+        "--dry-run",
+        "import",
+        export.to_str().unwrap(),
+        "--strategy",
+        "replace",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = stderr(&output);
+    assert!(report.contains("add provider 'c'"), "{}", report);
+    assert!(report.contains("remove provider 'a'"), "{}", report);
+    assert_eq!(sandbox.read_config(), TWO_PROVIDERS);
+}
+
+#[test]
+fn eval_of_a_missing_provider_fails_without_output() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+
+    for shell in ["bash", "fish", "powershell"] {
+        let output = sandbox.run(&["use", "missing", "--eval", "--shell", shell]);
+        assert!(!output.status.success(), "{}", shell);
+        // Nothing for the shell wrapper to evaluate
+        assert_eq!(stdout(&output), "", "{}", shell);
+        assert!(stderr(&output).contains("missing"), "{}", stderr(&output));
+    }
+
+    let output = sandbox
+        .command(&["use", "missing"])
+        .env("CCE_SHELL_INTEGRATION", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "");
+}
+
+#[cfg(unix)]
+fn mode(path: &std::path::Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[cfg(unix)]
+#[test]
+fn saved_config_is_private() {
+    let sandbox = Sandbox::new();
+    let config_path = sandbox.dir.path().join("nested").join("config.toml");
+
+    let output = sandbox.run(&[
+        "--config",
+        config_path.to_str().unwrap(),
+        "add",
+        "a",
+        "https://a.example.com",
+        "tok-aaaaaa",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(mode(&config_path), 0o600);
+    assert_eq!(mode(config_path.parent().unwrap()), 0o700);
+}
+
+#[cfg(unix)]
+#[test]
+fn export_with_tokens_is_private() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+    let export = sandbox.dir.path().join("export.json");
+
+    let output = sandbox.run(&["export", "--with-tokens", "--out", export.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fs::read_to_string(&export).unwrap().contains("tok-aaaaaa"));
+    assert_eq!(mode(&export), 0o600);
+}
+
+#[test]
+fn locked_provider_needs_force() {
+    let locked = TWO_PROVIDERS.replace(
+        "token = \"tok-aaaaaa\"\n",
+        "token = \"tok-aaaaaa\"\nlocked = true\n",
+    );
+    let sandbox = Sandbox::with_config(&locked);
+    let token_file = sandbox.dir.path().join("token");
+    fs::write(&token_file, "tok-rotated\n").unwrap();
+    let token_file = token_file.to_str().unwrap();
+    let export = sandbox.dir.path().join("export.json");
+    fs::write(
+        &export,
+        r#"{"providers": {"a": {"name": "a", "api_url": "https://imported.example.com", "token": "tok-imported"}}}"#,
+    )
+    .unwrap();
+    let export = export.to_str().unwrap();
+    // Replacing drops `a` even though the file does not mention it
+    let other = sandbox.dir.path().join("other.json");
+    fs::write(
+        &other,
+        r#"{"providers": {"c": {"name": "c", "api_url": "https://c.example.com", "token": "tok-cccccc"}}}"#,
+    )
+    .unwrap();
+    let other = other.to_str().unwrap();
+
+    for args in [
+        vec!["delete", "a"],
+        vec!["edit", "a", "--url", "https://edited.example.com"],
+        vec!["rotate", "a", "--token-file", token_file],
+        vec!["add", "a", "https://other.example.com", "tok-other"],
+        vec!["import", export],
+        vec!["import", other, "--strategy", "replace"],
+    ] {
+        let output = sandbox.run(&args);
+        assert!(!output.status.success(), "{:?} succeeded", args);
+        assert!(stderr(&output).contains("--force"), "{}", stderr(&output));
+        assert_eq!(
+            sandbox.read_config(),
+            locked,
+            "{:?} changed the config",
+            args
+        );
+    }
+
+    let output = sandbox.run(&["delete", "a", "--force"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!sandbox.read_config().contains("[providers.a]"));
+}
+
+#[test]
+fn whitespace_around_token_and_url_is_trimmed() {
+    let sandbox = Sandbox::new();
+
+    let output = sandbox.run(&["add", "a", " https://a.example.com/ \n", "\ttok-aaaaaa \n"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Removed whitespace around the token"));
+    assert!(stdout(&output).contains("Removed whitespace around the API URL"));
+
+    let output = sandbox.run(&["edit", "a", "--token", " tok-edited\n"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let config: toml::Value = toml::from_str(&sandbox.read_config()).unwrap();
+    let provider = &config["providers"]["a"];
+    assert_eq!(provider["api_url"].as_str(), Some("https://a.example.com"));
+    assert_eq!(provider["token"].as_str(), Some("tok-edited"));
+
+    let output = sandbox.run(&["add", "b", "https://b.example.com", " \n"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("cannot be empty"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn comments_survive_add_and_save() {
+    let commented = r#"# Providers for work and home
+version = 2
+
+# The default gateway
+[providers.a]
+name = "a"
+api_url = "https://a.example.com" # behind the VPN
+token = "tok-aaaaaa"
+"#;
+    let sandbox = Sandbox::with_config(commented);
+
+    let output = sandbox.run(&["add", "b", "https://b.example.com", "tok-bbbbbb"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let saved = sandbox.read_config();
+    for comment in [
+        "# Providers for work and home",
+        "# The default gateway",
+        "# behind the VPN",
+    ] {
+        assert!(saved.contains(comment), "lost '{}' in:\n{}", comment, saved);
+    }
+    let config: toml::Value = toml::from_str(&saved).unwrap();
+    assert_eq!(
+        config["providers"]["b"]["api_url"].as_str(),
+        Some("https://b.example.com")
+    );
+}
+
+#[test]
+fn posix_wrapper_exports_the_provider() {
+    let sandbox = Sandbox::with_config(TWO_PROVIDERS);
+
+    for shell in ["bash", "zsh"] {
+        let Some(output) = sandbox.run_wrapped(
+            shell,
+            "cce use a && echo \"url=$ANTHROPIC_BASE_URL\"\ncce use missing || echo \"failed with $?\"",
+        ) else {
+            continue;
+        };
+        assert!(output.status.success(), "{}: {}", shell, stderr(&output));
+        let out = stdout(&output);
+        assert!(
+            out.contains("url=https://a.example.com"),
+            "{}: {}",
+            shell,
+            out
+        );
+        assert!(out.contains("failed with 1"), "{}: {}", shell, out);
+
+        let Some(output) = sandbox.run_wrapped(
+            shell,
+            "cce use b --print && echo \"url=$ANTHROPIC_BASE_URL\"",
+        ) else {
+            continue;
+        };
+        assert!(output.status.success(), "{}: {}", shell, stderr(&output));
+        let out = stdout(&output);
+        assert!(out.contains("https://b.example.com"), "{}: {}", shell, out);
+        assert!(!out.contains("tok-bbbbbb"), "{}: {}", shell, out);
+        assert!(out.contains("url=\n"), "{}: {}", shell, out);
+
+        let Some(output) = sandbox.run_wrapped(
+            shell,
+            "cce use --help && echo \"url=$ANTHROPIC_BASE_URL\"\ncce use -q b && cce use - && cce last -q && echo \"url=$ANTHROPIC_BASE_URL\"",
+        ) else {
+            continue;
+        };
+        assert!(output.status.success(), "{}: {}", shell, stderr(&output));
+        assert!(stdout(&output).contains("Usage:"), "{}", stdout(&output));
+        assert!(stdout(&output).contains("url=\n"), "{}", stdout(&output));
+        assert!(
+            stdout(&output).contains("url=https://b.example.com"),
+            "{}",
+            stdout(&output)
+        );
+        // The switch is reported by name, and not at all with -q
+        assert_eq!(
+            stderr(&output),
+            "🔄 Switched to service provider 'a'\n",
+            "{}",
+            shell
+        );
+    }
+}
+
+#[test]
+fn current_prints_the_url_without_logging() {
+    let sandbox = Sandbox::with_config(&format!("current_provider = \"a\"\n{}", TWO_PROVIDERS));
+
+    let output = sandbox.run(&["current"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "a\n");
+
+    for flag in ["--url", "-u"] {
+        let output = sandbox.run(&["current", flag]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "a\thttps://a.example.com\n");
+        assert!(!stderr(&output).contains("DEBUG"), "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn completion_only_reads_the_config() {
+    // Unversioned and pointing at a missing provider, so loading it would
+    // migrate, back it up, repair it, and warn
+    let config = r#"current_provider = "gone"
+
+[providers.b]
+name = "b"
+api_url = "https://b.example.com"
+token = "tok-bbbbbb"
+"#;
+    let sandbox = Sandbox::with_config(config);
+
+    let output = sandbox
+        .command(&["--", "cce", "use", ""])
+        .env("COMPLETE", "fish")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains("b\thttps://b.example.com\n"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(stderr(&output), "");
+    assert_eq!(sandbox.read_config(), config);
+    assert!(!sandbox.dir.path().join("config.toml.bak").exists());
+}